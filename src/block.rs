@@ -0,0 +1,262 @@
+//! Block module for column (rectangular) selections
+//!
+//! This module reads and edits the rectangle spanned by two positions. The
+//! rectangle covers the lines between the positions and the display columns
+//! between them, so it stays straight on screen across tabs and wide
+//! characters. Edits are returned as one edit per line for the TextBuffer to
+//! apply in a single batch. A CR ending a line is never part of the block.
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{EditOperation, Position, Range, TextBufferResult};
+use crate::visual::char_width;
+
+impl TextSnapshot {
+    /// Returns the range of each line covered by the block between two
+    /// positions
+    ///
+    /// The block spans the display columns between the positions; a tab or
+    /// wide character under either edge is included. Lines shorter than the
+    /// block give an empty range at the end of their content.
+    pub fn block_ranges(
+        &self,
+        start: Position,
+        end: Position,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<Range>> {
+        let (left, right) = self.block_columns(start, end, tab_size)?;
+        (start.line.min(end.line)..=start.line.max(end.line))
+            .map(|line| {
+                Ok(Range::new(
+                    self.block_edge(line, left, tab_size, false)?,
+                    self.block_edge(line, right, tab_size, left < right)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the text of each line covered by the block between two
+    /// positions
+    pub fn block_text(
+        &self,
+        start: Position,
+        end: Position,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<String>> {
+        self.block_ranges(start, end, tab_size)?
+            .into_iter()
+            .map(|range| {
+                let start = self.position_to_offset(range.start)?;
+                let end = self.position_to_offset(range.end)?;
+                self.text_between(start, end)
+            })
+            .collect()
+    }
+
+    /// Helper method to find the display columns spanned by a block
+    fn block_columns(
+        &self,
+        start: Position,
+        end: Position,
+        tab_size: usize,
+    ) -> TextBufferResult<(usize, usize)> {
+        let start = self.visual_column(start, tab_size)?;
+        let end = self.visual_column(end, tab_size)?;
+        Ok((start.min(end), start.max(end)))
+    }
+
+    /// Helper method to find the position of a block edge on a line
+    ///
+    /// A column inside a tab or wide character resolves to the start of that
+    /// character, or to its end when `round_up` is set; a column past the
+    /// end resolves to the end of the line's content.
+    fn block_edge(
+        &self,
+        line: usize,
+        column: usize,
+        tab_size: usize,
+        round_up: bool,
+    ) -> TextBufferResult<Position> {
+        let start = self.line_start_summary(line)?.bytes;
+        let end = self.line_content_end(line)?;
+        let mut visual = 0;
+        let mut offset = end;
+        for (char_offset, ch) in self.chars_in(start, end) {
+            let width = char_width(ch, visual, tab_size);
+            if visual + width > column {
+                offset = match round_up && visual < column {
+                    true => char_offset + ch.len_utf8(),
+                    false => char_offset,
+                };
+                break;
+            }
+            visual += width;
+        }
+        self.offset_to_position(offset)
+    }
+
+    /// Helper method to return the display width of a line's content and its
+    /// end
+    fn line_width(&self, line: usize, tab_size: usize) -> TextBufferResult<(usize, Position)> {
+        let end = self.offset_to_position(self.line_content_end(line)?)?;
+        Ok((self.visual_column(end, tab_size)?, end))
+    }
+}
+
+/// Returns the edits replacing the block between two positions with one line
+/// of text per block line
+///
+/// A single line of text replaces every line of the block. With `pad`, lines
+/// ending before the block are padded with spaces up to its first column.
+pub(crate) fn replace_block(
+    text: &TextSnapshot,
+    start: Position,
+    end: Position,
+    lines: &[&str],
+    tab_size: usize,
+    pad: bool,
+) -> TextBufferResult<Vec<EditOperation>> {
+    let ranges = text.block_ranges(start, end, tab_size)?;
+    if lines.len() != 1 && lines.len() != ranges.len() {
+        return Err("Block line count mismatch".to_string());
+    }
+    let (left, _) = text.block_columns(start, end, tab_size)?;
+
+    let mut edits = Vec::with_capacity(ranges.len());
+    for (index, mut range) in ranges.into_iter().enumerate() {
+        let mut replacement = lines[index.min(lines.len() - 1)].to_string();
+        let (width, line_end) = text.line_width(range.start.line, tab_size)?;
+        if pad && width < left && !replacement.is_empty() {
+            range = Range::new(line_end, line_end);
+            replacement.insert_str(0, &" ".repeat(left - width));
+        }
+        edits.push(EditOperation::new(range, replacement));
+    }
+    Ok(edits)
+}
+
+/// Returns the edits inserting one line of text per line at the display
+/// column of a position, starting on its line
+///
+/// Lines past the end of the text are appended. With `pad`, lines ending
+/// before the column are padded with spaces up to it.
+pub(crate) fn insert_block(
+    text: &TextSnapshot,
+    position: Position,
+    lines: &[&str],
+    tab_size: usize,
+    pad: bool,
+) -> TextBufferResult<Vec<EditOperation>> {
+    let column = text.visual_column(position, tab_size)?;
+    let padding = |width: usize| match pad {
+        true => " ".repeat(column.saturating_sub(width)),
+        false => String::new(),
+    };
+
+    let line_ending = text.line_ending();
+    let mut edits = Vec::with_capacity(lines.len());
+    let mut appended = String::new();
+    for (index, line_text) in lines.iter().enumerate() {
+        let line = position.line + index;
+        if line >= text.line_count() {
+            appended.push_str(line_ending);
+            appended.push_str(&padding(0));
+            appended.push_str(line_text);
+            continue;
+        }
+
+        let (width, line_end) = text.line_width(line, tab_size)?;
+        let edit = match width < column {
+            true => EditOperation::insert(line_end, padding(width) + line_text),
+            false => {
+                let at = text.block_edge(line, column, tab_size, false)?;
+                EditOperation::insert(at, *line_text)
+            }
+        };
+        edits.push(edit);
+    }
+
+    if !appended.is_empty() {
+        let end = text.offset_to_position(text.length())?;
+        edits.push(EditOperation::insert(end, appended));
+    }
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_buffer::TextBuffer;
+    use crate::types::Position;
+
+    #[test]
+    fn test_block_text() {
+        let buffer = TextBuffer::from_text("abcdef\n\tx\nab\n日本語");
+        let text = buffer
+            .block_text(Position::new(0, 2), Position::new(3, 2), 4)
+            .unwrap();
+        // Columns 2..4: the tab starts before the block but covers it
+        assert_eq!(text, vec!["cd", "\t", "", "本"]);
+
+        // Either edge inside a tab includes it
+        let buffer = TextBuffer::from_text("x\tabc\r\nxyzabc");
+        let text = buffer
+            .block_text(Position::new(0, 1), Position::new(1, 2), 4)
+            .unwrap();
+        assert_eq!(text, vec!["\t", "y"]);
+        let text = buffer
+            .block_text(Position::new(0, 3), Position::new(1, 2), 4)
+            .unwrap();
+        assert_eq!(text, vec!["\ta", "zab"]);
+    }
+
+    #[test]
+    fn test_block_crlf() {
+        let mut buffer = TextBuffer::from_text("abcd\r\nab\r\nabcd");
+        let text = buffer
+            .block_text(Position::new(0, 1), Position::new(2, 4), 4)
+            .unwrap();
+        assert_eq!(text, vec!["bcd", "b", "bcd"]);
+
+        buffer
+            .replace_block(Position::new(0, 3), Position::new(2, 3), &["X"], 4, true)
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "abcXd\r\nab X\r\nabcXd");
+
+        buffer
+            .insert_block(Position::new(2, 5), &["1", "2"], 4, true)
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "abcXd\r\nab X\r\nabcXd1\r\n     2");
+    }
+
+    #[test]
+    fn test_block_edits() {
+        let mut buffer = TextBuffer::from_text("abcd\nab\nabcd");
+
+        buffer
+            .replace_block(Position::new(0, 3), Position::new(2, 3), &["X"], 4, true)
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "abcXd\nab X\nabcXd");
+        assert_eq!(buffer.version(), 1);
+
+        buffer
+            .delete_block(Position::new(0, 1), Position::new(2, 3), 4)
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "aXd\naX\naXd");
+
+        let undo = buffer
+            .insert_block(Position::new(0, 3), &["1", "2", "3", "4"], 4, true)
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "aXd1\naX 2\naXd3\n   4");
+        buffer.apply_edits(undo).unwrap();
+        assert_eq!(buffer.get_all_text(), "aXd\naX\naXd");
+
+        assert!(buffer
+            .replace_block(
+                Position::new(0, 0),
+                Position::new(1, 0),
+                &["a", "b", "c"],
+                4,
+                false
+            )
+            .is_err());
+    }
+}
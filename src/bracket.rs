@@ -0,0 +1,617 @@
+//! Bracket module for matching bracket pairs
+//!
+//! This module finds the partner of a bracket and the pair enclosing a
+//! position. Brackets inside strings and comments are skipped, so the text
+//! is read by a small lexer fed one piece at a time. Whether an offset is in
+//! a string or comment depends on everything before it, so the lexer state at
+//! the start of each line is kept in BracketStates, which stay current up to
+//! the first line edited since they were computed. Searches start lexing at
+//! the line of the position and read outwards only as far as the partner
+//! bracket.
+
+use std::collections::VecDeque;
+
+use crate::event::ContentChangeEvent;
+use crate::snapshot::TextSnapshot;
+use crate::types::{Position, Range, TextBufferResult};
+
+/// Bracket pairs and the delimiters of text in which brackets are ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BracketConfig {
+    /// Opening and closing bracket of each pair
+    pub pairs: Vec<(char, char)>,
+    /// Opening and closing delimiters of strings; a backslash escapes the
+    /// next char
+    pub strings: Vec<(String, String)>,
+    /// Delimiters starting a comment that runs to the end of the line
+    pub line_comments: Vec<String>,
+    /// Opening and closing delimiters of block comments
+    pub block_comments: Vec<(String, String)>,
+}
+
+impl Default for BracketConfig {
+    fn default() -> Self {
+        Self {
+            pairs: vec![('(', ')'), ('[', ']'), ('{', '}')],
+            strings: vec![("\"".to_string(), "\"".to_string())],
+            line_comments: vec!["//".to_string()],
+            block_comments: vec![("/*".to_string(), "*/".to_string())],
+        }
+    }
+}
+
+impl BracketConfig {
+    /// Returns the pair index and direction of a bracket char
+    fn bracket(&self, ch: char) -> Option<(usize, bool)> {
+        self.pairs
+            .iter()
+            .enumerate()
+            .find_map(|(pair, &(open, close))| match ch {
+                _ if ch == open => Some((pair, true)),
+                _ if ch == close => Some((pair, false)),
+                _ => None,
+            })
+    }
+}
+
+/// A bracket found outside strings and comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BracketToken {
+    /// Byte offset of the bracket
+    pub(crate) offset: usize,
+    /// Index of the pair in the config
+    pub(crate) pair: usize,
+    pub(crate) open: bool,
+    /// Length of the bracket in bytes
+    pub(crate) len: usize,
+}
+
+/// Lexical context of the next char
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum LexState {
+    #[default]
+    Code,
+    /// Inside the string with this delimiter index
+    String(usize),
+    LineComment,
+    /// Inside the block comment with this delimiter index
+    BlockComment(usize),
+}
+
+/// Lexer yielding the brackets of a char stream outside strings and comments
+pub(crate) struct Lexer<'c, I> {
+    config: &'c BracketConfig,
+    chars: I,
+    /// Chars read ahead to match multi-char delimiters
+    pending: VecDeque<(usize, char)>,
+    state: LexState,
+}
+
+impl<'c, I: Iterator<Item = (usize, char)>> Lexer<'c, I> {
+    /// Creates a lexer reading `chars` in the given context
+    pub(crate) fn new(config: &'c BracketConfig, chars: I, state: LexState) -> Self {
+        Self {
+            config,
+            chars,
+            pending: VecDeque::new(),
+            state,
+        }
+    }
+
+    /// Returns the context of the next unread char
+    pub(crate) fn state(&self) -> LexState {
+        self.state
+    }
+
+    /// Returns true if the unread chars start with the delimiter
+    fn starts_with(&mut self, delimiter: &str) -> bool {
+        for (index, expected) in delimiter.chars().enumerate() {
+            if index == self.pending.len() {
+                match self.chars.next() {
+                    Some(next) => self.pending.push_back(next),
+                    None => return false,
+                }
+            }
+            if self.pending[index].1 != expected {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Consumes the unread delimiter
+    fn skip(&mut self, delimiter: &str) {
+        for _ in delimiter.chars() {
+            self.advance();
+        }
+    }
+
+    fn advance(&mut self) -> Option<(usize, char)> {
+        self.pending.pop_front().or_else(|| self.chars.next())
+    }
+
+    /// Enters a string or comment if one starts at the next char
+    fn enter_literal(&mut self) -> bool {
+        let config = self.config;
+        for (index, (open, _)) in config.block_comments.iter().enumerate() {
+            if self.starts_with(open) {
+                self.skip(open);
+                self.state = LexState::BlockComment(index);
+                return true;
+            }
+        }
+        for open in &config.line_comments {
+            if self.starts_with(open) {
+                self.skip(open);
+                self.state = LexState::LineComment;
+                return true;
+            }
+        }
+        for (index, (open, _)) in config.strings.iter().enumerate() {
+            if self.starts_with(open) {
+                self.skip(open);
+                self.state = LexState::String(index);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<I: Iterator<Item = (usize, char)>> Iterator for Lexer<'_, I> {
+    type Item = BracketToken;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let config = self.config;
+        loop {
+            match self.state {
+                LexState::Code => {
+                    if self.enter_literal() {
+                        continue;
+                    }
+                    let (offset, ch) = self.advance()?;
+                    if let Some((pair, open)) = config.bracket(ch) {
+                        return Some(BracketToken {
+                            offset,
+                            pair,
+                            open,
+                            len: ch.len_utf8(),
+                        });
+                    }
+                }
+                LexState::String(index) => {
+                    let close = &config.strings[index].1;
+                    if self.starts_with(close) {
+                        self.skip(close);
+                        self.state = LexState::Code;
+                    } else if self.advance()?.1 == '\\' {
+                        self.advance();
+                    }
+                }
+                LexState::LineComment => {
+                    if self.advance()?.1 == '\n' {
+                        self.state = LexState::Code;
+                    }
+                }
+                LexState::BlockComment(index) => {
+                    let close = &config.block_comments[index].1;
+                    if self.starts_with(close) {
+                        self.skip(close);
+                        self.state = LexState::Code;
+                    } else {
+                        self.advance()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lexer state at the start of each line, computed on demand for bracket
+/// matching
+///
+/// The states of the lines up to the first line edited since they were
+/// computed stay current, so lexing resumes from there. A reader holding a
+/// snapshot keeps its own states and applies change events to them.
+#[derive(Debug, Clone)]
+pub struct BracketStates {
+    states: Vec<LexState>,
+    /// Number of leading lines whose state is current
+    valid: usize,
+    config: BracketConfig,
+}
+
+impl BracketStates {
+    /// Creates states for a text with every line but the first unknown
+    pub fn new(text: &impl AsRef<TextSnapshot>, config: BracketConfig) -> Self {
+        Self::with_line_count(text.as_ref().line_count(), config)
+    }
+
+    pub(crate) fn with_line_count(line_count: usize, config: BracketConfig) -> Self {
+        Self {
+            states: vec![LexState::Code; line_count.max(1)],
+            valid: 1,
+            config,
+        }
+    }
+
+    /// Returns the bracket pairs and delimiters the states are lexed with
+    pub fn config(&self) -> &BracketConfig {
+        &self.config
+    }
+
+    /// Forgets the states of the lines after the start of each change
+    pub fn apply_event(&mut self, event: &ContentChangeEvent) -> TextBufferResult<()> {
+        for change in &event.changes {
+            let start_line = change.range.start.line;
+            let end_line = change.range.end.line;
+            let inserted = (end_line - start_line + 1)
+                .checked_add_signed(change.line_delta)
+                .ok_or_else(|| "Invalid line delta".to_string())?;
+            self.splice(start_line, end_line, inserted);
+        }
+        Ok(())
+    }
+
+    /// Replaces the lines `start_line..=old_end_line` by `new_line_count`
+    /// lines whose state is unknown, except for the first one
+    pub(crate) fn splice(&mut self, start_line: usize, old_end_line: usize, new_line_count: usize) {
+        // The state at the start of the first edited line does not change
+        let end = (old_end_line + 1).min(self.states.len());
+        let start = (start_line + 1).min(end);
+        self.states.splice(
+            start..end,
+            std::iter::repeat_n(LexState::Code, new_line_count.saturating_sub(1)),
+        );
+        self.valid = self.valid.min(start).max(1);
+    }
+
+    /// Returns the lexer state at the start of a line, lexing the lines
+    /// before it whose state is unknown
+    fn state_at_line(&mut self, text: &TextSnapshot, line: usize) -> TextBufferResult<LexState> {
+        if line >= self.states.len() || line >= text.line_count() {
+            return Err(format!("Line {line} out of bounds"));
+        }
+        while self.valid <= line {
+            let previous = self.valid - 1;
+            let (_, state) = lex_line(text, &self.config, previous, self.states[previous])?;
+            self.states[self.valid] = state;
+            self.valid += 1;
+        }
+        Ok(self.states[line])
+    }
+
+    /// Returns the brackets of a line
+    fn brackets_in_line(
+        &mut self,
+        text: &TextSnapshot,
+        line: usize,
+    ) -> TextBufferResult<Vec<BracketToken>> {
+        let state = self.state_at_line(text, line)?;
+        lex_line(text, &self.config, line, state).map(|(tokens, _)| tokens)
+    }
+}
+
+/// Returns the brackets of a line lexed from `state`, and the state at its
+/// end
+pub(crate) fn lex_line(
+    text: &TextSnapshot,
+    config: &BracketConfig,
+    line: usize,
+    state: LexState,
+) -> TextBufferResult<(Vec<BracketToken>, LexState)> {
+    let start = text.line_start_summary(line)?.bytes;
+    // Include the line break so line comments end with the line
+    let end = match line + 1 < text.line_count() {
+        true => text.line_start_summary(line + 1)?.bytes,
+        false => text.length(),
+    };
+
+    let mut lexer = Lexer::new(config, text.chars_in(start, end), state);
+    let tokens = lexer.by_ref().collect();
+    Ok((tokens, lexer.state()))
+}
+
+/// Pushes an opening bracket, or pops the matching opening bracket; a
+/// closing bracket that does not match the innermost open one is ignored
+fn push_token(stack: &mut Vec<BracketToken>, token: BracketToken) {
+    if token.open {
+        stack.push(token);
+    } else if stack.last().is_some_and(|open| open.pair == token.pair) {
+        stack.pop();
+    }
+}
+
+impl TextSnapshot {
+    /// Returns the ranges of the bracket touching a position and of its
+    /// partner, opening bracket first
+    ///
+    /// The bracket after the position is tried before the one before it.
+    /// Brackets inside strings and comments have no partner. `states` must
+    /// follow every edit up to this snapshot.
+    pub fn matching_bracket(
+        &self,
+        position: Position,
+        states: &mut BracketStates,
+    ) -> TextBufferResult<Option<(Range, Range)>> {
+        let offset = self.position_to_offset(position)?;
+        if let Some(pair) = self.match_bracket_at(offset, states)? {
+            return Ok(Some(pair));
+        }
+        match offset.checked_sub(1) {
+            Some(_) => {
+                let before = self.prev_char_start(offset)?;
+                self.match_bracket_at(before, states)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the ranges of the innermost bracket pair around a position
+    ///
+    /// The opening bracket is before the position and the closing bracket at
+    /// or after it.
+    pub fn enclosing_brackets(
+        &self,
+        position: Position,
+        states: &mut BracketStates,
+    ) -> TextBufferResult<Option<(Range, Range)>> {
+        let offset = self.position_to_offset(position)?;
+        let Some(open) = self.open_bracket_before(offset, states)? else {
+            return Ok(None);
+        };
+        match self.closing_bracket(open, offset, states)? {
+            Some(close) => self.bracket_ranges(open, close).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Helper method to match the bracket starting at a byte offset
+    fn match_bracket_at(
+        &self,
+        offset: usize,
+        states: &mut BracketStates,
+    ) -> TextBufferResult<Option<(Range, Range)>> {
+        let line = self.offset_to_position(offset)?.line;
+        let tokens = states.brackets_in_line(self, line)?;
+        let Some(&token) = tokens.iter().find(|token| token.offset == offset) else {
+            return Ok(None);
+        };
+
+        let partner = match token.open {
+            true => self.closing_bracket(token, offset + token.len, states)?,
+            false => self
+                .open_bracket_before(offset, states)?
+                .filter(|open| open.pair == token.pair),
+        };
+        match partner {
+            Some(partner) if token.open => self.bracket_ranges(token, partner).map(Some),
+            Some(partner) => self.bracket_ranges(partner, token).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Helper method to find the innermost bracket still open before a byte
+    /// offset
+    ///
+    /// Whether a bracket is still open depends only on the brackets after
+    /// it, so lines are read backwards from the offset, doubling the number
+    /// read each time, until one of the brackets read is still open.
+    fn open_bracket_before(
+        &self,
+        offset: usize,
+        states: &mut BracketStates,
+    ) -> TextBufferResult<Option<BracketToken>> {
+        let mut first_line = self.offset_to_position(offset)?.line + 1;
+        let mut tokens: Vec<BracketToken> = Vec::new();
+        let mut count = 1;
+        loop {
+            let start_line = first_line.saturating_sub(count);
+            let mut read = Vec::new();
+            for line in start_line..first_line {
+                read.extend(
+                    states
+                        .brackets_in_line(self, line)?
+                        .into_iter()
+                        .filter(|token| token.offset < offset),
+                );
+            }
+            read.append(&mut tokens);
+            tokens = read;
+            first_line = start_line;
+
+            let mut stack = Vec::new();
+            for &token in &tokens {
+                push_token(&mut stack, token);
+            }
+            if let Some(&open) = stack.last() {
+                return Ok(Some(open));
+            }
+            if first_line == 0 {
+                return Ok(None);
+            }
+            count *= 2;
+        }
+    }
+
+    /// Helper method to find the bracket closing `open`, reading on from a
+    /// byte offset at which `open` is the innermost open bracket
+    fn closing_bracket(
+        &self,
+        open: BracketToken,
+        from: usize,
+        states: &mut BracketStates,
+    ) -> TextBufferResult<Option<BracketToken>> {
+        let line = self.offset_to_position(from)?.line;
+        let line_start = self.line_start_summary(line)?.bytes;
+        let state = states.state_at_line(self, line)?;
+        let lexer = Lexer::new(
+            &states.config,
+            self.chars_in(line_start, self.length()),
+            state,
+        );
+
+        let mut stack = vec![open];
+        for token in lexer.skip_while(|token| token.offset < from) {
+            if !token.open && stack.len() == 1 && token.pair == open.pair {
+                return Ok(Some(token));
+            }
+            push_token(&mut stack, token);
+        }
+        Ok(None)
+    }
+
+    /// Helper method to find where the char ending at a byte offset starts
+    fn prev_char_start(&self, offset: usize) -> TextBufferResult<usize> {
+        let (chunk, chunk_start) = self.chunk_at(offset - 1)?;
+        let mut index = offset - 1 - chunk_start;
+        while !chunk.is_char_boundary(index) {
+            index -= 1;
+        }
+        Ok(chunk_start + index)
+    }
+
+    /// Helper method to convert two bracket tokens to ranges
+    fn bracket_ranges(
+        &self,
+        open: BracketToken,
+        close: BracketToken,
+    ) -> TextBufferResult<(Range, Range)> {
+        let range = |token: BracketToken| -> TextBufferResult<Range> {
+            Ok(Range::new(
+                self.offset_to_position(token.offset)?,
+                self.offset_to_position(token.offset + token.len)?,
+            ))
+        };
+        Ok((range(open)?, range(close)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+
+    fn range(line: usize, column: usize) -> Range {
+        Range::new(Position::new(line, column), Position::new(line, column + 1))
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let buffer = TextBuffer::from_text("f(a[0], \")\" /* ] */) {\n  g(); // }\n}");
+
+        // After the cursor, then before it
+        let pair = Some((range(0, 1), range(0, 19)));
+        assert_eq!(buffer.matching_bracket(Position::new(0, 1)).unwrap(), pair);
+        assert_eq!(buffer.matching_bracket(Position::new(0, 20)).unwrap(), pair);
+        assert_eq!(
+            buffer.matching_bracket(Position::new(2, 0)).unwrap(),
+            Some((range(0, 21), range(2, 0)))
+        );
+
+        // Brackets in strings and comments are not matched
+        assert_eq!(buffer.matching_bracket(Position::new(0, 9)).unwrap(), None);
+        assert_eq!(buffer.matching_bracket(Position::new(1, 10)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_enclosing_brackets() {
+        let mut buffer = TextBuffer::from_text("<a(b)c>");
+        assert_eq!(
+            buffer.enclosing_brackets(Position::new(0, 3)).unwrap(),
+            Some((range(0, 2), range(0, 4)))
+        );
+        assert_eq!(
+            buffer.enclosing_brackets(Position::new(0, 5)).unwrap(),
+            None
+        );
+
+        let mut config = BracketConfig::default();
+        config.pairs.push(('<', '>'));
+        buffer.set_bracket_config(config);
+        assert_eq!(
+            buffer.enclosing_brackets(Position::new(0, 5)).unwrap(),
+            Some((range(0, 0), range(0, 6)))
+        );
+    }
+
+    #[test]
+    fn test_edit_in_line_starting_in_comment() {
+        let mut buffer = TextBuffer::from_text("f(/*\nx)\n\"\n(]\n");
+        assert_eq!(buffer.matching_bracket(Position::new(1, 1)).unwrap(), None);
+        assert_eq!(buffer.matching_bracket(Position::new(3, 1)).unwrap(), None);
+
+        // The edited lines still start inside the comment and the string
+        buffer.insert(Position::new(1, 0), "y").unwrap();
+        buffer.insert(Position::new(3, 0), "z").unwrap();
+        assert_eq!(buffer.matching_bracket(Position::new(1, 2)).unwrap(), None);
+        assert_eq!(buffer.matching_bracket(Position::new(3, 2)).unwrap(), None);
+        assert_eq!(
+            buffer.enclosing_brackets(Position::new(1, 2)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_snapshot_bracket_states() {
+        let mut buffer = TextBuffer::from_text("a(\n/*\n)*/)");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        buffer.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+
+        let snapshot = buffer.snapshot();
+        let mut states = BracketStates::new(&snapshot, BracketConfig::default());
+        assert_eq!(
+            snapshot
+                .matching_bracket(Position::new(0, 1), &mut states)
+                .unwrap(),
+            Some((range(0, 1), range(2, 3)))
+        );
+
+        // A reader applies the queued events before using a newer snapshot
+        buffer
+            .delete(Range::new(Position::new(1, 0), Position::new(1, 2)))
+            .unwrap();
+        buffer.insert(Position::new(0, 0), "\n").unwrap();
+        for event in events.lock().unwrap().drain(..) {
+            states.apply_event(&event).unwrap();
+        }
+        let snapshot = buffer.snapshot();
+        assert_eq!(
+            snapshot
+                .matching_bracket(Position::new(1, 1), &mut states)
+                .unwrap(),
+            Some((range(1, 1), range(3, 0)))
+        );
+    }
+
+    #[test]
+    fn test_line_states_follow_edits() {
+        let mut buffer = TextBuffer::from_text("a(\n  b[\n\n  ]\n)");
+        assert_eq!(
+            buffer.matching_bracket(Position::new(4, 1)).unwrap(),
+            Some((range(0, 1), range(4, 0)))
+        );
+        assert_eq!(
+            buffer.enclosing_brackets(Position::new(2, 0)).unwrap(),
+            Some((range(1, 3), range(3, 2)))
+        );
+
+        // Opening a block comment hides the brackets of the lines below
+        buffer.insert(Position::new(1, 0), "/*").unwrap();
+        assert_eq!(
+            buffer.enclosing_brackets(Position::new(2, 0)).unwrap(),
+            None
+        );
+        assert_eq!(buffer.matching_bracket(Position::new(4, 1)).unwrap(), None);
+
+        buffer.insert(Position::new(2, 0), "*/").unwrap();
+        assert_eq!(
+            buffer.matching_bracket(Position::new(0, 1)).unwrap(),
+            Some((range(0, 1), range(4, 0)))
+        );
+        assert_eq!(buffer.matching_bracket(Position::new(3, 3)).unwrap(), None);
+    }
+}
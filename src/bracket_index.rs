@@ -0,0 +1,758 @@
+//! Bracket index module for bracket pair colorization
+//!
+//! This module implements the BracketIndex which assigns a nesting depth to
+//! every bracket outside strings and comments. Lines are kept in an implicit
+//! treap ordered by line number. Each line stores its brackets, the lexer
+//! state at its start and end, and the effect of its brackets on the stack of
+//! open brackets; subtrees compose those effects, so the brackets open at the
+//! start of any line are found in O(log n) compositions. A closing bracket
+//! only closes an opening bracket of its own pair, as in bracket matching.
+//! Change events splice the affected lines out of the treap and mark the new
+//! ones dirty; `sync` then lexes only those lines against the current text,
+//! plus the following lines when the edit opens or closes a multi-line
+//! string or comment, so events can be queued and applied later.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::bracket::{self, BracketConfig, LexState};
+use crate::event::ContentChangeEvent;
+use crate::snapshot::TextSnapshot;
+use crate::types::{Range, TextBufferResult};
+
+/// A bracket with its nesting depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BracketInfo {
+    pub range: Range,
+    pub open: bool,
+    /// Number of brackets open around this one, or None for a closing
+    /// bracket without an opening bracket
+    pub depth: Option<usize>,
+}
+
+/// A bracket within a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineBracket {
+    /// Byte offset within the line
+    offset: usize,
+    len: usize,
+    /// Index of the bracket's pair in the config
+    pair: usize,
+    open: bool,
+}
+
+/// A persistent sequence of pair indices
+///
+/// The sequence is an implicit treap whose nodes are shared between copies,
+/// so copying is O(1) and splitting or concatenating is O(log n).
+#[derive(Debug, Clone, Default)]
+struct PairSeq(Option<Arc<PairNode>>);
+
+#[derive(Debug)]
+struct PairNode {
+    pair: usize,
+    priority: u32,
+    /// Number of pairs in the subtree
+    len: usize,
+    left: PairSeq,
+    right: PairSeq,
+}
+
+impl PairSeq {
+    fn node(pair: usize, priority: u32, left: PairSeq, right: PairSeq) -> PairSeq {
+        let len = left.len() + 1 + right.len();
+        PairSeq(Some(Arc::new(PairNode {
+            pair,
+            priority,
+            len,
+            left,
+            right,
+        })))
+    }
+
+    fn len(&self) -> usize {
+        self.0.as_ref().map_or(0, |node| node.len)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn get(&self, index: usize) -> Option<usize> {
+        let mut node = self.0.as_deref()?;
+        let mut index = index;
+        loop {
+            let left_len = node.left.len();
+            if index < left_len {
+                node = node.left.0.as_deref()?;
+            } else if index == left_len {
+                return Some(node.pair);
+            } else {
+                index -= left_len + 1;
+                node = node.right.0.as_deref()?;
+            }
+        }
+    }
+
+    fn last(&self) -> Option<usize> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    /// Returns the sequence with a pair appended
+    fn push(&self, pair: usize) -> PairSeq {
+        self.concat(&PairSeq::node(
+            pair,
+            next_priority(),
+            PairSeq::default(),
+            PairSeq::default(),
+        ))
+    }
+
+    /// Returns the sequence without its last pair
+    fn pop(&self) -> PairSeq {
+        self.split(self.len().saturating_sub(1)).0
+    }
+
+    /// Splits the sequence into its first `count` pairs and the rest
+    fn split(&self, count: usize) -> (PairSeq, PairSeq) {
+        let Some(node) = &self.0 else {
+            return (PairSeq::default(), PairSeq::default());
+        };
+        let left_len = node.left.len();
+        if count <= left_len {
+            let (first, middle) = node.left.split(count);
+            let rest = PairSeq::node(node.pair, node.priority, middle, node.right.clone());
+            (first, rest)
+        } else {
+            let (middle, rest) = node.right.split(count - left_len - 1);
+            let first = PairSeq::node(node.pair, node.priority, node.left.clone(), middle);
+            (first, rest)
+        }
+    }
+
+    /// Returns the pairs of `self` followed by those of `next`
+    fn concat(&self, next: &PairSeq) -> PairSeq {
+        let (l, r) = match (&self.0, &next.0) {
+            (None, _) => return next.clone(),
+            (_, None) => return self.clone(),
+            (Some(l), Some(r)) => (l, r),
+        };
+        if l.priority > r.priority {
+            let right = l.right.concat(next);
+            PairSeq::node(l.pair, l.priority, l.left.clone(), right)
+        } else {
+            let left = self.concat(&r.left);
+            PairSeq::node(r.pair, r.priority, left, r.right.clone())
+        }
+    }
+}
+
+/// Returns a priority for a new PairSeq node
+fn next_priority() -> u32 {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    // Murmur3 finalizer over a counter spreads the priorities
+    let mut x = COUNTER.fetch_add(0x9e37_79b9, Ordering::Relaxed);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2_ae35);
+    x ^ (x >> 16)
+}
+
+/// Effect of a run of brackets on the stack of open brackets, as pair
+/// indices
+///
+/// Each closing bracket in `closes` came when every opening bracket of the
+/// run was closed: it pops the stack if the innermost open bracket is of its
+/// pair and is ignored otherwise. The brackets of `opens` are then pushed,
+/// innermost last. Two effects compose into one of the same shape; the
+/// sequences are shared rather than copied, so composing costs O(log n) plus
+/// O(log n) for each closing bracket of `next` matched against `self`.
+#[derive(Debug, Clone, Default)]
+struct StackChange {
+    closes: PairSeq,
+    opens: PairSeq,
+}
+
+impl StackChange {
+    /// Returns the effect of `self` followed by `next`
+    fn then(&self, next: &StackChange) -> StackChange {
+        let mut opens = self.opens.clone();
+        let mut consumed = 0;
+        while consumed < next.closes.len() && !opens.is_empty() {
+            if opens.last() == next.closes.get(consumed) {
+                opens = opens.pop();
+            }
+            consumed += 1;
+        }
+        StackChange {
+            closes: self.closes.concat(&next.closes.split(consumed).1),
+            opens: opens.concat(&next.opens),
+        }
+    }
+
+    fn of(brackets: &[LineBracket]) -> StackChange {
+        let mut change = StackChange::default();
+        for bracket in brackets {
+            change = change.then(&match bracket.open {
+                true => StackChange {
+                    closes: PairSeq::default(),
+                    opens: PairSeq::default().push(bracket.pair),
+                },
+                false => StackChange {
+                    closes: PairSeq::default().push(bracket.pair),
+                    opens: PairSeq::default(),
+                },
+            });
+        }
+        change
+    }
+}
+
+/// A buffer line in the treap
+#[derive(Debug, Clone)]
+struct BracketNode {
+    brackets: Vec<LineBracket>,
+    /// Lexer state at the start and at the end of the line
+    start_state: LexState,
+    end_state: LexState,
+    /// Whether the line must be lexed again
+    dirty: bool,
+    /// Effect of the line's brackets on the open brackets
+    change: StackChange,
+    priority: u32,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// Number of lines in the subtree
+    lines: usize,
+    /// Effect of the subtree's brackets on the open brackets
+    subtree_change: StackChange,
+    /// Lexer state at the start of the subtree's first line
+    first_state: LexState,
+    /// Lexer state at the end of the subtree's last line
+    last_state: LexState,
+    /// Whether any line in the subtree must be lexed again
+    has_dirty: bool,
+}
+
+/// Nesting depth of every bracket, kept current with change events
+#[derive(Debug, Clone)]
+pub struct BracketIndex {
+    /// Arena holding every node; freed slots are reused
+    nodes: Vec<BracketNode>,
+    /// Indices of freed slots in `nodes`
+    free: Vec<usize>,
+    root: Option<usize>,
+    config: BracketConfig,
+    /// State of the priority generator
+    seed: u32,
+}
+
+impl BracketIndex {
+    /// Indexes the brackets of every line of the text
+    pub fn new(text: &impl AsRef<TextSnapshot>, config: BracketConfig) -> TextBufferResult<Self> {
+        let text = text.as_ref();
+        let mut index = Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            config,
+            seed: 0x9e37_79b9,
+        };
+
+        for _ in 0..text.line_count() {
+            let node = index.alloc();
+            index.root = index.merge(index.root, Some(node));
+        }
+        index.refresh(text, index.root, 0, LexState::Code)?;
+        Ok(index)
+    }
+
+    /// Returns the bracket pairs and delimiters used by the index
+    pub fn config(&self) -> &BracketConfig {
+        &self.config
+    }
+
+    /// Returns the number of buffer lines
+    pub fn line_count(&self) -> usize {
+        self.lines(self.root)
+    }
+
+    /// Returns the number of brackets open at the start of a line
+    pub fn depth_at_line(&self, line: usize) -> Option<usize> {
+        self.open_at_line(line).map(|open| open.len())
+    }
+
+    /// Helper method to find the pairs of the brackets open at the start of
+    /// a line, innermost last
+    fn open_at_line(&self, line: usize) -> Option<PairSeq> {
+        if line >= self.line_count() {
+            return None;
+        }
+
+        let mut change = StackChange::default();
+        let mut node = self.root;
+        let mut line = line;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            let left_lines = self.lines(current.left);
+            if line < left_lines {
+                node = current.left;
+                continue;
+            }
+            change = change.then(&self.subtree_change(current.left));
+            if line == left_lines {
+                break;
+            }
+            change = change.then(&current.change);
+            line -= left_lines + 1;
+            node = current.right;
+        }
+
+        // Closing brackets before every opening bracket close nothing
+        Some(change.opens)
+    }
+
+    /// Returns the brackets of the lines `start_line..=end_line` with their
+    /// depths
+    pub fn brackets_in_lines(
+        &self,
+        text: &impl AsRef<TextSnapshot>,
+        start_line: usize,
+        end_line: usize,
+    ) -> TextBufferResult<Vec<BracketInfo>> {
+        let text = text.as_ref();
+        if start_line > end_line {
+            return Err("Invalid range".to_string());
+        }
+        let mut open = self
+            .open_at_line(start_line)
+            .ok_or_else(|| format!("Line {start_line} out of bounds"))?;
+
+        let mut brackets = Vec::new();
+        for line in start_line..=end_line.min(self.line_count() - 1) {
+            let index = self
+                .find_line(line)
+                .ok_or_else(|| format!("Line {line} out of bounds"))?;
+            let line_start = text.line_start_summary(line)?.bytes;
+
+            for bracket in &self.nodes[index].brackets {
+                let depth = if bracket.open {
+                    open = open.push(bracket.pair);
+                    Some(open.len() - 1)
+                } else if open.last() == Some(bracket.pair) {
+                    open = open.pop();
+                    Some(open.len())
+                } else {
+                    None
+                };
+                let start = line_start + bracket.offset;
+                brackets.push(BracketInfo {
+                    range: Range::new(
+                        text.offset_to_position(start)?,
+                        text.offset_to_position(start + bracket.len)?,
+                    ),
+                    open: bracket.open,
+                    depth,
+                });
+            }
+        }
+
+        Ok(brackets)
+    }
+
+    /// Splices the lines touched by a change event and marks them dirty
+    ///
+    /// Events must be applied in the order they were emitted. Dirty lines
+    /// have no brackets until `sync` lexes them again.
+    pub fn apply_event(&mut self, event: &ContentChangeEvent) -> TextBufferResult<()> {
+        for change in &event.changes {
+            let start_line = change.range.start.line;
+            let removed = change.range.end.line - start_line + 1;
+            let inserted = removed
+                .checked_add_signed(change.line_delta)
+                .ok_or_else(|| "Invalid line delta".to_string())?;
+
+            let (before, rest) = self.split(self.root, start_line);
+            let (replaced, after) = self.split(rest, removed);
+            self.release(replaced);
+
+            let mut middle = None;
+            for _ in 0..inserted {
+                let node = self.alloc();
+                middle = self.merge(middle, Some(node));
+            }
+
+            let root = self.merge(before, middle);
+            self.root = self.merge(root, after);
+        }
+        Ok(())
+    }
+
+    /// Lexes the dirty lines again against the current text
+    ///
+    /// `text` must be the buffer after the last applied event.
+    pub fn sync(&mut self, text: &impl AsRef<TextSnapshot>) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        if self.line_count() != text.line_count() {
+            return Err("Line count mismatch".to_string());
+        }
+        self.refresh(text, self.root, 0, LexState::Code).map(|_| ())
+    }
+
+    /// Returns true if lines are waiting for `sync`
+    pub fn is_dirty(&self) -> bool {
+        self.has_dirty(self.root)
+    }
+
+    /// Lexes the lines of a subtree whose first line is `first_line` and
+    /// starts in `state`, and returns the state at the end of its last line
+    ///
+    /// Clean lines are lexed again only when the state they start in changed.
+    fn refresh(
+        &mut self,
+        text: &TextSnapshot,
+        root: Option<usize>,
+        first_line: usize,
+        state: LexState,
+    ) -> TextBufferResult<LexState> {
+        let index = match root {
+            None => return Ok(state),
+            Some(index) => index,
+        };
+        let node = &self.nodes[index];
+        if !node.has_dirty && node.first_state == state {
+            return Ok(node.last_state);
+        }
+
+        let (left, right) = (node.left, node.right);
+        let line = first_line + self.lines(left);
+        let state = self.refresh(text, left, first_line, state)?;
+        let node = &self.nodes[index];
+        if node.dirty || node.start_state != state {
+            let (brackets, end_state) = self.lex_line(text, line, state)?;
+            let node = &mut self.nodes[index];
+            node.change = StackChange::of(&brackets);
+            node.brackets = brackets;
+            node.start_state = state;
+            node.end_state = end_state;
+            node.dirty = false;
+        }
+        let state = self.nodes[index].end_state;
+        let state = self.refresh(text, right, line + 1, state)?;
+
+        self.update(index);
+        Ok(state)
+    }
+
+    /// Returns the brackets of a line lexed from `state`, and the state at
+    /// its end
+    fn lex_line(
+        &self,
+        text: &TextSnapshot,
+        line: usize,
+        state: LexState,
+    ) -> TextBufferResult<(Vec<LineBracket>, LexState)> {
+        let start = text.line_start_summary(line)?.bytes;
+        let (tokens, state) = bracket::lex_line(text, &self.config, line, state)?;
+        let brackets = tokens
+            .into_iter()
+            .map(|token| LineBracket {
+                offset: token.offset - start,
+                len: token.len,
+                pair: token.pair,
+                open: token.open,
+            })
+            .collect();
+        Ok((brackets, state))
+    }
+
+    /// Finds the node of a buffer line
+    fn find_line(&self, line: usize) -> Option<usize> {
+        let mut node = self.root;
+        let mut line = line;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            let left_lines = self.lines(current.left);
+            if line < left_lines {
+                node = current.left;
+            } else if line == left_lines {
+                return Some(index);
+            } else {
+                line -= left_lines + 1;
+                node = current.right;
+            }
+        }
+        None
+    }
+
+    fn lines(&self, root: Option<usize>) -> usize {
+        root.map(|index| self.nodes[index].lines).unwrap_or(0)
+    }
+
+    fn subtree_change(&self, root: Option<usize>) -> StackChange {
+        root.map(|index| self.nodes[index].subtree_change.clone())
+            .unwrap_or_default()
+    }
+
+    fn has_dirty(&self, root: Option<usize>) -> bool {
+        root.map(|index| self.nodes[index].has_dirty)
+            .unwrap_or(false)
+    }
+
+    /// Recomputes the subtree aggregates of a node from its children
+    fn update(&mut self, index: usize) {
+        let node = &self.nodes[index];
+        let (left, right) = (node.left, node.right);
+        let lines = self.lines(left) + 1 + self.lines(right);
+        let subtree_change = self
+            .subtree_change(left)
+            .then(&node.change)
+            .then(&self.subtree_change(right));
+        let first_state = left.map_or(node.start_state, |left| self.nodes[left].first_state);
+        let last_state = right.map_or(node.end_state, |right| self.nodes[right].last_state);
+        let has_dirty = node.dirty || self.has_dirty(left) || self.has_dirty(right);
+
+        let node = &mut self.nodes[index];
+        node.lines = lines;
+        node.subtree_change = subtree_change;
+        node.first_state = first_state;
+        node.last_state = last_state;
+        node.has_dirty = has_dirty;
+    }
+
+    /// Allocates a dirty node for a line
+    fn alloc(&mut self) -> usize {
+        let node = BracketNode {
+            brackets: Vec::new(),
+            start_state: LexState::Code,
+            end_state: LexState::Code,
+            dirty: true,
+            change: StackChange::default(),
+            priority: self.next_priority(),
+            left: None,
+            right: None,
+            lines: 1,
+            subtree_change: StackChange::default(),
+            first_state: LexState::Code,
+            last_state: LexState::Code,
+            has_dirty: true,
+        };
+
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Returns every node of a subtree to the free list
+    fn release(&mut self, root: Option<usize>) {
+        let mut stack: Vec<usize> = root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            stack.extend(self.nodes[index].left);
+            stack.extend(self.nodes[index].right);
+            self.nodes[index].brackets = Vec::new();
+            self.free.push(index);
+        }
+    }
+
+    /// Xorshift generator for treap priorities
+    fn next_priority(&mut self) -> u32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    /// Splits a treap into its first `count` lines and the rest
+    fn split(&mut self, root: Option<usize>, count: usize) -> (Option<usize>, Option<usize>) {
+        let index = match root {
+            Some(index) => index,
+            None => return (None, None),
+        };
+
+        let left = self.nodes[index].left;
+        let left_lines = self.lines(left);
+        if count <= left_lines {
+            let (rest, middle) = self.split(left, count);
+            self.nodes[index].left = middle;
+            self.update(index);
+            (rest, Some(index))
+        } else {
+            let right = self.nodes[index].right;
+            let (middle, rest) = self.split(right, count - left_lines - 1);
+            self.nodes[index].right = middle;
+            self.update(index);
+            (Some(index), rest)
+        }
+    }
+
+    /// Merges two treaps where every line of `left` precedes those of `right`
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        let (l, r) = match (left, right) {
+            (None, tree) | (tree, None) => return tree,
+            (Some(l), Some(r)) => (l, r),
+        };
+
+        if self.nodes[l].priority > self.nodes[r].priority {
+            let merged = self.merge(self.nodes[l].right, Some(r));
+            self.nodes[l].right = merged;
+            self.update(l);
+            Some(l)
+        } else {
+            let merged = self.merge(Some(l), self.nodes[r].left);
+            self.nodes[r].left = merged;
+            self.update(r);
+            Some(r)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+    use crate::types::{EditOperation, Position};
+
+    fn depths(index: &BracketIndex, buffer: &TextBuffer) -> Vec<(usize, usize, Option<usize>)> {
+        let last_line = buffer.line_count() - 1;
+        index
+            .brackets_in_lines(buffer, 0, last_line)
+            .unwrap()
+            .into_iter()
+            .map(|bracket| {
+                (
+                    bracket.range.start.line,
+                    bracket.range.start.column,
+                    bracket.depth,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bracket_depths() {
+        let buffer = TextBuffer::from_text("f(a[0]) {\n  \"{\" /* (\n ) */ }\n}\n)");
+        let index = BracketIndex::new(&buffer, BracketConfig::default()).unwrap();
+
+        assert_eq!(
+            depths(&index, &buffer),
+            vec![
+                (0, 1, Some(0)),
+                (0, 3, Some(1)),
+                (0, 5, Some(1)),
+                (0, 6, Some(0)),
+                (0, 8, Some(0)),
+                (2, 6, Some(0)),
+                (3, 0, None),
+                (4, 0, None),
+            ]
+        );
+        assert_eq!(index.depth_at_line(1), Some(1));
+        assert_eq!(index.depth_at_line(3), Some(0));
+        assert_eq!(index.brackets_in_lines(&buffer, 1, 2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mismatched_brackets() {
+        let buffer = TextBuffer::from_text("( ]\n[ )\n{ (\n]\n) }");
+        let index = BracketIndex::new(&buffer, BracketConfig::default()).unwrap();
+
+        // A closing bracket of another pair closes nothing
+        assert_eq!(
+            depths(&index, &buffer),
+            vec![
+                (0, 0, Some(0)),
+                (0, 2, None),
+                (1, 0, Some(1)),
+                (1, 2, None),
+                (2, 0, Some(2)),
+                (2, 2, Some(3)),
+                (3, 0, None),
+                (4, 0, Some(3)),
+                (4, 2, Some(2)),
+            ]
+        );
+        assert_eq!(index.depth_at_line(1), Some(1));
+        assert_eq!(index.depth_at_line(3), Some(4));
+        assert_eq!(index.depth_at_line(4), Some(4));
+    }
+
+    #[test]
+    fn test_brackets_update_incrementally() {
+        let mut buffer = TextBuffer::from_text("a {\n  b(c)\n  [d]\n}\n");
+        let mut index = BracketIndex::new(&buffer, BracketConfig::default()).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        buffer.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+
+        // Comment out the middle lines, then add a block on a new line
+        let edits = [
+            (Range::new(Position::new(1, 0), Position::new(1, 0)), "/*"),
+            (Range::new(Position::new(2, 5), Position::new(2, 5)), "*/"),
+            (
+                Range::new(Position::new(0, 3), Position::new(0, 3)),
+                "\n  {(",
+            ),
+            (Range::new(Position::new(0, 2), Position::new(0, 3)), ""),
+        ];
+        for (range, text) in edits {
+            buffer
+                .apply_edits(vec![EditOperation::new(range, text)])
+                .unwrap();
+            for event in events.lock().unwrap().drain(..) {
+                index.apply_event(&event).unwrap();
+            }
+            index.sync(&buffer).unwrap();
+        }
+
+        let rebuilt = BracketIndex::new(&buffer, BracketConfig::default()).unwrap();
+        assert_eq!(index.line_count(), buffer.line_count());
+        assert_eq!(depths(&index, &buffer), depths(&rebuilt, &buffer));
+        assert_eq!(depths(&index, &buffer).len(), 3);
+    }
+
+    #[test]
+    fn test_brackets_apply_queued_events() {
+        let text = "(\n[\n".repeat(100) + "x";
+        let mut buffer = TextBuffer::from_text(&text);
+        let mut index = BracketIndex::new(&buffer, BracketConfig::default()).unwrap();
+        assert_eq!(index.depth_at_line(200), Some(200));
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        buffer.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+
+        // A batch closing brackets on two lines, then a comment opener
+        buffer
+            .apply_edits(vec![
+                EditOperation::insert(Position::new(200, 0), "])"),
+                EditOperation::insert(Position::new(150, 1), "]\n"),
+            ])
+            .unwrap();
+        buffer.insert(Position::new(100, 0), "/*").unwrap();
+        for event in events.lock().unwrap().drain(..) {
+            index.apply_event(&event).unwrap();
+        }
+        assert!(index.is_dirty());
+        assert!(index.sync(&TextBuffer::new()).is_err());
+        index.sync(&buffer).unwrap();
+        assert!(!index.is_dirty());
+
+        let rebuilt = BracketIndex::new(&buffer, BracketConfig::default()).unwrap();
+        assert_eq!(depths(&index, &buffer), depths(&rebuilt, &buffer));
+        assert_eq!(index.depth_at_line(150), Some(100));
+        assert_eq!(index.depth_at_line(201), Some(100));
+    }
+}
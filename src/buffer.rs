@@ -178,6 +178,12 @@ impl Buffer {
     }
 }
 
+impl AsRef<Buffer> for Buffer {
+    fn as_ref(&self) -> &Buffer {
+        self
+    }
+}
+
 impl Default for Buffer {
     fn default() -> Self {
         Self::new()
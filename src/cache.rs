@@ -0,0 +1,61 @@
+//! Cache module for state filled in by read-only queries
+//!
+//! This module implements the QueryCache which lets `&self` methods of the
+//! TextBuffer build and reuse derived state, such as per-line indentation or
+//! lexer states. The value sits behind a mutex so queries can fill it in, and
+//! behind an `Arc` so clones share it until either copy changes it.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Derived state created on first use and updated by queries and edits
+#[derive(Debug)]
+pub(crate) struct QueryCache<T>(Mutex<Option<Arc<T>>>);
+
+impl<T> Default for QueryCache<T> {
+    fn default() -> Self {
+        Self(Mutex::new(None))
+    }
+}
+
+impl<T> Clone for QueryCache<T> {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.lock().clone()))
+    }
+}
+
+impl<T> QueryCache<T> {
+    fn lock(&self) -> MutexGuard<'_, Option<Arc<T>>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: Clone> QueryCache<T> {
+    /// Runs `f` on the cached value, first creating it with `init` when there
+    /// is none or `valid` rejects it
+    pub(crate) fn with<R>(
+        &self,
+        valid: impl FnOnce(&T) -> bool,
+        init: impl FnOnce() -> T,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> R {
+        let mut cache = self.lock();
+        let value = match cache.as_mut() {
+            Some(value) if valid(value) => value,
+            _ => cache.insert(Arc::new(init())),
+        };
+        f(Arc::make_mut(value))
+    }
+
+    /// Updates the cached value, if any
+    pub(crate) fn update(&mut self, f: impl FnOnce(&mut T)) {
+        let cache = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some(value) = cache {
+            f(Arc::make_mut(value));
+        }
+    }
+
+    /// Drops the cached value
+    pub(crate) fn clear(&mut self) {
+        *self.0.get_mut().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
@@ -0,0 +1,190 @@
+//! Change log module for tracking which lines changed between versions
+//!
+//! This module implements the ChangeLog which remembers the line ranges
+//! touched by recent edits so renderers can ask which lines changed since the
+//! version they last drew.
+
+use std::collections::VecDeque;
+use std::ops::Range as LineRange;
+
+use crate::event::ContentChange;
+
+/// Number of versions kept before the oldest entries are dropped
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Line span touched by one change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineEdit {
+    /// First line of the replaced range
+    start_line: usize,
+    /// Last line of the replaced range, before the change
+    old_end_line: usize,
+    /// Last line of the inserted text, after the change
+    new_end_line: usize,
+}
+
+/// Bounded log of the line spans changed by each version
+#[derive(Debug, Clone)]
+pub struct ChangeLog {
+    /// Edits of each recorded version, oldest first
+    entries: VecDeque<(u64, Vec<LineEdit>)>,
+    /// Oldest version from which changes can still be reconstructed
+    horizon: u64,
+    capacity: usize,
+}
+
+impl ChangeLog {
+    /// Creates an empty log starting at the given version
+    pub fn new(version: u64) -> Self {
+        Self::with_capacity(version, DEFAULT_CAPACITY)
+    }
+
+    /// Creates an empty log keeping at most `capacity` versions
+    pub fn with_capacity(version: u64, capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            horizon: version,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records the changes that produced `version`, in the order applied
+    pub fn record(&mut self, version: u64, changes: &[ContentChange]) {
+        let edits = changes
+            .iter()
+            .map(|change| {
+                let start_line = change.range.start.line;
+                let old_end_line = change.range.end.line;
+                let new_end_line = (old_end_line as isize + change.line_delta) as usize;
+                LineEdit {
+                    start_line,
+                    old_end_line,
+                    new_end_line,
+                }
+            })
+            .collect();
+
+        self.entries.push_back((version, edits));
+        while self.entries.len() > self.capacity {
+            if let Some((dropped, _)) = self.entries.pop_front() {
+                self.horizon = dropped;
+            }
+        }
+    }
+
+    /// Returns the coalesced line ranges changed after `version`, in
+    /// current line numbers, or None if that version is no longer covered
+    pub fn changed_lines_since(&self, version: u64) -> Option<Vec<LineRange<usize>>> {
+        if version < self.horizon {
+            return None;
+        }
+
+        let mut dirty: Vec<LineRange<usize>> = Vec::new();
+        let edits = self
+            .entries
+            .iter()
+            .filter(|(entry_version, _)| *entry_version > version)
+            .flat_map(|(_, edits)| edits.iter());
+
+        for edit in edits {
+            let delta = edit.new_end_line as isize - edit.old_end_line as isize;
+            let mut changed = edit.start_line..edit.new_end_line + 1;
+
+            dirty.retain_mut(|range| {
+                if range.start > edit.old_end_line {
+                    // Entirely after the edit: only shifts
+                    range.start = (range.start as isize + delta) as usize;
+                    range.end = (range.end as isize + delta) as usize;
+                    true
+                } else if range.end <= edit.start_line {
+                    // Entirely before the edit
+                    true
+                } else {
+                    // Overlapping: fold into the changed span
+                    changed.start = changed.start.min(range.start);
+                    if range.end > edit.old_end_line + 1 {
+                        let shifted_end = (range.end as isize + delta) as usize;
+                        changed.end = changed.end.max(shifted_end);
+                    }
+                    false
+                }
+            });
+            dirty.push(changed);
+        }
+
+        dirty.sort_by_key(|range| range.start);
+        let mut coalesced: Vec<LineRange<usize>> = Vec::with_capacity(dirty.len());
+        for range in dirty {
+            match coalesced.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => coalesced.push(range),
+            }
+        }
+
+        Some(coalesced)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use crate::types::{Position, Range};
+
+    fn change(start: (usize, usize), end: (usize, usize), text: &str) -> ContentChange {
+        let range = Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1));
+        let removed_lines = (end.0 - start.0) as isize;
+        ContentChange {
+            range,
+            start_offset: 0,
+            old_end_offset: 0,
+            new_end_offset: 0,
+            text: text.to_string(),
+            line_delta: text.matches('\n').count() as isize - removed_lines,
+        }
+    }
+
+    #[test]
+    fn test_single_line_edits_coalesce() {
+        let mut log = ChangeLog::new(0);
+        log.record(1, &[change((3, 0), (3, 0), "a")]);
+        log.record(2, &[change((4, 1), (4, 1), "b")]);
+        log.record(3, &[change((10, 0), (10, 2), "")]);
+
+        assert_eq!(log.changed_lines_since(0), Some(vec![3..5, 10..11]));
+        assert_eq!(log.changed_lines_since(2), Some(vec![10..11]));
+        assert_eq!(log.changed_lines_since(3), Some(vec![]));
+    }
+
+    #[test]
+    fn test_line_insertions_shift_earlier_ranges() {
+        let mut log = ChangeLog::new(0);
+        log.record(1, &[change((10, 0), (10, 0), "x")]);
+        // Two new lines inserted above line 10
+        log.record(2, &[change((2, 0), (2, 0), "a\nb\n")]);
+
+        assert_eq!(log.changed_lines_since(0), Some(vec![2..5, 12..13]));
+    }
+
+    #[test]
+    fn test_line_deletions_merge_ranges() {
+        let mut log = ChangeLog::new(0);
+        log.record(1, &[change((2, 0), (2, 0), "x")]);
+        log.record(2, &[change((6, 0), (6, 0), "y")]);
+        // Lines 3..=6 joined into line 3
+        log.record(3, &[change((3, 0), (6, 0), "")]);
+
+        assert_eq!(log.changed_lines_since(0), Some(vec![2..4]));
+    }
+
+    #[test]
+    fn test_capacity_limits_history() {
+        let mut log = ChangeLog::with_capacity(0, 2);
+        for version in 1..=4 {
+            log.record(version, &[change((0, 0), (0, 0), "a")]);
+        }
+
+        assert_eq!(log.changed_lines_since(1), None);
+        assert_eq!(log.changed_lines_since(2), Some(vec![0..1]));
+    }
+}
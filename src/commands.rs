@@ -0,0 +1,514 @@
+//! Line commands module for line-oriented editing actions
+//!
+//! This module implements the standard editor actions on whole lines: moving,
+//! duplicating, deleting, joining, sorting, deduplicating, reversing and
+//! indenting them, plus a newline that keeps the indentation. Lines are given
+//! as an inclusive range `first_line..=last_line`. Each command reads the
+//! affected lines once and applies its edits as one batch, a single edit for
+//! most commands and one per line for indenting, so it is one version and one
+//! undo step; the returned edits undo it, and commands that change nothing
+//! return no edits. Lines are read without their line breaks and written
+//! back with the line break of the document, so CRLF text stays CRLF.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::indentation::{leading_whitespace, IndentKind, IndentStyle};
+use crate::snapshot::TextSnapshot;
+use crate::text_buffer::TextBuffer;
+use crate::types::{EditOperation, Position, Range, TextBufferResult};
+
+/// How `sort_lines` compares lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortOptions {
+    /// Compare lines ignoring case
+    pub case_insensitive: bool,
+    /// Compare runs of digits by their numeric value, so "a2" sorts before
+    /// "a10"
+    pub natural: bool,
+    /// Sort from the greatest line to the smallest
+    pub descending: bool,
+}
+
+/// Moves the lines one line up, above the line preceding them
+pub fn move_lines_up(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    if first_line == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut lines = read_lines(buffer, first_line - 1, last_line)?;
+    lines.rotate_left(1);
+    replace_lines(buffer, first_line - 1, last_line, lines)
+}
+
+/// Moves the lines one line down, below the line following them
+pub fn move_lines_down(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    if last_line + 1 >= buffer.line_count() {
+        return Ok(Vec::new());
+    }
+
+    let mut lines = read_lines(buffer, first_line, last_line + 1)?;
+    lines.rotate_right(1);
+    replace_lines(buffer, first_line, last_line + 1, lines)
+}
+
+/// Inserts a copy of the lines below them
+pub fn duplicate_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let lines = read_lines(buffer, first_line, last_line)?;
+    let end = line_end(buffer, last_line)?;
+    let line_ending = buffer.as_ref().line_ending();
+    buffer.apply_edits(vec![EditOperation::insert(
+        end,
+        format!("{line_ending}{}", lines.join(line_ending)),
+    )])
+}
+
+/// Deletes the lines along with their line breaks
+pub fn delete_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+
+    // Take the line break after the lines, or before them on the last line
+    let range = if last_line + 1 < buffer.line_count() {
+        Range::new(
+            Position::new(first_line, 0),
+            Position::new(last_line + 1, 0),
+        )
+    } else if first_line > 0 {
+        Range::new(
+            line_end(buffer, first_line - 1)?,
+            line_end(buffer, last_line)?,
+        )
+    } else {
+        Range::new(Position::new(0, 0), line_end(buffer, last_line)?)
+    };
+    buffer.apply_edits(vec![EditOperation::delete(range)])
+}
+
+/// Joins the lines into one, separated by single spaces
+///
+/// Leading whitespace of the joined lines is dropped, and no space is added
+/// before an empty line. A single line is joined with the line after it.
+pub fn join_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let last_line = match first_line == last_line {
+        true if last_line + 1 < buffer.line_count() => last_line + 1,
+        true => return Ok(Vec::new()),
+        false => last_line,
+    };
+
+    let lines = read_lines(buffer, first_line, last_line)?;
+    let mut joined = lines[0].trim_end().to_string();
+    for line in &lines[1..] {
+        let line = line.trim();
+        if !line.is_empty() && !joined.is_empty() {
+            joined.push(' ');
+        }
+        joined.push_str(line);
+    }
+    replace_lines(buffer, first_line, last_line, vec![joined])
+}
+
+/// Sorts the lines; equal lines keep their order
+pub fn sort_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+    options: SortOptions,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let lines = read_lines(buffer, first_line, last_line)?;
+
+    let mut keyed: Vec<(String, String)> = lines
+        .into_iter()
+        .map(|line| match options.case_insensitive {
+            true => (line.to_lowercase(), line),
+            false => (line.clone(), line),
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| {
+        let ordering = match options.natural {
+            true => natural_cmp(a, b),
+            false => a.cmp(b),
+        };
+        match options.descending {
+            true => ordering.reverse(),
+            false => ordering,
+        }
+    });
+
+    let lines = keyed.into_iter().map(|(_, line)| line).collect();
+    replace_lines(buffer, first_line, last_line, lines)
+}
+
+/// Removes lines equal to an earlier line in the range
+pub fn unique_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let mut lines = read_lines(buffer, first_line, last_line)?;
+    let mut seen = HashSet::new();
+    lines.retain(|line| seen.insert(line.clone()));
+    replace_lines(buffer, first_line, last_line, lines)
+}
+
+/// Reverses the order of the lines
+pub fn reverse_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let mut lines = read_lines(buffer, first_line, last_line)?;
+    lines.reverse();
+    replace_lines(buffer, first_line, last_line, lines)
+}
+
+/// Indents the non-blank lines by one level of the buffer's indentation style
+pub fn indent_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let unit = indent_unit(buffer.indent_style().unwrap_or_default());
+
+    let mut edits = Vec::new();
+    for line in first_line..=last_line {
+        let (_, blank) = leading_whitespace(buffer.as_ref(), line)?;
+        if !blank {
+            edits.push(EditOperation::insert(Position::new(line, 0), unit.clone()));
+        }
+    }
+    buffer.apply_edits(edits)
+}
+
+/// Removes up to one level of indentation from the lines: a tab, or up to
+/// the indentation size in spaces
+pub fn outdent_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    check_lines(buffer, first_line, last_line)?;
+    let size = buffer.indent_style().unwrap_or_default().size.max(1);
+
+    let mut edits = Vec::new();
+    for line in first_line..=last_line {
+        let (whitespace, _) = leading_whitespace(buffer.as_ref(), line)?;
+        let width = match whitespace.first() {
+            Some('\t') => 1,
+            _ => whitespace
+                .iter()
+                .take(size)
+                .take_while(|&&ch| ch == ' ')
+                .count(),
+        };
+        if width > 0 {
+            let range = Range::new(Position::new(line, 0), Position::new(line, width));
+            edits.push(EditOperation::delete(range));
+        }
+    }
+    buffer.apply_edits(edits)
+}
+
+/// Inserts a line break that keeps the indentation of the line
+///
+/// After one of the buffer's indent openers the new line is indented one
+/// level further, and a closing bracket right after the position moves to a
+/// line of its own. Returns the position for the cursor and the edits that
+/// undo the newline.
+pub fn insert_newline(
+    buffer: &mut TextBuffer,
+    position: Position,
+) -> TextBufferResult<(Position, Vec<EditOperation>)> {
+    let text: &TextSnapshot = buffer.as_ref();
+    let offset = text.position_to_offset(position)?;
+    let line_start = text.line_start_summary(position.line)?;
+    let content_end = text.line_content_end(position.line)?;
+    let line_ending = text.line_ending();
+    let before = text.text_between(line_start.bytes, offset)?;
+
+    // Indentation ends at the position when it is inside the indentation
+    let (whitespace, _) = leading_whitespace(text, position.line)?;
+    let indent: String = whitespace
+        .into_iter()
+        .take(before.chars().count())
+        .collect();
+
+    let opener = before
+        .trim_end()
+        .chars()
+        .last()
+        .filter(|&ch| buffer.indent_openers().contains(ch));
+    let next = text.chars_in(offset, content_end).next().map(|(_, ch)| ch);
+
+    let mut inserted = format!("{line_ending}{indent}");
+    let mut cursor = Position::new(position.line + 1, indent.chars().count());
+    if opener.is_some() {
+        let unit = indent_unit(buffer.indent_style().unwrap_or_default());
+        inserted.push_str(&unit);
+        cursor.column += unit.chars().count();
+
+        let closer = match opener {
+            Some('{') => Some('}'),
+            Some('[') => Some(']'),
+            Some('(') => Some(')'),
+            _ => None,
+        };
+        if closer.is_some() && next == closer {
+            inserted.push_str(line_ending);
+            inserted.push_str(&indent);
+        }
+    }
+
+    let undo = buffer.apply_edits(vec![EditOperation::insert(position, inserted)])?;
+    Ok((cursor, undo))
+}
+
+/// Returns the text of one level of indentation
+fn indent_unit(style: IndentStyle) -> String {
+    match style.kind {
+        IndentKind::Tabs => "\t".to_string(),
+        IndentKind::Spaces => " ".repeat(style.size.max(1)),
+    }
+}
+
+/// Compares strings treating runs of ASCII digits as numbers
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_digits = a.len() - a.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+            let b_digits = b.len() - b.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+            let (a_number, b_number) = (&a[..a_digits], &b[..b_digits]);
+            let (a_value, b_value) = (
+                a_number.trim_start_matches('0'),
+                b_number.trim_start_matches('0'),
+            );
+            let ordering = a_value
+                .len()
+                .cmp(&b_value.len())
+                .then_with(|| a_value.cmp(b_value))
+                .then_with(|| a_number.len().cmp(&b_number.len()));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (a, b) = (&a[a_digits..], &b[b_digits..]);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+/// Checks that `first_line..=last_line` is a valid line range
+fn check_lines(buffer: &TextBuffer, first_line: usize, last_line: usize) -> TextBufferResult<()> {
+    if first_line > last_line {
+        return Err("Invalid range".to_string());
+    }
+    if last_line >= buffer.line_count() {
+        return Err(format!("Line {last_line} out of bounds"));
+    }
+    Ok(())
+}
+
+/// Returns the content of the lines, without line breaks
+fn read_lines(
+    buffer: &TextBuffer,
+    first_line: usize,
+    last_line: usize,
+) -> TextBufferResult<Vec<String>> {
+    let text: &TextSnapshot = buffer.as_ref();
+    let (start, _) = text.line_bounds(first_line)?;
+    let (_, end) = text.line_bounds(last_line)?;
+    let content = text.text_between(start.bytes, end.bytes)?;
+    Ok(content
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect())
+}
+
+/// Returns the position at the end of a line's content, before any CR
+fn line_end(buffer: &TextBuffer, line: usize) -> TextBufferResult<Position> {
+    let text: &TextSnapshot = buffer.as_ref();
+    text.offset_to_position(text.line_content_end(line)?)
+}
+
+/// Replaces the lines with new lines in one edit, unless nothing changes
+fn replace_lines(
+    buffer: &mut TextBuffer,
+    first_line: usize,
+    last_line: usize,
+    lines: Vec<String>,
+) -> TextBufferResult<Vec<EditOperation>> {
+    let line_ending = buffer.as_ref().line_ending();
+    let text = lines.join(line_ending);
+    if read_lines(buffer, first_line, last_line)?.join(line_ending) == text {
+        return Ok(Vec::new());
+    }
+
+    let range = Range::new(Position::new(first_line, 0), line_end(buffer, last_line)?);
+    buffer.apply_edits(vec![EditOperation::new(range, text)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_duplicate_delete() {
+        let mut buffer = TextBuffer::from_text("a\nb\nc\nd");
+
+        move_lines_up(&mut buffer, 2, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\nc\nd\nb");
+        let undo = move_lines_down(&mut buffer, 0, 0).unwrap();
+        assert_eq!(buffer.get_all_text(), "c\na\nd\nb");
+        assert_eq!(buffer.version(), 2);
+        buffer.apply_edits(undo).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\nc\nd\nb");
+        assert!(move_lines_up(&mut buffer, 0, 1).unwrap().is_empty());
+
+        duplicate_lines(&mut buffer, 2, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\nc\nd\nb\nd\nb");
+        delete_lines(&mut buffer, 0, 1).unwrap();
+        assert_eq!(buffer.get_all_text(), "d\nb\nd\nb");
+        delete_lines(&mut buffer, 2, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "d\nb");
+        assert!(delete_lines(&mut buffer, 1, 2).is_err());
+    }
+
+    #[test]
+    fn test_crlf_lines() {
+        let mut buffer = TextBuffer::from_text("a\r\nb\r\nc");
+
+        move_lines_up(&mut buffer, 1, 1).unwrap();
+        assert_eq!(buffer.get_all_text(), "b\r\na\r\nc");
+        move_lines_down(&mut buffer, 1, 1).unwrap();
+        assert_eq!(buffer.get_all_text(), "b\r\nc\r\na");
+        duplicate_lines(&mut buffer, 0, 1).unwrap();
+        assert_eq!(buffer.get_all_text(), "b\r\nc\r\nb\r\nc\r\na");
+        sort_lines(&mut buffer, 0, 4, SortOptions::default()).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\r\nb\r\nb\r\nc\r\nc");
+        join_lines(&mut buffer, 0, 1).unwrap();
+        assert_eq!(buffer.get_all_text(), "a b\r\nb\r\nc\r\nc");
+        delete_lines(&mut buffer, 3, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "a b\r\nb\r\nc");
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let mut buffer = TextBuffer::from_text("fn f(  \n    a,\n\n    b)\nx");
+        join_lines(&mut buffer, 0, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "fn f( a, b)\nx");
+        join_lines(&mut buffer, 0, 0).unwrap();
+        assert_eq!(buffer.get_all_text(), "fn f( a, b) x");
+    }
+
+    #[test]
+    fn test_sort_unique_reverse() {
+        let mut buffer = TextBuffer::from_text("b10\nB2\na\nb2\na\nend");
+
+        sort_lines(&mut buffer, 0, 4, SortOptions::default()).unwrap();
+        assert_eq!(buffer.get_all_text(), "B2\na\na\nb10\nb2\nend");
+
+        let options = SortOptions {
+            case_insensitive: true,
+            natural: true,
+            descending: false,
+        };
+        sort_lines(&mut buffer, 0, 4, options).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\na\nB2\nb2\nb10\nend");
+
+        unique_lines(&mut buffer, 0, 5).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\nB2\nb2\nb10\nend");
+        reverse_lines(&mut buffer, 1, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\nb10\nb2\nB2\nend");
+        assert!(sort_lines(&mut buffer, 2, 3, options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_indent_outdent() {
+        let mut buffer = TextBuffer::from_text("a\n\n  b\n\tc");
+        buffer.set_indent_style(IndentStyle {
+            kind: IndentKind::Spaces,
+            size: 4,
+            confidence: 1.0,
+        });
+
+        indent_lines(&mut buffer, 0, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "    a\n\n      b\n    \tc");
+        assert_eq!(buffer.version(), 1);
+
+        outdent_lines(&mut buffer, 0, 3).unwrap();
+        outdent_lines(&mut buffer, 0, 3).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\n\nb\nc");
+        assert!(outdent_lines(&mut buffer, 0, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_insert_newline() {
+        let mut buffer = TextBuffer::from_text("  if x {}\n  y");
+
+        let (cursor, _) = insert_newline(&mut buffer, Position::new(0, 8)).unwrap();
+        assert_eq!(buffer.get_all_text(), "  if x {\n      \n  }\n  y");
+        assert_eq!(cursor, Position::new(1, 6));
+        assert_eq!(buffer.version(), 1);
+
+        let (cursor, undo) = insert_newline(&mut buffer, Position::new(3, 3)).unwrap();
+        assert_eq!(buffer.get_all_text(), "  if x {\n      \n  }\n  y\n  ");
+        assert_eq!(cursor, Position::new(4, 2));
+        buffer.apply_edits(undo).unwrap();
+
+        // Inside the indentation only the part before the position is kept
+        let (cursor, _) = insert_newline(&mut buffer, Position::new(3, 1)).unwrap();
+        assert_eq!(buffer.get_all_text(), "  if x {\n      \n  }\n \n  y");
+        assert_eq!(cursor, Position::new(4, 1));
+
+        let mut buffer = TextBuffer::from_text("if x {\r\n}");
+        let (cursor, _) = insert_newline(&mut buffer, Position::new(0, 6)).unwrap();
+        assert_eq!(buffer.get_all_text(), "if x {\r\n    \r\n}");
+        assert_eq!(cursor, Position::new(1, 4));
+
+        let mut buffer = TextBuffer::from_text("f(\r\n");
+        insert_newline(&mut buffer, Position::new(0, 2)).unwrap();
+        assert_eq!(buffer.get_all_text(), "f(\r\n    \r\n");
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("file9", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("v1.02", "v1.2"), Ordering::Greater);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+}
@@ -0,0 +1,246 @@
+//! Cursor module for caret movement
+//!
+//! This module implements the Cursor, a caret position with the movements
+//! every editor frontend needs. Horizontal moves step over whole grapheme
+//! clusters and across line breaks. Vertical moves remember the display
+//! column the cursor started from, so moving through a short line and back
+//! returns to the original column.
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{Position, TextBufferResult};
+
+/// A caret in a text with a sticky display column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    position: Position,
+    /// Display column kept by consecutive vertical moves
+    desired_column: Option<usize>,
+}
+
+impl Cursor {
+    /// Creates a cursor at a position
+    pub fn new(position: Position) -> Self {
+        Self {
+            position,
+            desired_column: None,
+        }
+    }
+
+    /// Returns the position of the cursor
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Returns the display column kept by vertical moves, if any
+    pub fn desired_column(&self) -> Option<usize> {
+        self.desired_column
+    }
+
+    /// Moves the cursor to a position, snapped to a grapheme boundary
+    pub fn set_position(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+        position: Position,
+    ) -> TextBufferResult<()> {
+        let position = text.as_ref().snap_to_grapheme(position)?;
+        self.jump(position);
+        Ok(())
+    }
+
+    /// Moves one grapheme left, to the end of the previous line at the start
+    /// of a line
+    pub fn move_left(&mut self, text: &impl AsRef<TextSnapshot>) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        let offset = text.position_to_offset(self.position)?;
+        let offset = text.prev_grapheme_boundary(offset)?;
+        self.jump(text.offset_to_position(offset)?);
+        Ok(())
+    }
+
+    /// Moves one grapheme right, to the start of the next line at the end of
+    /// a line
+    pub fn move_right(&mut self, text: &impl AsRef<TextSnapshot>) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        let offset = text.position_to_offset(self.position)?;
+        let offset = text.next_grapheme_boundary(offset)?;
+        self.jump(text.offset_to_position(offset)?);
+        Ok(())
+    }
+
+    /// Moves one line up, to the start of the text from the first line
+    pub fn move_up(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+        tab_size: usize,
+    ) -> TextBufferResult<()> {
+        self.move_lines(text.as_ref(), -1, tab_size)
+    }
+
+    /// Moves one line down, to the end of the text from the last line
+    pub fn move_down(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+        tab_size: usize,
+    ) -> TextBufferResult<()> {
+        self.move_lines(text.as_ref(), 1, tab_size)
+    }
+
+    /// Moves up by a viewport of `viewport_height` lines
+    pub fn page_up(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+        viewport_height: usize,
+        tab_size: usize,
+    ) -> TextBufferResult<()> {
+        let lines = -(viewport_height.max(1) as isize);
+        self.move_lines(text.as_ref(), lines, tab_size)
+    }
+
+    /// Moves down by a viewport of `viewport_height` lines
+    pub fn page_down(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+        viewport_height: usize,
+        tab_size: usize,
+    ) -> TextBufferResult<()> {
+        let lines = viewport_height.max(1) as isize;
+        self.move_lines(text.as_ref(), lines, tab_size)
+    }
+
+    /// Moves to the first non-whitespace char of the line, or to the start
+    /// of the line when already there
+    pub fn move_home(&mut self, text: &impl AsRef<TextSnapshot>) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        let line = self.position.line;
+        let (start, end) = text.line_bounds(line)?;
+        let first_non_whitespace = text
+            .chars_in(start.bytes, end.bytes)
+            .find(|&(_, ch)| ch != ' ' && ch != '\t')
+            .map_or(end.bytes, |(offset, _)| offset);
+
+        let indented = text.offset_to_position(first_non_whitespace)?;
+        self.jump(match self.position == indented {
+            true => Position::new(line, 0),
+            false => indented,
+        });
+        Ok(())
+    }
+
+    /// Moves to the end of the line, before a CR ending it
+    pub fn move_end(&mut self, text: &impl AsRef<TextSnapshot>) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        let (start, end) = text.line_bounds(self.position.line)?;
+        let ends_with_cr = end.bytes > start.bytes
+            && text.chars_in(end.bytes - 1, end.bytes).next() == Some((end.bytes - 1, '\r'));
+        let end = end.bytes - usize::from(ends_with_cr);
+        self.jump(text.offset_to_position(end)?);
+        Ok(())
+    }
+
+    /// Moves to the start of the text
+    pub fn move_to_document_start(&mut self) {
+        self.jump(Position::new(0, 0));
+    }
+
+    /// Moves to the end of the text
+    pub fn move_to_document_end(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+    ) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        self.jump(text.offset_to_position(text.length())?);
+        Ok(())
+    }
+
+    /// Helper method to move by a number of lines, keeping the display column
+    fn move_lines(
+        &mut self,
+        text: &TextSnapshot,
+        lines: isize,
+        tab_size: usize,
+    ) -> TextBufferResult<()> {
+        let last_line = text.line_count() - 1;
+        let line = match self.position.line.checked_add_signed(lines) {
+            Some(line) if line <= last_line => line,
+            Some(_) => return self.move_to_document_end(&text),
+            None => {
+                self.move_to_document_start();
+                return Ok(());
+            }
+        };
+
+        let column = match self.desired_column {
+            Some(column) => column,
+            None => text.visual_column(self.position, tab_size)?,
+        };
+        let position = text.position_at_visual_column(line, column, tab_size)?;
+
+        // Columns inside a cluster of wide chars resolve to its start
+        let offset = text.position_to_offset(position)?;
+        let position = match text.is_grapheme_boundary(offset)? {
+            true => position,
+            false => text.offset_to_position(text.prev_grapheme_boundary(offset)?)?,
+        };
+
+        self.position = position;
+        self.desired_column = Some(column);
+        Ok(())
+    }
+
+    /// Helper method to move without keeping the display column
+    fn jump(&mut self, position: Position) {
+        self.position = position;
+        self.desired_column = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+
+    #[test]
+    fn test_horizontal_moves() {
+        let buffer = TextBuffer::from_text("  ab\r\ne\u{301}");
+        let mut cursor = Cursor::new(Position::new(0, 4));
+
+        cursor.move_right(&buffer).unwrap();
+        assert_eq!(cursor.position(), Position::new(1, 0));
+        cursor.move_right(&buffer).unwrap();
+        assert_eq!(cursor.position(), Position::new(1, 2));
+        cursor.move_left(&buffer).unwrap();
+        cursor.move_left(&buffer).unwrap();
+        assert_eq!(cursor.position(), Position::new(0, 4));
+
+        // Smart home toggles between the indentation and column zero
+        cursor.move_home(&buffer).unwrap();
+        assert_eq!(cursor.position(), Position::new(0, 2));
+        cursor.move_home(&buffer).unwrap();
+        assert_eq!(cursor.position(), Position::new(0, 0));
+        cursor.move_end(&buffer).unwrap();
+        assert_eq!(cursor.position(), Position::new(0, 4));
+    }
+
+    #[test]
+    fn test_vertical_moves_keep_column() {
+        let buffer = TextBuffer::from_text("hello world\nab\n\tx = 1;\n日本語\nlast");
+        let mut cursor = Cursor::new(Position::new(0, 5));
+
+        cursor.move_down(&buffer, 4).unwrap();
+        assert_eq!(cursor.position(), Position::new(1, 2));
+        cursor.move_down(&buffer, 4).unwrap();
+        assert_eq!(cursor.position(), Position::new(2, 2));
+        // Column 5 falls inside "語", so the cursor lands before it
+        cursor.move_down(&buffer, 4).unwrap();
+        assert_eq!(cursor.position(), Position::new(3, 2));
+        cursor.page_up(&buffer, 3, 4).unwrap();
+        assert_eq!(cursor.position(), Position::new(0, 5));
+        assert_eq!(cursor.desired_column(), Some(5));
+
+        cursor.move_up(&buffer, 4).unwrap();
+        assert_eq!(cursor.position(), Position::new(0, 0));
+        cursor.page_down(&buffer, 10, 4).unwrap();
+        assert_eq!(cursor.position(), Position::new(4, 4));
+        assert_eq!(cursor.desired_column(), None);
+    }
+}
@@ -0,0 +1,718 @@
+//! Decoration module for ranges that follow edits
+//!
+//! This module implements the DecorationTree, an interval tree storing byte
+//! ranges with a payload each. It is a treap ordered by start offset where
+//! every node caches the largest end offset of its subtree, which lets
+//! intersection queries skip subtrees that end before the queried range.
+//! Nodes carry lazy shift tags so decorations after an edit move in
+//! O(log n); only decorations touching the edited range are adjusted one by
+//! one.
+
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::marker::Stickiness;
+use crate::types::Range;
+
+/// Identifier of a decoration returned by `DecorationTree::add`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecorationId {
+    index: usize,
+    generation: u32,
+}
+
+/// How a decoration grows when text is typed at its edges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackedRangeStickiness {
+    /// Text inserted at either edge becomes part of the range
+    AlwaysGrowsWhenTypingAtEdges,
+    /// Text inserted at either edge stays outside of the range
+    NeverGrowsWhenTypingAtEdges,
+    /// Only text inserted at the start becomes part of the range
+    GrowsOnlyWhenTypingBefore,
+    /// Only text inserted at the end becomes part of the range
+    GrowsOnlyWhenTypingAfter,
+}
+
+impl TrackedRangeStickiness {
+    /// Returns the stickiness of the start offset
+    pub fn start(self) -> Stickiness {
+        match self {
+            Self::AlwaysGrowsWhenTypingAtEdges | Self::GrowsOnlyWhenTypingBefore => {
+                Stickiness::Left
+            }
+            Self::NeverGrowsWhenTypingAtEdges | Self::GrowsOnlyWhenTypingAfter => Stickiness::Right,
+        }
+    }
+
+    /// Returns the stickiness of the end offset
+    pub fn end(self) -> Stickiness {
+        match self {
+            Self::AlwaysGrowsWhenTypingAtEdges | Self::GrowsOnlyWhenTypingAfter => {
+                Stickiness::Right
+            }
+            Self::NeverGrowsWhenTypingAtEdges | Self::GrowsOnlyWhenTypingBefore => Stickiness::Left,
+        }
+    }
+}
+
+/// Payload attached to a decoration of a `TextBuffer`
+pub type DecorationPayload = Arc<dyn Any + Send + Sync>;
+
+/// A decoration returned by `TextBuffer` queries
+#[derive(Debug, Clone)]
+pub struct Decoration {
+    /// Identifier of the decoration
+    pub id: DecorationId,
+    /// Current range covered by the decoration
+    pub range: Range,
+    /// Payload attached when the decoration was added
+    payload: DecorationPayload,
+}
+
+impl Decoration {
+    /// Creates a decoration result
+    pub fn new(id: DecorationId, range: Range, payload: DecorationPayload) -> Self {
+        Self { id, range, payload }
+    }
+
+    /// Returns the payload if it has the requested type
+    pub fn payload<T: Any>(&self) -> Option<&T> {
+        self.payload.downcast_ref()
+    }
+}
+
+/// A decoration node in the treap
+#[derive(Debug, Clone)]
+struct DecorationNode<T> {
+    /// Start offset once the tags of all ancestors are applied
+    start: usize,
+    /// End offset once the tags of all ancestors are applied
+    end: usize,
+    /// Largest end offset in this subtree
+    max_end: usize,
+    /// Shift not yet pushed down to the children
+    pending: isize,
+    priority: u32,
+    stickiness: TrackedRangeStickiness,
+    generation: u32,
+    payload: Option<T>,
+    parent: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// Interval tree of byte ranges with payloads that follow edits
+#[derive(Debug, Clone)]
+pub struct DecorationTree<T> {
+    /// Arena holding every node; freed slots are reused
+    nodes: Vec<DecorationNode<T>>,
+    /// Indices of freed slots in `nodes`
+    free: Vec<usize>,
+    root: Option<usize>,
+    len: usize,
+    /// State of the priority generator
+    seed: u32,
+}
+
+impl<T> DecorationTree<T> {
+    /// Creates an empty decoration tree
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            len: 0,
+            seed: 0x85eb_ca6b,
+        }
+    }
+
+    /// Returns the number of decorations
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no decorations
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a decoration covering the bytes in `start..end`
+    pub fn add(
+        &mut self,
+        start: usize,
+        end: usize,
+        stickiness: TrackedRangeStickiness,
+        payload: T,
+    ) -> DecorationId {
+        assert!(start <= end, "Decoration start after end");
+
+        let node = DecorationNode {
+            start,
+            end,
+            max_end: end,
+            pending: 0,
+            priority: self.next_priority(),
+            stickiness,
+            generation: 0,
+            payload: Some(payload),
+            parent: None,
+            left: None,
+            right: None,
+        };
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                let generation = self.nodes[index].generation;
+                self.nodes[index] = DecorationNode { generation, ..node };
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        self.insert_node(index);
+        self.len += 1;
+
+        DecorationId {
+            index,
+            generation: self.nodes[index].generation,
+        }
+    }
+
+    /// Returns the current byte range of a decoration
+    pub fn range(&self, id: DecorationId) -> Option<(usize, usize)> {
+        let node = self.node(id)?;
+        let mut delta = 0;
+        let mut parent = node.parent;
+        while let Some(index) = parent {
+            delta += self.nodes[index].pending;
+            parent = self.nodes[index].parent;
+        }
+        Some((shift(node.start, delta), shift(node.end, delta)))
+    }
+
+    /// Returns the payload of a decoration
+    pub fn payload(&self, id: DecorationId) -> Option<&T> {
+        self.node(id)?.payload.as_ref()
+    }
+
+    /// Returns the stickiness of a decoration
+    pub fn stickiness(&self, id: DecorationId) -> Option<TrackedRangeStickiness> {
+        self.node(id).map(|node| node.stickiness)
+    }
+
+    /// Removes a decoration and returns its payload
+    pub fn remove(&mut self, id: DecorationId) -> Option<T> {
+        self.node(id)?;
+        self.detach(id.index);
+
+        let node = &mut self.nodes[id.index];
+        node.generation = node.generation.wrapping_add(1);
+        let payload = node.payload.take();
+        self.free.push(id.index);
+        self.len -= 1;
+        payload
+    }
+
+    /// Returns decorations intersecting the closed byte range `start..=end`
+    /// ordered by start offset, as `(id, start, end)`
+    pub fn search(&self, start: usize, end: usize) -> Vec<(DecorationId, usize, usize)> {
+        let mut result = Vec::new();
+        self.search_recursive(self.root, 0, start, end, &mut result);
+        result
+    }
+
+    /// Adjusts decorations after the bytes in `offset..offset + deleted`
+    /// were replaced by `inserted` bytes
+    pub fn apply_edit(&mut self, offset: usize, deleted: usize, inserted: usize) {
+        if deleted == 0 && inserted == 0 {
+            return;
+        }
+        let edit_end = offset + deleted;
+
+        // Decorations starting after the edit only move
+        let (touched, after) = self.split(self.root, edit_end, true);
+        let delta = inserted as isize - deleted as isize;
+        self.apply_shift(after, delta);
+
+        // Decorations overlapping or touching the edit are adjusted one by one
+        self.root = touched;
+        if let Some(index) = touched {
+            self.nodes[index].parent = None;
+        }
+        let mut affected = Vec::new();
+        self.collect_ending_after(self.root, offset, &mut affected);
+        for &index in &affected {
+            self.detach(index);
+        }
+
+        self.root = self.merge(self.root, after);
+        if let Some(index) = self.root {
+            self.nodes[index].parent = None;
+        }
+
+        for index in affected {
+            let node = &self.nodes[index];
+            let stickiness = node.stickiness;
+            let start = adjust(node.start, stickiness.start(), offset, deleted, inserted);
+            let end = adjust(node.end, stickiness.end(), offset, deleted, inserted);
+
+            let node = &mut self.nodes[index];
+            // A collapsed range that would invert stays collapsed at its end
+            node.start = start.min(end);
+            node.end = end;
+            self.insert_node(index);
+        }
+    }
+
+    fn node(&self, id: DecorationId) -> Option<&DecorationNode<T>> {
+        self.nodes
+            .get(id.index)
+            .filter(|node| node.payload.is_some() && node.generation == id.generation)
+    }
+
+    /// Xorshift generator for treap priorities
+    fn next_priority(&mut self) -> u32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    /// Links a detached node into the treap at the position of its start
+    fn insert_node(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        node.pending = 0;
+        node.max_end = node.end;
+        node.parent = None;
+        node.left = None;
+        node.right = None;
+
+        let start = node.start;
+        let (before, after) = self.split(self.root, start, false);
+        let root = self.merge(before, Some(index));
+        self.root = self.merge(root, after);
+        if let Some(root) = self.root {
+            self.nodes[root].parent = None;
+        }
+    }
+
+    /// Unlinks a node from the treap, leaving its start and end exact
+    fn detach(&mut self, index: usize) {
+        let mut path = Vec::new();
+        let mut current = Some(index);
+        while let Some(i) = current {
+            path.push(i);
+            current = self.nodes[i].parent;
+        }
+        for &i in path.iter().rev() {
+            self.push_down(i);
+        }
+
+        let (left, right, parent) = {
+            let node = &self.nodes[index];
+            (node.left, node.right, node.parent)
+        };
+        let merged = self.merge(left, right);
+
+        match parent {
+            Some(parent) => {
+                if self.nodes[parent].left == Some(index) {
+                    self.nodes[parent].left = merged;
+                } else {
+                    self.nodes[parent].right = merged;
+                }
+                if let Some(child) = merged {
+                    self.nodes[child].parent = Some(parent);
+                }
+                // Refresh the cached maximum up to the root
+                let mut current = Some(parent);
+                while let Some(i) = current {
+                    self.update(i);
+                    current = self.nodes[i].parent;
+                }
+            }
+            None => {
+                if let Some(child) = merged {
+                    self.nodes[child].parent = None;
+                }
+                self.root = merged;
+            }
+        }
+
+        let node = &mut self.nodes[index];
+        node.parent = None;
+        node.left = None;
+        node.right = None;
+    }
+
+    /// Collects nodes whose end is at or after `offset`
+    fn collect_ending_after(&mut self, root: Option<usize>, offset: usize, out: &mut Vec<usize>) {
+        let index = match root {
+            Some(index) if self.nodes[index].max_end >= offset => index,
+            _ => return,
+        };
+        self.push_down(index);
+
+        let (left, right) = (self.nodes[index].left, self.nodes[index].right);
+        self.collect_ending_after(left, offset, out);
+        if self.nodes[index].end >= offset {
+            out.push(index);
+        }
+        self.collect_ending_after(right, offset, out);
+    }
+
+    fn search_recursive(
+        &self,
+        root: Option<usize>,
+        delta: isize,
+        start: usize,
+        end: usize,
+        out: &mut Vec<(DecorationId, usize, usize)>,
+    ) {
+        let index = match root {
+            Some(index) => index,
+            None => return,
+        };
+        let node = &self.nodes[index];
+        if shift(node.max_end, delta) < start {
+            return;
+        }
+
+        let child_delta = delta + node.pending;
+        self.search_recursive(node.left, child_delta, start, end, out);
+
+        let node_start = shift(node.start, delta);
+        if node_start > end {
+            return;
+        }
+        let node_end = shift(node.end, delta);
+        if node_end >= start {
+            let id = DecorationId {
+                index,
+                generation: node.generation,
+            };
+            out.push((id, node_start, node_end));
+        }
+
+        self.search_recursive(node.right, child_delta, start, end, out);
+    }
+
+    /// Applies a shift to a whole subtree
+    fn apply_shift(&mut self, root: Option<usize>, delta: isize) {
+        if let Some(index) = root {
+            let node = &mut self.nodes[index];
+            node.start = shift(node.start, delta);
+            node.end = shift(node.end, delta);
+            node.max_end = shift(node.max_end, delta);
+            node.pending += delta;
+        }
+    }
+
+    /// Pushes the pending shift of a node down to its children
+    fn push_down(&mut self, index: usize) {
+        let pending = self.nodes[index].pending;
+        if pending == 0 {
+            return;
+        }
+        let (left, right) = (self.nodes[index].left, self.nodes[index].right);
+        self.apply_shift(left, pending);
+        self.apply_shift(right, pending);
+        self.nodes[index].pending = 0;
+    }
+
+    /// Recomputes the cached maximum end of a node from its children
+    fn update(&mut self, index: usize) {
+        let node = &self.nodes[index];
+        let mut max_end = node.end;
+        for child in [node.left, node.right].into_iter().flatten() {
+            max_end = max_end.max(self.nodes[child].max_end);
+        }
+        self.nodes[index].max_end = max_end;
+    }
+
+    /// Splits a treap into nodes starting before `offset` and the rest; nodes
+    /// starting at `offset` go to the first part when `inclusive` is set
+    fn split(
+        &mut self,
+        root: Option<usize>,
+        offset: usize,
+        inclusive: bool,
+    ) -> (Option<usize>, Option<usize>) {
+        let index = match root {
+            Some(index) => index,
+            None => return (None, None),
+        };
+        self.push_down(index);
+
+        let start = self.nodes[index].start;
+        let goes_left = start < offset || (inclusive && start == offset);
+
+        if goes_left {
+            let (middle, rest) = self.split(self.nodes[index].right, offset, inclusive);
+            self.set_right(index, middle);
+            self.update(index);
+            if let Some(rest) = rest {
+                self.nodes[rest].parent = None;
+            }
+            (Some(index), rest)
+        } else {
+            let (rest, middle) = self.split(self.nodes[index].left, offset, inclusive);
+            self.set_left(index, middle);
+            self.update(index);
+            if let Some(rest) = rest {
+                self.nodes[rest].parent = None;
+            }
+            (rest, Some(index))
+        }
+    }
+
+    /// Merges two treaps where every node of `left` starts no later than
+    /// those of `right`
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        let (l, r) = match (left, right) {
+            (None, tree) | (tree, None) => return tree,
+            (Some(l), Some(r)) => (l, r),
+        };
+
+        if self.nodes[l].priority > self.nodes[r].priority {
+            self.push_down(l);
+            let merged = self.merge(self.nodes[l].right, Some(r));
+            self.set_right(l, merged);
+            self.update(l);
+            Some(l)
+        } else {
+            self.push_down(r);
+            let merged = self.merge(Some(l), self.nodes[r].left);
+            self.set_left(r, merged);
+            self.update(r);
+            Some(r)
+        }
+    }
+
+    fn set_left(&mut self, index: usize, child: Option<usize>) {
+        self.nodes[index].left = child;
+        if let Some(child) = child {
+            self.nodes[child].parent = Some(index);
+        }
+    }
+
+    fn set_right(&mut self, index: usize, child: Option<usize>) {
+        self.nodes[index].right = child;
+        if let Some(child) = child {
+            self.nodes[child].parent = Some(index);
+        }
+    }
+}
+
+impl<T> Default for DecorationTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn shift(offset: usize, delta: isize) -> usize {
+    offset
+        .checked_add_signed(delta)
+        .expect("decoration shifted before the start of the buffer")
+}
+
+/// Moves one edge of a range through an edit, treated as a delete followed by
+/// an insert. An edge at the end of the deleted bytes stays with the text after
+/// them; the others collapse onto `offset`, where the inserted text pushes them
+/// according to their stickiness
+fn adjust(
+    edge: usize,
+    stickiness: Stickiness,
+    offset: usize,
+    deleted: usize,
+    inserted: usize,
+) -> usize {
+    if edge < offset {
+        edge
+    } else if edge > offset + deleted || (deleted > 0 && edge == offset + deleted) {
+        edge - deleted + inserted
+    } else if stickiness == Stickiness::Right {
+        offset + inserted
+    } else {
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_search_remove() {
+        let mut tree = DecorationTree::new();
+        let a = tree.add(
+            0,
+            5,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            "a",
+        );
+        let b = tree.add(
+            10,
+            20,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            "b",
+        );
+        let c = tree.add(
+            15,
+            16,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            "c",
+        );
+
+        assert_eq!(tree.search(4, 12), vec![(a, 0, 5), (b, 10, 20)]);
+        assert_eq!(tree.search(16, 30), vec![(b, 10, 20), (c, 15, 16)]);
+        assert!(tree.search(6, 9).is_empty());
+
+        assert_eq!(tree.remove(b), Some("b"));
+        assert_eq!(tree.search(0, 30), vec![(a, 0, 5), (c, 15, 16)]);
+        assert_eq!(tree.payload(c), Some(&"c"));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_stickiness_at_edges() {
+        let mut tree = DecorationTree::new();
+        let always = tree.add(
+            5,
+            10,
+            TrackedRangeStickiness::AlwaysGrowsWhenTypingAtEdges,
+            (),
+        );
+        let never = tree.add(
+            5,
+            10,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            (),
+        );
+        let before = tree.add(5, 10, TrackedRangeStickiness::GrowsOnlyWhenTypingBefore, ());
+        let after = tree.add(5, 10, TrackedRangeStickiness::GrowsOnlyWhenTypingAfter, ());
+
+        tree.apply_edit(5, 0, 2);
+        tree.apply_edit(12, 0, 3);
+
+        assert_eq!(tree.range(always), Some((5, 15)));
+        assert_eq!(tree.range(never), Some((7, 12)));
+        assert_eq!(tree.range(before), Some((5, 12)));
+        assert_eq!(tree.range(after), Some((7, 15)));
+    }
+
+    #[test]
+    fn test_replace_at_edges() {
+        let mut tree = DecorationTree::new();
+        let always = tree.add(
+            5,
+            10,
+            TrackedRangeStickiness::AlwaysGrowsWhenTypingAtEdges,
+            (),
+        );
+        let never = tree.add(
+            5,
+            10,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            (),
+        );
+        let following = tree.add(
+            10,
+            14,
+            TrackedRangeStickiness::AlwaysGrowsWhenTypingAtEdges,
+            (),
+        );
+
+        // Replacing the bytes before the start keeps the replacement outside
+        tree.apply_edit(3, 2, 4);
+        assert_eq!(tree.range(always), Some((7, 12)));
+        assert_eq!(tree.range(never), Some((7, 12)));
+
+        // Replacing the bytes after the end grows the range only when it
+        // grows at its end, and the range starting there keeps its stickiness
+        tree.apply_edit(12, 2, 3);
+        assert_eq!(tree.range(always), Some((7, 15)));
+        assert_eq!(tree.range(never), Some((7, 12)));
+        assert_eq!(tree.range(following), Some((12, 17)));
+
+        // Replacing the last bytes of a range keeps the replacement inside
+        tree.apply_edit(10, 2, 1);
+        assert_eq!(tree.range(never), Some((7, 11)));
+    }
+
+    #[test]
+    fn test_deletion_shrinks_and_shifts() {
+        let mut tree = DecorationTree::new();
+        let overlapping = tree.add(
+            2,
+            8,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            (),
+        );
+        let inside = tree.add(
+            4,
+            5,
+            TrackedRangeStickiness::AlwaysGrowsWhenTypingAtEdges,
+            (),
+        );
+        let later = tree.add(
+            20,
+            25,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            (),
+        );
+
+        tree.apply_edit(3, 4, 0);
+
+        assert_eq!(tree.range(overlapping), Some((2, 4)));
+        assert_eq!(tree.range(inside), Some((3, 3)));
+        assert_eq!(tree.range(later), Some((16, 21)));
+        assert_eq!(tree.search(16, 16).len(), 1);
+    }
+
+    #[test]
+    fn test_many_decorations_match_naive_model() {
+        let mut tree = DecorationTree::new();
+        let mut expected = Vec::new();
+
+        for i in 0..1000usize {
+            let start = (i * 37) % 2000;
+            let end = start + i % 30;
+            let stickiness = TrackedRangeStickiness::AlwaysGrowsWhenTypingAtEdges;
+            expected.push((tree.add(start, end, stickiness, i), start, end));
+        }
+
+        for step in 0..100usize {
+            let offset = (step * 71) % 1500;
+            let deleted = if step % 2 == 0 { step % 25 } else { 0 };
+            let inserted = step % 9;
+            tree.apply_edit(offset, deleted, inserted);
+
+            for (_, start, end) in expected.iter_mut() {
+                *start = adjust(*start, Stickiness::Left, offset, deleted, inserted);
+                *end = adjust(*end, Stickiness::Right, offset, deleted, inserted);
+            }
+        }
+
+        for &(id, start, end) in &expected {
+            assert_eq!(tree.range(id), Some((start, end)));
+        }
+
+        let by_start_and_payload =
+            |&(id, start, _): &(DecorationId, usize, usize)| (start, tree.payload(id).copied());
+        let mut hits: Vec<_> = expected
+            .into_iter()
+            .filter(|&(_, start, end)| start <= 900 && end >= 800)
+            .collect();
+        hits.sort_by_key(by_start_and_payload);
+        let mut found = tree.search(800, 900);
+        found.sort_by_key(by_start_and_payload);
+        assert_eq!(found, hits);
+    }
+}
@@ -0,0 +1,97 @@
+//! Event module for notifying listeners about buffer changes
+//!
+//! This module defines the change events emitted by TextBuffer after each
+//! insert, delete or batch of edits, and the registry holding the listeners.
+
+use std::fmt;
+
+use crate::types::Range;
+
+/// A single replacement applied to the buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentChange {
+    /// Range that was replaced, in positions before the change
+    pub range: Range,
+    /// Byte offset where the change starts
+    pub start_offset: usize,
+    /// Byte offset where the replaced text ended before the change
+    pub old_end_offset: usize,
+    /// Byte offset where the inserted text ends after the change
+    pub new_end_offset: usize,
+    /// Text inserted in place of the range
+    pub text: String,
+    /// Net change in the number of lines
+    pub line_delta: isize,
+}
+
+/// Event delivered to listeners after the buffer changed
+///
+/// Changes of a batch are listed in the order they were applied, from the
+/// end of the buffer towards the start, so each change's range is valid both
+/// before the batch and at the moment the change is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentChangeEvent {
+    /// Changes applied by the edit
+    pub changes: Vec<ContentChange>,
+    /// Version of the buffer after the edit
+    pub version_id: u64,
+}
+
+/// Identifier of a listener returned by `TextBuffer::subscribe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// Callback invoked with every change event
+pub type ChangeListener = Box<dyn FnMut(&ContentChangeEvent) + Send + Sync>;
+
+/// Registry of change listeners
+///
+/// Listeners belong to one buffer: cloning the registry yields an empty one.
+#[derive(Default)]
+pub(crate) struct Listeners {
+    listeners: Vec<(SubscriptionId, ChangeListener)>,
+    next_id: u64,
+}
+
+impl Listeners {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a listener
+    pub fn subscribe(&mut self, listener: ChangeListener) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.listeners.push((id, listener));
+        id
+    }
+
+    /// Removes a listener, returning true if it was registered
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let count = self.listeners.len();
+        self.listeners.retain(|(listener_id, _)| *listener_id != id);
+        self.listeners.len() != count
+    }
+
+    /// Delivers an event to every listener in subscription order
+    pub fn emit(&mut self, event: &ContentChangeEvent) {
+        for (_, listener) in self.listeners.iter_mut() {
+            listener(event);
+        }
+    }
+}
+
+impl Clone for Listeners {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Listeners")
+            .field("len", &self.listeners.len())
+            .finish()
+    }
+}
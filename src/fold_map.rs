@@ -0,0 +1,199 @@
+//! Fold map module for hiding line ranges from display
+//!
+//! This module defines the folds a TextBuffer keeps and the FoldMap, a view
+//! of the buffer lines with folded lines removed. A fold keeps its first line
+//! visible and hides the lines after it up to the line where it ends. Folds
+//! are stored like decorations so they follow edits; a FoldMap is computed
+//! from the folds at one version and answers display-line to buffer-line
+//! conversions by binary search.
+
+use crate::decoration::DecorationId;
+
+/// Identifier of a fold returned by `TextBuffer::fold`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FoldId(pub(crate) DecorationId);
+
+/// A run of consecutive hidden lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HiddenLines {
+    lines: std::ops::Range<usize>,
+    /// Number of lines hidden before this run
+    hidden_before: usize,
+}
+
+/// Buffer lines with folded lines removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoldMap {
+    /// Disjoint hidden runs, ordered by start
+    hidden: Vec<HiddenLines>,
+    line_count: usize,
+}
+
+impl FoldMap {
+    /// Builds the map from the line spans `(first_line, last_line)` of folds
+    pub fn new(line_count: usize, mut folds: Vec<(usize, usize)>) -> Self {
+        folds.sort_unstable();
+
+        let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+        for (first_line, last_line) in folds {
+            let range = first_line + 1..(last_line + 1).min(line_count);
+            if range.is_empty() {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => ranges.push(range),
+            }
+        }
+
+        let mut hidden_before = 0;
+        let hidden = ranges
+            .into_iter()
+            .map(|lines| {
+                let run = HiddenLines {
+                    hidden_before,
+                    lines,
+                };
+                hidden_before += run.lines.len();
+                run
+            })
+            .collect();
+
+        Self { hidden, line_count }
+    }
+
+    /// Returns the number of buffer lines
+    pub fn buffer_line_count(&self) -> usize {
+        self.line_count
+    }
+
+    /// Returns the number of visible lines
+    pub fn display_line_count(&self) -> usize {
+        self.line_count - self.hidden_line_count()
+    }
+
+    /// Returns true if a buffer line is inside a fold
+    pub fn is_hidden(&self, buffer_line: usize) -> bool {
+        let index = self
+            .hidden
+            .partition_point(|run| run.lines.end <= buffer_line);
+        self.hidden
+            .get(index)
+            .is_some_and(|run| run.lines.contains(&buffer_line))
+    }
+
+    /// Returns the display line showing a buffer line
+    ///
+    /// Hidden lines map to the display line of the fold hiding them.
+    pub fn to_display_line(&self, buffer_line: usize) -> Option<usize> {
+        if buffer_line >= self.line_count {
+            return None;
+        }
+
+        let index = self
+            .hidden
+            .partition_point(|run| run.lines.start <= buffer_line);
+        let run = match index {
+            0 => return Some(buffer_line),
+            _ => &self.hidden[index - 1],
+        };
+
+        if run.lines.contains(&buffer_line) {
+            Some(run.lines.start - 1 - run.hidden_before)
+        } else {
+            Some(buffer_line - run.hidden_before - run.lines.len())
+        }
+    }
+
+    /// Returns the buffer line shown on a display line
+    pub fn to_buffer_line(&self, display_line: usize) -> Option<usize> {
+        if display_line >= self.display_line_count() {
+            return None;
+        }
+
+        // Runs hidden before the display line
+        let index = self
+            .hidden
+            .partition_point(|run| run.lines.start - run.hidden_before <= display_line);
+        Some(match index {
+            0 => display_line,
+            _ => {
+                let run = &self.hidden[index - 1];
+                display_line + run.hidden_before + run.lines.len()
+            }
+        })
+    }
+
+    /// Returns an iterator over the visible buffer lines
+    pub fn visible_lines(&self) -> VisibleLines<'_> {
+        VisibleLines {
+            map: self,
+            line: 0,
+            next_hidden: 0,
+        }
+    }
+
+    fn hidden_line_count(&self) -> usize {
+        self.hidden
+            .last()
+            .map(|run| run.hidden_before + run.lines.len())
+            .unwrap_or(0)
+    }
+}
+
+/// Iterator over the visible buffer lines of a fold map
+#[derive(Debug, Clone)]
+pub struct VisibleLines<'a> {
+    map: &'a FoldMap,
+    /// Next candidate buffer line
+    line: usize,
+    /// Index of the first hidden range not yet skipped
+    next_hidden: usize,
+}
+
+impl Iterator for VisibleLines<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(run) = self.map.hidden.get(self.next_hidden) {
+            if run.lines.start == self.line {
+                self.line = run.lines.end;
+                self.next_hidden += 1;
+            }
+        }
+
+        if self.line >= self.map.line_count {
+            return None;
+        }
+        self.line += 1;
+        Some(self.line - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_map_conversions() {
+        // Lines 2..=4 and 7..=8 hidden, with a nested fold inside the first
+        let map = FoldMap::new(10, vec![(6, 8), (1, 4), (2, 3)]);
+
+        assert_eq!(map.display_line_count(), 5);
+        assert_eq!(map.visible_lines().collect::<Vec<_>>(), vec![0, 1, 5, 6, 9]);
+        assert!(map.is_hidden(3));
+        assert!(!map.is_hidden(5));
+
+        assert_eq!(map.to_display_line(1), Some(1));
+        assert_eq!(map.to_display_line(3), Some(1));
+        assert_eq!(map.to_display_line(5), Some(2));
+        assert_eq!(map.to_display_line(8), Some(3));
+        assert_eq!(map.to_display_line(9), Some(4));
+        assert_eq!(map.to_display_line(10), None);
+
+        for (display_line, buffer_line) in map.visible_lines().enumerate() {
+            assert_eq!(map.to_buffer_line(display_line), Some(buffer_line));
+        }
+        assert_eq!(map.to_buffer_line(5), None);
+    }
+}
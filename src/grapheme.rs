@@ -0,0 +1,170 @@
+//! Grapheme module for extended grapheme cluster boundaries
+//!
+//! This module finds the boundaries of user-perceived characters so cursors
+//! never land inside an emoji sequence or between a letter and its combining
+//! marks. Boundaries are found by feeding piece contents to a grapheme cursor
+//! one chunk at a time, without materializing lines.
+
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{Position, TextBufferResult};
+
+impl TextSnapshot {
+    /// Returns true if the byte offset falls on a grapheme boundary
+    pub fn is_grapheme_boundary(&self, offset: usize) -> TextBufferResult<bool> {
+        let length = self.length();
+        self.summary_at(offset)?;
+        if offset == 0 || offset == length {
+            return Ok(true);
+        }
+
+        let mut cursor = GraphemeCursor::new(offset, length, true);
+        let (chunk, chunk_start) = self.chunk_at(offset)?;
+        loop {
+            match cursor.is_boundary(chunk, chunk_start) {
+                Ok(is_boundary) => return Ok(is_boundary),
+                Err(incomplete) => self.provide_context(&mut cursor, incomplete)?,
+            }
+        }
+    }
+
+    /// Returns the first grapheme boundary after the byte offset, or the
+    /// length of the text when the offset is in the last grapheme
+    pub fn next_grapheme_boundary(&self, offset: usize) -> TextBufferResult<usize> {
+        let length = self.length();
+        self.summary_at(offset)?;
+        if offset == length {
+            return Ok(length);
+        }
+
+        let mut cursor = GraphemeCursor::new(offset, length, true);
+        let (mut chunk, mut chunk_start) = self.chunk_at(offset)?;
+        loop {
+            match cursor.next_boundary(chunk, chunk_start) {
+                Ok(boundary) => return Ok(boundary.unwrap_or(length)),
+                Err(GraphemeIncomplete::NextChunk) => {
+                    (chunk, chunk_start) = self.chunk_at(chunk_start + chunk.len())?;
+                }
+                Err(incomplete) => self.provide_context(&mut cursor, incomplete)?,
+            }
+        }
+    }
+
+    /// Returns the last grapheme boundary before the byte offset, or zero
+    /// when the offset is in the first grapheme
+    pub fn prev_grapheme_boundary(&self, offset: usize) -> TextBufferResult<usize> {
+        self.summary_at(offset)?;
+        if offset == 0 {
+            return Ok(0);
+        }
+
+        let mut cursor = GraphemeCursor::new(offset, self.length(), true);
+        let (mut chunk, mut chunk_start) = self.chunk_at(offset - 1)?;
+        loop {
+            match cursor.prev_boundary(chunk, chunk_start) {
+                Ok(boundary) => return Ok(boundary.unwrap_or(0)),
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    (chunk, chunk_start) = self.chunk_at(chunk_start - 1)?;
+                }
+                Err(incomplete) => self.provide_context(&mut cursor, incomplete)?,
+            }
+        }
+    }
+
+    /// Moves a position to the nearest grapheme boundary
+    ///
+    /// Positions already on a boundary are returned unchanged (after clamping
+    /// columns past the end of the line); ties go to the earlier boundary.
+    pub fn snap_to_grapheme(&self, position: Position) -> TextBufferResult<Position> {
+        let offset = self.position_to_offset(position)?;
+        if self.is_grapheme_boundary(offset)? {
+            return self.offset_to_position(offset);
+        }
+
+        let prev = self.prev_grapheme_boundary(offset)?;
+        let next = self.next_grapheme_boundary(offset)?;
+        let nearest = if offset - prev <= next - offset {
+            prev
+        } else {
+            next
+        };
+        self.offset_to_position(nearest)
+    }
+
+    /// Supplies the text preceding a chunk when the cursor needs it
+    fn provide_context(
+        &self,
+        cursor: &mut GraphemeCursor,
+        incomplete: GraphemeIncomplete,
+    ) -> TextBufferResult<()> {
+        match incomplete {
+            GraphemeIncomplete::PreContext(end) => {
+                let (chunk, chunk_start) = self.chunk_at(end - 1)?;
+                cursor.provide_context(&chunk[..end - chunk_start], chunk_start);
+                Ok(())
+            }
+            _ => Err("Failed to find grapheme boundary".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_buffer::TextBuffer;
+    use crate::types::Position;
+
+    #[test]
+    fn test_grapheme_boundaries() {
+        // Family emoji joined by ZWJs, then "e" with a combining accent
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let text = format!("a{family}e\u{301}\r\nb");
+        let buffer = TextBuffer::from_text(&text);
+        let after_family = 1 + family.len();
+
+        assert_eq!(buffer.next_grapheme_boundary(0).unwrap(), 1);
+        assert_eq!(buffer.next_grapheme_boundary(1).unwrap(), after_family);
+        assert_eq!(buffer.next_grapheme_boundary(5).unwrap(), after_family);
+        assert_eq!(buffer.prev_grapheme_boundary(after_family).unwrap(), 1);
+        assert_eq!(
+            buffer.prev_grapheme_boundary(after_family + 1).unwrap(),
+            after_family
+        );
+        assert!(!buffer.is_grapheme_boundary(after_family + 1).unwrap());
+
+        // CRLF is a single grapheme
+        let crlf = after_family + 3;
+        assert_eq!(buffer.next_grapheme_boundary(crlf).unwrap(), crlf + 2);
+        assert!(buffer.next_grapheme_boundary(2).is_err());
+    }
+
+    #[test]
+    fn test_boundaries_across_pieces() {
+        let mut buffer = TextBuffer::from_text("xe\u{301}y");
+        // Split the cluster across pieces: "xe" + "\u{300}" + "\u{301}y"
+        buffer.insert(Position::new(0, 2), "\u{300}").unwrap();
+
+        assert_eq!(buffer.next_grapheme_boundary(1).unwrap(), 6);
+        assert_eq!(buffer.prev_grapheme_boundary(6).unwrap(), 1);
+        assert!(!buffer.is_grapheme_boundary(4).unwrap());
+    }
+
+    #[test]
+    fn test_snap_to_grapheme() {
+        let buffer = TextBuffer::from_text("ae\u{301}\u{302}b");
+
+        // Columns are in chars: 2 and 3 are inside "e" + two accents
+        assert_eq!(
+            buffer.snap_to_grapheme(Position::new(0, 2)).unwrap(),
+            Position::new(0, 1)
+        );
+        assert_eq!(
+            buffer.snap_to_grapheme(Position::new(0, 3)).unwrap(),
+            Position::new(0, 4)
+        );
+        assert_eq!(
+            buffer.snap_to_grapheme(Position::new(0, 4)).unwrap(),
+            Position::new(0, 4)
+        );
+    }
+}
@@ -0,0 +1,564 @@
+//! Indentation module for indentation-based structure
+//!
+//! This module measures the indentation of lines and derives folding ranges
+//! from it, the way VS Code folds languages without a folding provider. The
+//! indentation of each line and the folding ranges are cached by the
+//! TextBuffer. Edits mark the lines they touch as unknown, and the next query
+//! rescans only from the block enclosing the first edited line to the end of
+//! that block.
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{EditOperation, Position, Range, TextBufferResult};
+
+/// Number of lines sampled when detecting the indentation style
+const DETECTION_SAMPLE_LINES: usize = 10_000;
+
+/// Largest indentation step considered by the detection
+const MAX_INDENT_SIZE: usize = 8;
+
+/// Character used to indent lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndentKind {
+    Tabs,
+    Spaces,
+}
+
+/// Indentation style of a text, as detected from its lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndentStyle {
+    pub kind: IndentKind,
+    /// Width of one indentation level in columns
+    pub size: usize,
+    /// How consistently the text follows the style, from 0.0 to 1.0
+    pub confidence: f32,
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        Self {
+            kind: IndentKind::Spaces,
+            size: 4,
+            confidence: 0.0,
+        }
+    }
+}
+
+/// A foldable region spanning the lines `start_line..=end_line`
+///
+/// The start line is the header that stays visible when folded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FoldingRange {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Cached indentation of a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineIndent {
+    /// Not measured since the line was last edited
+    Unknown,
+    /// Only whitespace
+    Blank,
+    /// Indentation width in columns
+    Level(usize),
+}
+
+/// Indentation of every line and the folding ranges for one tab size
+#[derive(Debug, Clone)]
+pub(crate) struct IndentCache {
+    tab_size: usize,
+    lines: Vec<LineIndent>,
+    /// Ranges of the last computation, sorted by start line, with the
+    /// `off_side` flag they were computed with
+    ranges: Option<(bool, Vec<FoldingRange>)>,
+    /// First and last line edited since the ranges were computed
+    dirty: Option<(usize, usize)>,
+}
+
+impl IndentCache {
+    /// Creates a cache with every line of the text unmeasured
+    pub fn new(line_count: usize, tab_size: usize) -> Self {
+        Self {
+            tab_size,
+            lines: vec![LineIndent::Unknown; line_count],
+            ranges: None,
+            dirty: None,
+        }
+    }
+
+    /// Returns the tab size the levels are measured with
+    pub fn tab_size(&self) -> usize {
+        self.tab_size
+    }
+
+    /// Replaces the lines `start_line..=old_end_line` by `new_line_count`
+    /// unmeasured lines
+    pub fn splice(&mut self, start_line: usize, old_end_line: usize, new_line_count: usize) {
+        let end = (old_end_line + 1).min(self.lines.len());
+        let start = start_line.min(end);
+        self.lines.splice(
+            start..end,
+            std::iter::repeat_n(LineIndent::Unknown, new_line_count),
+        );
+
+        // Lines after the replaced ones move; lines inside them map to the
+        // first or last new line
+        let last_new = (start + new_line_count).saturating_sub(1).max(start);
+        let map = |line: usize, inside: usize| match line {
+            line if line < start => line,
+            line if line >= end => line - end + start + new_line_count,
+            _ => inside,
+        };
+
+        if let Some((_, ranges)) = &mut self.ranges {
+            ranges.retain(|range| range.start_line < start || range.start_line >= end);
+            for range in ranges.iter_mut() {
+                range.start_line = map(range.start_line, start);
+                range.end_line = map(range.end_line, last_new);
+            }
+        }
+        self.dirty = Some(match self.dirty {
+            Some((first, last)) => (
+                map(first, start).min(start),
+                map(last, last_new).max(last_new),
+            ),
+            None => (start, last_new),
+        });
+    }
+
+    /// Computes the folding ranges
+    ///
+    /// In off-side languages (Python, YAML) blank lines at the end of a block
+    /// are left outside of it. After edits only the ranges starting between
+    /// the block enclosing the first edited line and the last edited line are
+    /// computed again.
+    pub fn folding_ranges(
+        &mut self,
+        text: &TextSnapshot,
+        off_side: bool,
+    ) -> TextBufferResult<Vec<FoldingRange>> {
+        let last_line = self.lines.len().saturating_sub(1);
+        match (&self.ranges, self.dirty) {
+            (Some((cached, _)), None) if *cached == off_side => {}
+            (Some((cached, _)), Some((first, last))) if *cached == off_side => {
+                let last = last.min(last_line);
+                let first = self.enclosing_block_start(text, first.min(last))?;
+                let computed = self.scan(text, first, last, off_side)?;
+
+                let (_, ranges) = self.ranges.as_mut().unwrap();
+                let from = ranges.partition_point(|range| range.start_line < first);
+                let to = ranges.partition_point(|range| range.start_line <= last);
+                ranges.splice(from..to, computed);
+            }
+            _ => {
+                let ranges = self.scan(text, 0, last_line, off_side)?;
+                self.ranges = Some((off_side, ranges));
+            }
+        }
+
+        self.dirty = None;
+        Ok(self
+            .ranges
+            .as_ref()
+            .map_or_else(Vec::new, |(_, ranges)| ranges.clone()))
+    }
+
+    /// Returns the indentation of a line, measuring it if unknown
+    fn level(&mut self, text: &TextSnapshot, line: usize) -> TextBufferResult<Option<usize>> {
+        if self.lines[line] == LineIndent::Unknown {
+            self.lines[line] = match indent_level(text, line, self.tab_size)? {
+                Some(level) => LineIndent::Level(level),
+                None => LineIndent::Blank,
+            };
+        }
+        Ok(match self.lines[line] {
+            LineIndent::Level(level) => Some(level),
+            _ => None,
+        })
+    }
+
+    /// Returns the first line of the outermost block open at a line
+    ///
+    /// A line above starts a block still open when every non-blank line
+    /// between them is indented further, so only those lines can start
+    /// ranges reaching the line.
+    fn enclosing_block_start(
+        &mut self,
+        text: &TextSnapshot,
+        line: usize,
+    ) -> TextBufferResult<usize> {
+        let mut start = line;
+        let mut min_level = usize::MAX;
+        for above in (0..line).rev() {
+            if min_level == 0 {
+                break;
+            }
+            if let Some(level) = self.level(text, above)? {
+                if level < min_level {
+                    min_level = level;
+                    start = above;
+                }
+            }
+        }
+        Ok(start)
+    }
+
+    /// Computes the ranges starting on the lines `first_line..=last_line`,
+    /// scanning down until all of them are closed
+    ///
+    /// A line starts a range when the next non-blank line is indented
+    /// further; the range ends before the next non-blank line indented no
+    /// further than it.
+    fn scan(
+        &mut self,
+        text: &TextSnapshot,
+        first_line: usize,
+        last_line: usize,
+        off_side: bool,
+    ) -> TextBufferResult<Vec<FoldingRange>> {
+        // Lines that may start a range, each with its level and whether a
+        // line indented further follows it
+        let mut open: Vec<(usize, usize, bool)> = Vec::new();
+        let mut ranges = Vec::new();
+        let mut last_non_blank = first_line;
+
+        let mut line = first_line;
+        while line < self.lines.len() && (line <= last_line || !open.is_empty()) {
+            if let Some(level) = self.level(text, line)? {
+                while let Some(&(start_line, open_level, nested)) = open.last() {
+                    if open_level < level {
+                        break;
+                    }
+                    open.pop();
+                    let end_line = if off_side { last_non_blank } else { line - 1 };
+                    if nested {
+                        ranges.push(FoldingRange {
+                            start_line,
+                            end_line,
+                        });
+                    }
+                }
+                if let Some(parent) = open.last_mut() {
+                    parent.2 = true;
+                }
+                if line <= last_line {
+                    open.push((line, level, false));
+                }
+                last_non_blank = line;
+            }
+            line += 1;
+        }
+
+        // Blocks still open run to the end of the text
+        let end_line = if off_side {
+            last_non_blank
+        } else {
+            self.lines.len().saturating_sub(1)
+        };
+        for (start_line, _, nested) in open {
+            if nested {
+                ranges.push(FoldingRange {
+                    start_line,
+                    end_line,
+                });
+            }
+        }
+
+        ranges.sort_unstable_by_key(|range| range.start_line);
+        Ok(ranges)
+    }
+}
+
+/// Detects the indentation style from the leading whitespace of the first
+/// lines
+///
+/// Lines indented with tabs vote for tabs and lines indented with spaces for
+/// spaces. The size is the most frequent change in indentation between
+/// consecutive space-indented lines.
+pub(crate) fn detect_indentation(text: &TextSnapshot) -> TextBufferResult<IndentStyle> {
+    let mut tab_lines = 0usize;
+    let mut space_lines = 0usize;
+    let mut deltas = [0usize; MAX_INDENT_SIZE + 1];
+    let mut previous_spaces: Option<usize> = None;
+
+    for line in 0..text.line_count().min(DETECTION_SAMPLE_LINES) {
+        let (whitespace, blank) = leading_whitespace(text, line)?;
+        if blank {
+            continue;
+        }
+
+        let tabs = whitespace.iter().filter(|&&ch| ch == '\t').count();
+        let spaces = whitespace.len() - tabs;
+        match (tabs, spaces) {
+            (0, 0) => {}
+            (_, 0) => tab_lines += 1,
+            (0, _) => space_lines += 1,
+            _ => {}
+        }
+
+        if tabs == 0 {
+            if let Some(previous) = previous_spaces {
+                let delta = previous.abs_diff(spaces);
+                if (1..=MAX_INDENT_SIZE).contains(&delta) {
+                    deltas[delta] += 1;
+                }
+            }
+            previous_spaces = Some(spaces);
+        } else {
+            previous_spaces = None;
+        }
+    }
+
+    let indented_lines = tab_lines + space_lines;
+    if indented_lines == 0 {
+        return Ok(IndentStyle::default());
+    }
+
+    // Most frequent step, preferring the smaller one on ties
+    let (size, &size_votes) = deltas
+        .iter()
+        .enumerate()
+        .skip(1)
+        .rev()
+        .max_by_key(|&(_, votes)| votes)
+        .unwrap_or((4, &0));
+    let total_deltas: usize = deltas.iter().sum();
+    let size = if size_votes == 0 {
+        IndentStyle::default().size
+    } else {
+        size
+    };
+
+    let kind_confidence = tab_lines.max(space_lines) as f32 / indented_lines as f32;
+    if tab_lines > space_lines {
+        return Ok(IndentStyle {
+            kind: IndentKind::Tabs,
+            size,
+            confidence: kind_confidence,
+        });
+    }
+
+    let size_confidence = match total_deltas {
+        0 => 0.0,
+        total => size_votes as f32 / total as f32,
+    };
+    Ok(IndentStyle {
+        kind: IndentKind::Spaces,
+        size,
+        confidence: kind_confidence * size_confidence,
+    })
+}
+
+/// Returns the edits rewriting the leading whitespace of the lines
+/// `start_line..=end_line` with the given kind
+pub(crate) fn convert_indentation(
+    text: &TextSnapshot,
+    start_line: usize,
+    end_line: usize,
+    kind: IndentKind,
+    tab_size: usize,
+) -> TextBufferResult<Vec<EditOperation>> {
+    let tab_size = tab_size.max(1);
+    let mut edits = Vec::new();
+
+    for line in start_line..=end_line {
+        let (whitespace, _) = leading_whitespace(text, line)?;
+        let width = whitespace.iter().fold(0, |width, &ch| match ch {
+            '\t' => width + tab_size - width % tab_size,
+            _ => width + 1,
+        });
+
+        let replacement = match kind {
+            IndentKind::Spaces => " ".repeat(width),
+            IndentKind::Tabs => "\t".repeat(width / tab_size) + &" ".repeat(width % tab_size),
+        };
+        if replacement.chars().eq(whitespace.iter().copied()) {
+            continue;
+        }
+
+        // Leading whitespace is ASCII, so its length is the same in every
+        // column unit
+        let range = Range::new(
+            Position::new(line, 0),
+            Position::new(line, whitespace.len()),
+        );
+        edits.push(EditOperation::new(range, replacement));
+    }
+
+    Ok(edits)
+}
+
+/// Returns the spaces and tabs starting a line, and whether the line holds
+/// nothing else
+pub(crate) fn leading_whitespace(
+    text: &TextSnapshot,
+    line: usize,
+) -> TextBufferResult<(Vec<char>, bool)> {
+    let (start, end) = text.line_bounds(line)?;
+    let mut whitespace = Vec::new();
+    for (_, ch) in text.chars_in(start.bytes, end.bytes) {
+        match ch {
+            ' ' | '\t' => whitespace.push(ch),
+            '\r' => {}
+            _ => return Ok((whitespace, false)),
+        }
+    }
+    Ok((whitespace, true))
+}
+
+/// Returns the indentation width of a line in columns, or None if the line
+/// holds only whitespace
+pub(crate) fn indent_level(
+    text: &TextSnapshot,
+    line: usize,
+    tab_size: usize,
+) -> TextBufferResult<Option<usize>> {
+    let tab_size = tab_size.max(1);
+    let (start, end) = text.line_bounds(line)?;
+    let mut level = 0;
+    for (_, ch) in text.chars_in(start.bytes, end.bytes) {
+        match ch {
+            ' ' => level += 1,
+            '\t' => level += tab_size - level % tab_size,
+            '\r' => {}
+            _ => return Ok(Some(level)),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+
+    fn ranges(pairs: &[(usize, usize)]) -> Vec<FoldingRange> {
+        pairs
+            .iter()
+            .map(|&(start_line, end_line)| FoldingRange {
+                start_line,
+                end_line,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_folding_ranges_by_indent() {
+        let text = "fn a() {\n    if x {\n\t\ty();\n    }\n\n}\nfn b() {\n}\n";
+        let buffer = TextBuffer::from_text(text);
+
+        assert_eq!(
+            buffer.folding_ranges_by_indent(4).unwrap(),
+            ranges(&[(0, 4), (1, 2)])
+        );
+    }
+
+    #[test]
+    fn test_off_side_blank_lines() {
+        let text = "def a():\n    x = 1\n\n    if x:\n        y()\n\n\ndef b():\n    pass\n";
+        let buffer = TextBuffer::from_text(text);
+
+        assert_eq!(
+            buffer.folding_ranges_by_indent_off_side(4).unwrap(),
+            ranges(&[(0, 4), (3, 4), (7, 8)])
+        );
+        assert_eq!(
+            buffer.folding_ranges_by_indent(4).unwrap(),
+            ranges(&[(0, 6), (3, 6), (7, 9)])
+        );
+    }
+
+    #[test]
+    fn test_detect_indentation() {
+        let spaces = "a {\n  b {\n    c\n  }\n\n  d\n}\n";
+        let style = TextBuffer::from_text(spaces).detect_indentation().unwrap();
+        assert_eq!((style.kind, style.size), (IndentKind::Spaces, 2));
+        assert!(style.confidence > 0.9);
+
+        let tabs = "a {\n\tb {\n\t\tc\n\t}\n    odd\n}\n";
+        let mut builder = crate::text_buffer::TextBufferBuilder::new();
+        builder.accept_chunk(tabs).detect_indentation(true);
+        let style = builder.build().indent_style().unwrap();
+        assert_eq!(style.kind, IndentKind::Tabs);
+        assert_eq!(style.confidence, 0.75);
+
+        let flat = TextBuffer::from_text("a\nb\n")
+            .detect_indentation()
+            .unwrap();
+        assert_eq!(flat, IndentStyle::default());
+    }
+
+    #[test]
+    fn test_convert_indentation() {
+        let mut buffer = TextBuffer::from_text("a\n\tb\n  \t c\n     d\n");
+        let range = Range::new(Position::new(1, 0), Position::new(2, 3));
+
+        let undo = buffer.indentation_to_spaces(range, 4).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\n    b\n     c\n     d\n");
+        assert_eq!(buffer.version(), 1);
+        buffer.apply_edits(undo).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\n\tb\n  \t c\n     d\n");
+
+        let whole = Range::new(Position::new(0, 0), Position::new(3, 0));
+        buffer.indentation_to_tabs(whole, 4).unwrap();
+        assert_eq!(buffer.get_all_text(), "a\n\tb\n\t c\n\t d\n");
+        assert!(buffer.indentation_to_tabs(whole, 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ranges_follow_edits() {
+        let mut buffer = TextBuffer::from_text("a:\n  b\nc:\n  d\n");
+        assert_eq!(
+            buffer.folding_ranges_by_indent_off_side(2).unwrap(),
+            ranges(&[(0, 1), (2, 3)])
+        );
+
+        buffer.insert(Position::new(1, 3), "\n  e\n  f").unwrap();
+        buffer.insert(Position::new(4, 0), "  ").unwrap();
+        let expected = TextBuffer::from_text(&buffer.get_all_text())
+            .folding_ranges_by_indent_off_side(2)
+            .unwrap();
+        assert_eq!(
+            buffer.folding_ranges_by_indent_off_side(2).unwrap(),
+            expected
+        );
+        assert_eq!(expected, ranges(&[(0, 5)]));
+    }
+
+    #[test]
+    fn test_cached_ranges_match_full_computation() {
+        let text = "mod a {\n    fn b() {\n        c();\n\n    }\n}\n\nfn d() {\n    e();\n}\n";
+        let edits = [
+            (Position::new(2, 0), "    "),
+            (Position::new(3, 0), "x\n"),
+            (Position::new(8, 0), "        f();\n            g();\n"),
+            (Position::new(0, 0), "\n\n"),
+            (Position::new(4, 4), "}\n"),
+        ];
+
+        for off_side in [false, true] {
+            let compute = |buffer: &TextBuffer| match off_side {
+                true => buffer.folding_ranges_by_indent_off_side(4).unwrap(),
+                false => buffer.folding_ranges_by_indent(4).unwrap(),
+            };
+            let mut buffer = TextBuffer::from_text(text);
+            compute(&buffer);
+            let check = |buffer: &mut TextBuffer| {
+                let expected = compute(&TextBuffer::from_text(&buffer.get_all_text()));
+                assert_eq!(compute(buffer), expected);
+            };
+
+            for (position, text) in edits {
+                buffer.insert(position, text).unwrap();
+                // Two edits between queries merge their dirty lines
+                let last_line = buffer.line_count() - 1;
+                buffer.insert(Position::new(last_line, 0), "  ").unwrap();
+                check(&mut buffer);
+            }
+
+            let range = Range::new(Position::new(1, 0), Position::new(6, 0));
+            buffer.delete(range).unwrap();
+            check(&mut buffer);
+        }
+    }
+}
@@ -6,17 +6,55 @@
 //! The piece tree provides efficient text editing operations with O(log n) complexity
 //! for insertions, deletions, and lookups while maintaining low memory overhead.
 
+mod block;
+mod bracket;
+mod bracket_index;
 mod buffer;
+mod cache;
+mod change_log;
+mod cursor;
+mod decoration;
+mod event;
+mod fold_map;
+mod grapheme;
+mod indentation;
+mod marker;
 mod piece;
 mod red_black_tree;
+mod selection;
+mod snapshot;
+mod summary;
 mod text_buffer;
+mod transform;
 mod types;
+mod visual;
+mod whitespace;
+mod word;
+mod wrap_map;
 
+pub use bracket::{BracketConfig, BracketStates};
+pub use bracket_index::{BracketIndex, BracketInfo};
 pub use buffer::Buffer;
+pub use cursor::Cursor;
+pub use decoration::{
+    Decoration, DecorationId, DecorationPayload, DecorationTree, TrackedRangeStickiness,
+};
+pub use event::{ChangeListener, ContentChange, ContentChangeEvent, SubscriptionId};
+pub use fold_map::{FoldId, FoldMap, VisibleLines};
+pub use indentation::{FoldingRange, IndentKind, IndentStyle};
+pub use marker::{MarkerId, MarkerTree, Stickiness};
 pub use piece::{Piece, PiecePosition, PieceType};
-pub use red_black_tree::{Color, RBNode, RBTree};
+pub use red_black_tree::{Color, Pieces, RBNode, RBTree, Seek};
+pub use selection::{Selection, SelectionSet};
+pub use snapshot::{Chunks, Lines, TextSnapshot};
+pub use summary::{Bytes, Chars, LineBreaks, Metric, Summary, TextSummary, Utf16};
 pub use text_buffer::{TextBuffer, TextBufferBuilder};
-pub use types::{Position, Range, TextBufferResult};
+pub use transform::Transform;
+pub use types::{ColumnUnit, EditOperation, Position, Range, TextBufferResult};
+pub use wrap_map::WrapMap;
+
+/// Line-oriented editing commands over a TextBuffer
+pub mod commands;
 
 /// Utility functions for working with pieces and text content
 pub mod utils {
@@ -0,0 +1,517 @@
+//! Marker module for offsets that move with edits
+//!
+//! This module implements the MarkerTree which keeps markers (cursors,
+//! breakpoints, bookmarks) in sync with insertions and deletions. Markers are
+//! stored in two treaps ordered by offset, one per stickiness, whose nodes
+//! carry lazy shift tags. An edit splits the affected treap at the edit
+//! offset, tags the split-off part and merges it back, so it only touches
+//! O(log n) nodes no matter how many markers follow the edit.
+
+/// Identifier of a marker returned by `TextBuffer::add_marker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarkerId {
+    index: usize,
+    generation: u32,
+}
+
+/// Which side of an insertion at a marker's offset the marker stays on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stickiness {
+    /// The marker sticks to the previous character and stays before text
+    /// inserted at its offset
+    Left,
+    /// The marker sticks to the next character and moves after text
+    /// inserted at its offset
+    Right,
+}
+
+/// Pending offset update for every node below a treap node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Shift {
+    /// Offset all markers collapse to before `delta` is added
+    assign: Option<usize>,
+    /// Signed distance added after `assign`
+    delta: isize,
+}
+
+impl Shift {
+    const IDENTITY: Shift = Shift {
+        assign: None,
+        delta: 0,
+    };
+
+    fn by(delta: isize) -> Self {
+        Self {
+            assign: None,
+            delta,
+        }
+    }
+
+    fn to(offset: usize) -> Self {
+        Self {
+            assign: Some(offset),
+            delta: 0,
+        }
+    }
+
+    fn apply(&self, offset: usize) -> usize {
+        let base = self.assign.unwrap_or(offset);
+        base.checked_add_signed(self.delta)
+            .expect("marker shifted before the start of the buffer")
+    }
+
+    /// Returns the shift equivalent to applying `self` and then `later`
+    fn then(&self, later: Shift) -> Shift {
+        match later.assign {
+            Some(_) => later,
+            None => Shift {
+                assign: self.assign,
+                delta: self.delta + later.delta,
+            },
+        }
+    }
+}
+
+/// A marker node in one of the treaps
+#[derive(Debug, Clone)]
+struct MarkerNode {
+    /// Offset of the marker once the tags of all ancestors are applied
+    offset: usize,
+    /// Shift not yet pushed down to the children
+    pending: Shift,
+    priority: u32,
+    stickiness: Stickiness,
+    generation: u32,
+    live: bool,
+    parent: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// Collection of markers that follow edits of a text buffer
+#[derive(Debug, Clone)]
+pub struct MarkerTree {
+    /// Arena holding every node; freed slots are reused
+    nodes: Vec<MarkerNode>,
+    /// Indices of freed slots in `nodes`
+    free: Vec<usize>,
+    /// Root of the left stickiness treap
+    left_root: Option<usize>,
+    /// Root of the right stickiness treap
+    right_root: Option<usize>,
+    /// Number of live markers
+    len: usize,
+    /// State of the priority generator
+    seed: u32,
+}
+
+impl MarkerTree {
+    /// Creates an empty marker tree
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            left_root: None,
+            right_root: None,
+            len: 0,
+            seed: 0x9e37_79b9,
+        }
+    }
+
+    /// Returns the number of markers
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no markers
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a marker at the given offset
+    pub fn add(&mut self, offset: usize, stickiness: Stickiness) -> MarkerId {
+        let priority = self.next_priority();
+        let node = MarkerNode {
+            offset,
+            pending: Shift::IDENTITY,
+            priority,
+            stickiness,
+            generation: 0,
+            live: true,
+            parent: None,
+            left: None,
+            right: None,
+        };
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                let generation = self.nodes[index].generation;
+                self.nodes[index] = MarkerNode { generation, ..node };
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        let root = self.root(stickiness);
+        let (before, after) = self.split(root, offset, false);
+        let root = self.merge(before, Some(index));
+        let root = self.merge(root, after);
+        self.set_root(stickiness, root);
+        self.len += 1;
+
+        MarkerId {
+            index,
+            generation: self.nodes[index].generation,
+        }
+    }
+
+    /// Returns the current offset of a marker, or None if it was removed
+    pub fn offset(&self, id: MarkerId) -> Option<usize> {
+        let node = self.node(id)?;
+        let mut offset = node.offset;
+        let mut parent = node.parent;
+
+        // Tags closer to the node are older, so apply them first
+        while let Some(index) = parent {
+            offset = self.nodes[index].pending.apply(offset);
+            parent = self.nodes[index].parent;
+        }
+
+        Some(offset)
+    }
+
+    /// Returns the stickiness of a marker, or None if it was removed
+    pub fn stickiness(&self, id: MarkerId) -> Option<Stickiness> {
+        self.node(id).map(|node| node.stickiness)
+    }
+
+    /// Removes a marker, returning its last offset
+    pub fn remove(&mut self, id: MarkerId) -> Option<usize> {
+        self.node(id)?;
+        let index = id.index;
+
+        // Push every pending tag down to the node and its children
+        let mut path = Vec::new();
+        let mut current = Some(index);
+        while let Some(i) = current {
+            path.push(i);
+            current = self.nodes[i].parent;
+        }
+        for &i in path.iter().rev() {
+            self.push_down(i);
+        }
+
+        let node = &self.nodes[index];
+        let (offset, stickiness, parent) = (node.offset, node.stickiness, node.parent);
+        let (left, right) = (node.left, node.right);
+        let merged = self.merge(left, right);
+
+        match parent {
+            Some(parent) => {
+                if self.nodes[parent].left == Some(index) {
+                    self.nodes[parent].left = merged;
+                } else {
+                    self.nodes[parent].right = merged;
+                }
+                if let Some(child) = merged {
+                    self.nodes[child].parent = Some(parent);
+                }
+            }
+            None => {
+                if let Some(child) = merged {
+                    self.nodes[child].parent = None;
+                }
+                self.set_root(stickiness, merged);
+            }
+        }
+
+        let node = &mut self.nodes[index];
+        node.live = false;
+        node.generation = node.generation.wrapping_add(1);
+        node.parent = None;
+        node.left = None;
+        node.right = None;
+        self.free.push(index);
+        self.len -= 1;
+
+        Some(offset)
+    }
+
+    /// Shifts markers after an insertion of `length` bytes at `offset`
+    pub fn apply_insert(&mut self, offset: usize, length: usize) {
+        if length == 0 {
+            return;
+        }
+
+        for stickiness in [Stickiness::Left, Stickiness::Right] {
+            // Left sticky markers at the offset stay where they are
+            let inclusive = stickiness == Stickiness::Left;
+            let root = self.root(stickiness);
+            let (before, after) = self.split(root, offset, inclusive);
+            self.apply_shift(after, Shift::by(length as isize));
+            let root = self.merge(before, after);
+            self.set_root(stickiness, root);
+        }
+    }
+
+    /// Moves markers after a deletion of the bytes in `start..end`
+    ///
+    /// Markers inside the deleted range collapse onto `start`.
+    pub fn apply_delete(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+
+        for stickiness in [Stickiness::Left, Stickiness::Right] {
+            let root = self.root(stickiness);
+            let (before, rest) = self.split(root, start, false);
+            let (inside, after) = self.split(rest, end, true);
+            self.apply_shift(inside, Shift::to(start));
+            self.apply_shift(after, Shift::by(-((end - start) as isize)));
+            let root = self.merge(before, inside);
+            let root = self.merge(root, after);
+            self.set_root(stickiness, root);
+        }
+    }
+
+    fn node(&self, id: MarkerId) -> Option<&MarkerNode> {
+        self.nodes
+            .get(id.index)
+            .filter(|node| node.live && node.generation == id.generation)
+    }
+
+    fn root(&self, stickiness: Stickiness) -> Option<usize> {
+        match stickiness {
+            Stickiness::Left => self.left_root,
+            Stickiness::Right => self.right_root,
+        }
+    }
+
+    fn set_root(&mut self, stickiness: Stickiness, root: Option<usize>) {
+        if let Some(index) = root {
+            self.nodes[index].parent = None;
+        }
+        match stickiness {
+            Stickiness::Left => self.left_root = root,
+            Stickiness::Right => self.right_root = root,
+        }
+    }
+
+    /// Xorshift generator for treap priorities
+    fn next_priority(&mut self) -> u32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    /// Applies a shift to a whole subtree
+    fn apply_shift(&mut self, root: Option<usize>, shift: Shift) {
+        if let Some(index) = root {
+            let node = &mut self.nodes[index];
+            node.offset = shift.apply(node.offset);
+            node.pending = node.pending.then(shift);
+        }
+    }
+
+    /// Pushes the pending shift of a node down to its children
+    fn push_down(&mut self, index: usize) {
+        let pending = self.nodes[index].pending;
+        if pending == Shift::IDENTITY {
+            return;
+        }
+        let (left, right) = (self.nodes[index].left, self.nodes[index].right);
+        self.apply_shift(left, pending);
+        self.apply_shift(right, pending);
+        self.nodes[index].pending = Shift::IDENTITY;
+    }
+
+    /// Splits a treap into markers before `offset` and the rest; markers at
+    /// `offset` go to the first part when `inclusive` is set
+    fn split(
+        &mut self,
+        root: Option<usize>,
+        offset: usize,
+        inclusive: bool,
+    ) -> (Option<usize>, Option<usize>) {
+        let index = match root {
+            Some(index) => index,
+            None => return (None, None),
+        };
+        self.push_down(index);
+
+        let node_offset = self.nodes[index].offset;
+        let goes_left = node_offset < offset || (inclusive && node_offset == offset);
+
+        if goes_left {
+            let right = self.nodes[index].right;
+            let (middle, rest) = self.split(right, offset, inclusive);
+            self.set_right(index, middle);
+            (Some(index), rest)
+        } else {
+            let left = self.nodes[index].left;
+            let (rest, middle) = self.split(left, offset, inclusive);
+            self.set_left(index, middle);
+            (rest, Some(index))
+        }
+    }
+
+    /// Merges two treaps where every marker of `left` precedes those of `right`
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        let (l, r) = match (left, right) {
+            (None, tree) | (tree, None) => return tree,
+            (Some(l), Some(r)) => (l, r),
+        };
+
+        if self.nodes[l].priority > self.nodes[r].priority {
+            self.push_down(l);
+            let merged = self.merge(self.nodes[l].right, Some(r));
+            self.set_right(l, merged);
+            Some(l)
+        } else {
+            self.push_down(r);
+            let merged = self.merge(Some(l), self.nodes[r].left);
+            self.set_left(r, merged);
+            Some(r)
+        }
+    }
+
+    fn set_left(&mut self, index: usize, child: Option<usize>) {
+        self.nodes[index].left = child;
+        if let Some(child) = child {
+            self.nodes[child].parent = Some(index);
+        }
+    }
+
+    fn set_right(&mut self, index: usize, child: Option<usize>) {
+        self.nodes[index].right = child;
+        if let Some(child) = child {
+            self.nodes[child].parent = Some(index);
+        }
+    }
+}
+
+impl Default for MarkerTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut markers = MarkerTree::new();
+        let a = markers.add(5, Stickiness::Left);
+        let b = markers.add(2, Stickiness::Right);
+
+        assert_eq!(markers.len(), 2);
+        assert_eq!(markers.offset(a), Some(5));
+        assert_eq!(markers.offset(b), Some(2));
+
+        assert_eq!(markers.remove(a), Some(5));
+        assert_eq!(markers.offset(a), None);
+        assert_eq!(markers.remove(a), None);
+
+        // A reused slot must not resurrect the stale id
+        let c = markers.add(7, Stickiness::Left);
+        assert_eq!(markers.offset(a), None);
+        assert_eq!(markers.offset(c), Some(7));
+        assert_eq!(markers.len(), 2);
+    }
+
+    #[test]
+    fn test_insert_respects_stickiness() {
+        let mut markers = MarkerTree::new();
+        let before = markers.add(2, Stickiness::Right);
+        let left = markers.add(5, Stickiness::Left);
+        let right = markers.add(5, Stickiness::Right);
+        let after = markers.add(8, Stickiness::Left);
+
+        markers.apply_insert(5, 3);
+
+        assert_eq!(markers.offset(before), Some(2));
+        assert_eq!(markers.offset(left), Some(5));
+        assert_eq!(markers.offset(right), Some(8));
+        assert_eq!(markers.offset(after), Some(11));
+    }
+
+    #[test]
+    fn test_delete_collapses_markers() {
+        let mut markers = MarkerTree::new();
+        let before = markers.add(1, Stickiness::Left);
+        let inside = markers.add(4, Stickiness::Right);
+        let at_end = markers.add(6, Stickiness::Left);
+        let after = markers.add(9, Stickiness::Right);
+
+        markers.apply_delete(2, 6);
+
+        assert_eq!(markers.offset(before), Some(1));
+        assert_eq!(markers.offset(inside), Some(2));
+        assert_eq!(markers.offset(at_end), Some(2));
+        assert_eq!(markers.offset(after), Some(5));
+
+        // Collapsed markers keep following later edits
+        markers.apply_insert(2, 10);
+        assert_eq!(markers.offset(inside), Some(12));
+        assert_eq!(markers.offset(at_end), Some(2));
+        assert_eq!(markers.offset(after), Some(15));
+    }
+
+    #[test]
+    fn test_many_markers_match_naive_model() {
+        let mut markers = MarkerTree::new();
+        let mut expected = Vec::new();
+
+        for i in 0..2000 {
+            let stickiness = if i % 2 == 0 {
+                Stickiness::Left
+            } else {
+                Stickiness::Right
+            };
+            let offset = (i * 37) % 1000;
+            expected.push((markers.add(offset, stickiness), offset, stickiness));
+        }
+
+        for step in 0..200usize {
+            let offset = (step * 53) % 900;
+            if step % 3 == 0 {
+                let end = offset + step % 40;
+                markers.apply_delete(offset, end);
+                for (_, marker_offset, _) in expected.iter_mut() {
+                    if *marker_offset > end {
+                        *marker_offset -= end - offset;
+                    } else if *marker_offset > offset {
+                        *marker_offset = offset;
+                    }
+                }
+            } else {
+                let length = step % 7 + 1;
+                markers.apply_insert(offset, length);
+                for (_, marker_offset, stickiness) in expected.iter_mut() {
+                    let moves = *marker_offset > offset
+                        || (*marker_offset == offset && *stickiness == Stickiness::Right);
+                    if moves {
+                        *marker_offset += length;
+                    }
+                }
+            }
+
+            if step % 50 == 0 {
+                let (id, offset, _) = expected.remove(step);
+                assert_eq!(markers.remove(id), Some(offset));
+            }
+        }
+
+        for (id, offset, _) in expected {
+            assert_eq!(markers.offset(id), Some(offset));
+        }
+    }
+}
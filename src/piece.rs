@@ -163,17 +163,17 @@ pub mod utils {
     }
 
     /// Gets the text content of a piece from the appropriate buffer
-    pub fn get_piece_content<'a>(
+    pub fn get_piece_content<'a, B: AsRef<Buffer>>(
         piece: &Piece,
-        original_buffers: &'a [Buffer],
-        added_buffers: &'a [Buffer],
+        original_buffers: &'a [B],
+        added_buffers: &'a [B],
     ) -> Option<&'a str> {
         let buffers = match piece.piece_type {
             PieceType::Original => original_buffers,
             PieceType::Added => added_buffers,
         };
 
-        let buffer = buffers.get(piece.buffer_index)?;
+        let buffer = buffers.get(piece.buffer_index)?.as_ref();
         if piece.start + piece.length <= buffer.len() {
             Some(buffer.slice(piece.start, piece.start + piece.length))
         } else {
@@ -182,11 +182,11 @@ pub mod utils {
     }
 
     /// Finds the line and column position within a piece at a given byte offset
-    pub fn position_in_piece(
+    pub fn position_in_piece<B: AsRef<Buffer>>(
         piece: &Piece,
         offset: usize,
-        original_buffers: &[Buffer],
-        added_buffers: &[Buffer],
+        original_buffers: &[B],
+        added_buffers: &[B],
     ) -> Option<(usize, usize)> {
         // Allow offset equal to piece length (end of piece)
         if offset > piece.length {
@@ -215,12 +215,12 @@ pub mod utils {
     }
 
     /// Finds the byte offset within a piece at a given line and column
-    pub fn offset_in_piece(
+    pub fn offset_in_piece<B: AsRef<Buffer>>(
         piece: &Piece,
         line: usize,
         column: usize,
-        original_buffers: &[Buffer],
-        added_buffers: &[Buffer],
+        original_buffers: &[B],
+        added_buffers: &[B],
     ) -> Option<usize> {
         let content = get_piece_content(piece, original_buffers, added_buffers)?;
 
@@ -1,9 +1,19 @@
 //! Red-Black Tree implementation for the piece tree
 //!
-//! This module implements a Red-Black Tree that stores pieces with additional
-//! metadata for efficient line-based and offset-based lookups.
+//! This module implements a Red-Black Tree that stores pieces together with
+//! a summary of each subtree. The summary type is pluggable (see the
+//! `summary` module), so the tree can be searched by bytes, line breaks or
+//! any other metric in O(log n). The tree of a TextBuffer is an
+//! `RBTree<TextSummary>`; other summaries are for trees built directly.
+//!
+//! The tree is persistent: nodes are shared through `Arc` and an edit copies
+//! only the O(log n) nodes on the paths it touches, so cloning a tree is O(1)
+//! and older versions stay intact.
+
+use std::sync::Arc;
 
 use crate::piece::Piece;
+use crate::summary::{Bytes, LineBreaks, Metric, Summary, TextSummary};
 
 /// Color of a Red-Black Tree node
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,39 +22,52 @@ pub enum Color {
     Black,
 }
 
+impl Color {
+    /// Returns the opposite color
+    fn flipped(self) -> Self {
+        match self {
+            Color::Red => Color::Black,
+            Color::Black => Color::Red,
+        }
+    }
+}
+
 /// A node in the Red-Black Tree
 #[derive(Debug, Clone)]
-pub struct RBNode {
+pub struct RBNode<S: Summary = TextSummary> {
     /// The piece stored in this node
     pub piece: Piece,
     /// Color of the node
     pub color: Color,
-    /// Total length of text in the left subtree
-    pub left_subtree_length: usize,
-    /// Total number of line breaks in the left subtree
-    pub left_subtree_line_breaks: usize,
-    /// Left child
-    pub left: Option<Box<RBNode>>,
-    /// Right child
-    pub right: Option<Box<RBNode>>,
+    /// Summary of the piece's text
+    pub summary: S,
+    /// Summary of the left subtree
+    pub left_summary: S,
+    /// Summary of this node and both subtrees
+    pub subtree_summary: S,
+    /// Left child, shared with other versions of the tree
+    pub left: Option<Arc<RBNode<S>>>,
+    /// Right child, shared with other versions of the tree
+    pub right: Option<Arc<RBNode<S>>>,
 }
 
-impl RBNode {
+impl<S: Summary> RBNode<S> {
     /// Creates a new Red-Black Tree node
-    pub fn new(piece: Piece) -> Self {
+    pub fn new(piece: Piece, summary: S) -> Self {
         Self {
             piece,
             color: Color::Red, // New nodes are always red
-            left_subtree_length: 0,
-            left_subtree_line_breaks: 0,
+            left_summary: S::default(),
+            subtree_summary: summary.clone(),
+            summary,
             left: None,
             right: None,
         }
     }
 
     /// Creates a new black node (used for root)
-    pub fn new_black(piece: Piece) -> Self {
-        let mut node = Self::new(piece);
+    pub fn new_black(piece: Piece, summary: S) -> Self {
+        let mut node = Self::new(piece, summary);
         node.color = Color::Black;
         node
     }
@@ -59,68 +82,79 @@ impl RBNode {
         self.color == Color::Black
     }
 
-    /// Updates the cached metadata for this node
+    /// Updates the cached summaries from the children
     pub fn update_metadata(&mut self) {
-        self.left_subtree_length = self
+        self.left_summary = self
             .left
             .as_ref()
-            .map(|node| node.left_subtree_length + node.piece.length + node.right_subtree_length())
-            .unwrap_or(0);
+            .map(|node| node.subtree_summary.clone())
+            .unwrap_or_default();
 
-        self.left_subtree_line_breaks = self
-            .left
-            .as_ref()
-            .map(|node| {
-                node.left_subtree_line_breaks
-                    + node.piece.line_break_count
-                    + node.right_subtree_line_breaks()
-            })
-            .unwrap_or(0);
-    }
-
-    /// Returns the total length of the right subtree
-    pub fn right_subtree_length(&self) -> usize {
-        self.right
-            .as_ref()
-            .map(|node| node.left_subtree_length + node.piece.length + node.right_subtree_length())
-            .unwrap_or(0)
+        let mut subtree_summary = self.left_summary.clone();
+        subtree_summary.add_summary(&self.summary);
+        if let Some(right) = &self.right {
+            subtree_summary.add_summary(&right.subtree_summary);
+        }
+        self.subtree_summary = subtree_summary;
     }
 
-    /// Returns the total line breaks in the right subtree
-    pub fn right_subtree_line_breaks(&self) -> usize {
-        self.right
-            .as_ref()
-            .map(|node| {
-                node.left_subtree_line_breaks
-                    + node.piece.line_break_count
-                    + node.right_subtree_line_breaks()
-            })
-            .unwrap_or(0)
+    /// Returns the size of the left subtree in the given metric
+    pub fn left_measure<M: Metric<S>>(&self) -> usize {
+        M::measure(&self.left_summary)
     }
 
     /// Returns the total length of this node and its subtrees
-    pub fn total_length(&self) -> usize {
-        self.left_subtree_length + self.piece.length + self.right_subtree_length()
+    pub fn total_length(&self) -> usize
+    where
+        Bytes: Metric<S>,
+    {
+        Bytes::measure(&self.subtree_summary)
     }
 
     /// Returns the total line breaks of this node and its subtrees
-    pub fn total_line_breaks(&self) -> usize {
-        self.left_subtree_line_breaks
-            + self.piece.line_break_count
-            + self.right_subtree_line_breaks()
+    pub fn total_line_breaks(&self) -> usize
+    where
+        LineBreaks: Metric<S>,
+    {
+        LineBreaks::measure(&self.subtree_summary)
+    }
+}
+
+/// Result of seeking a piece by a metric
+#[derive(Debug, Clone)]
+pub struct Seek<'a, S: Summary = TextSummary> {
+    /// The piece containing the sought unit
+    pub piece: &'a Piece,
+    /// Summary of the piece's text
+    pub summary: &'a S,
+    /// Summary of all the text before the piece
+    pub prefix: S,
+}
+
+impl<S: Summary> Seek<'_, S> {
+    /// Returns the byte offset at which the piece starts
+    pub fn piece_start(&self) -> usize
+    where
+        Bytes: Metric<S>,
+    {
+        Bytes::measure(&self.prefix)
     }
 }
 
 /// Red-Black Tree for storing pieces
+///
+/// Cloning shares every node; the copies diverge as they are edited. Pieces
+/// are positioned by byte offset, so the summary must provide the `Bytes`
+/// metric.
 #[derive(Debug, Clone)]
-pub struct RBTree {
+pub struct RBTree<S: Summary = TextSummary> {
     /// Root node of the tree
-    pub root: Option<Box<RBNode>>,
+    pub root: Option<Arc<RBNode<S>>>,
     /// Total number of nodes in the tree
     pub size: usize,
 }
 
-impl RBTree {
+impl<S: Summary> RBTree<S> {
     /// Creates a new empty Red-Black Tree
     pub fn new() -> Self {
         Self {
@@ -129,13 +163,6 @@ impl RBTree {
         }
     }
 
-    /// Creates a tree with a single piece
-    pub fn from_piece(piece: Piece) -> Self {
-        let mut tree = Self::new();
-        tree.insert(piece);
-        tree
-    }
-
     /// Returns true if the tree is empty
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
@@ -146,176 +173,421 @@ impl RBTree {
         self.size
     }
 
-    /// Returns the total length of all text in the tree
-    pub fn total_length(&self) -> usize {
+    /// Returns the summary of all text in the tree
+    pub fn summary(&self) -> S {
         self.root
             .as_ref()
-            .map(|node| node.total_length())
-            .unwrap_or(0)
+            .map(|node| node.subtree_summary.clone())
+            .unwrap_or_default()
     }
 
-    /// Returns the total number of line breaks in the tree
-    pub fn total_line_breaks(&self) -> usize {
+    /// Returns the total size of the tree in the given metric
+    pub fn measure<M: Metric<S>>(&self) -> usize {
         self.root
             .as_ref()
-            .map(|node| node.total_line_breaks())
+            .map(|node| M::measure(&node.subtree_summary))
             .unwrap_or(0)
     }
 
-    /// Inserts a piece into the tree
-    pub fn insert(&mut self, piece: Piece) {
-        self.root = Self::insert_recursive(self.root.take(), piece);
-        if let Some(ref mut root) = self.root {
-            root.color = Color::Black; // Root is always black
+    /// Finds the piece containing the unit at `value` in metric `M`
+    ///
+    /// Units are counted from zero, so seeking by `LineBreaks` finds the piece
+    /// holding the given line break. Returns None when `value` is past the
+    /// end of the text.
+    pub fn seek_by<M: Metric<S>>(&self, value: usize) -> Option<Seek<'_, S>> {
+        let mut node = self.root.as_deref();
+        let mut value = value;
+        let mut prefix = S::default();
+
+        while let Some(current) = node {
+            let left = M::measure(&current.left_summary);
+            if value < left {
+                node = current.left.as_deref();
+                continue;
+            }
+
+            let own = M::measure(&current.summary);
+            prefix.add_summary(&current.left_summary);
+            if value < left + own {
+                return Some(Seek {
+                    piece: &current.piece,
+                    summary: &current.summary,
+                    prefix,
+                });
+            }
+
+            prefix.add_summary(&current.summary);
+            value -= left + own;
+            node = current.right.as_deref();
+        }
+
+        None
+    }
+
+    /// Collects all pieces in order
+    pub fn collect_pieces(&self) -> Vec<&Piece> {
+        let mut pieces = Vec::new();
+        Self::collect_pieces_recursive(self.root.as_deref(), &mut pieces);
+        pieces
+    }
+
+    /// Returns an iterator over the pieces in order
+    pub fn iter(&self) -> Pieces<'_, S> {
+        let mut pieces = Pieces { stack: Vec::new() };
+        pieces.push_left_spine(self.root.as_deref());
+        pieces
+    }
+
+    /// Recursive helper for collecting pieces
+    fn collect_pieces_recursive<'a>(node: Option<&'a RBNode<S>>, pieces: &mut Vec<&'a Piece>) {
+        if let Some(node) = node {
+            Self::collect_pieces_recursive(node.left.as_deref(), pieces);
+            pieces.push(&node.piece);
+            Self::collect_pieces_recursive(node.right.as_deref(), pieces);
         }
+    }
+}
+
+impl<S: Summary> RBTree<S>
+where
+    Bytes: Metric<S>,
+{
+    /// Creates a tree with a single piece
+    pub fn from_piece(piece: Piece, summary: S) -> Self {
+        let mut tree = Self::new();
+        tree.insert(piece, summary);
+        tree
+    }
+
+    /// Returns the total length of all text in the tree
+    pub fn total_length(&self) -> usize {
+        self.measure::<Bytes>()
+    }
+
+    /// Returns the total number of line breaks in the tree
+    pub fn total_line_breaks(&self) -> usize
+    where
+        LineBreaks: Metric<S>,
+    {
+        self.measure::<LineBreaks>()
+    }
+
+    /// Appends a piece to the end of the tree
+    pub fn insert(&mut self, piece: Piece, summary: S) {
+        self.insert_at(self.total_length(), piece, summary);
+    }
+
+    /// Inserts a piece so that it starts at the given offset
+    ///
+    /// The offset is expected to fall on a piece boundary; callers split the
+    /// piece containing the offset beforehand.
+    pub fn insert_at(&mut self, offset: usize, piece: Piece, summary: S) {
+        let mut root = Self::insert_recursive(self.root.take(), offset, piece, summary);
+        Arc::make_mut(&mut root).color = Color::Black; // Root is always black
+        self.root = Some(root);
         self.size += 1;
     }
 
     /// Recursive helper for insertion
-    fn insert_recursive(node: Option<Box<RBNode>>, piece: Piece) -> Option<Box<RBNode>> {
+    fn insert_recursive(
+        node: Option<Arc<RBNode<S>>>,
+        offset: usize,
+        piece: Piece,
+        summary: S,
+    ) -> Arc<RBNode<S>> {
         let mut node = match node {
-            None => return Some(Box::new(RBNode::new(piece))),
+            None => return Arc::new(RBNode::new(piece, summary)),
             Some(node) => node,
         };
 
-        // Insert based on offset (we'll insert at the end for simplicity)
-        // In a real implementation, you'd compare based on the desired insertion point
-        node.right = Self::insert_recursive(node.right.take(), piece);
-
-        // Red-Black Tree balancing
-        if Self::is_red(&node.right) && !Self::is_red(&node.left) {
-            node = Self::rotate_left(node);
-        }
-        if Self::is_red(&node.left) && Self::is_red_left_child(&node.left) {
-            node = Self::rotate_right(node);
-        }
-        if Self::is_red(&node.left) && Self::is_red(&node.right) {
-            Self::flip_colors(&mut node);
+        let n = Arc::make_mut(&mut node);
+        let left_length = n.left_measure::<Bytes>();
+        if offset <= left_length {
+            n.left = Some(Self::insert_recursive(
+                n.left.take(),
+                offset,
+                piece,
+                summary,
+            ));
+        } else {
+            let right_offset = offset - left_length - n.piece.length;
+            n.right = Some(Self::insert_recursive(
+                n.right.take(),
+                right_offset,
+                piece,
+                summary,
+            ));
         }
 
-        node.update_metadata();
-        Some(node)
+        Self::balance(node)
     }
 
-    /// Finds a piece at the given offset
-    pub fn find_piece_at_offset(&self, offset: usize) -> Option<&Piece> {
-        Self::find_piece_at_offset_recursive(self.root.as_deref(), offset)
-    }
+    /// Removes the piece containing the given offset and returns it
+    pub fn remove_at(&mut self, offset: usize) -> Option<Piece> {
+        if offset >= self.total_length() {
+            return None;
+        }
 
-    /// Recursive helper for finding piece at offset
-    fn find_piece_at_offset_recursive(node: Option<&RBNode>, offset: usize) -> Option<&Piece> {
-        let node = node?;
+        let mut root = self.root.take()?;
+        if !Self::is_red(&root.left) && !Self::is_red(&root.right) {
+            Arc::make_mut(&mut root).color = Color::Red;
+        }
 
-        if offset < node.left_subtree_length {
-            // Target is in left subtree
-            Self::find_piece_at_offset_recursive(node.left.as_deref(), offset)
-        } else if offset < node.left_subtree_length + node.piece.length {
-            // Target is in this node
-            Some(&node.piece)
+        let (root, removed) = Self::remove_recursive(root, offset);
+        self.root = root.map(|mut root| {
+            Arc::make_mut(&mut root).color = Color::Black;
+            root
+        });
+        self.size -= 1;
+        Some(removed.0)
+    }
+
+    /// Recursive helper for removal, following the left-leaning deletion scheme
+    fn remove_recursive(
+        mut node: Arc<RBNode<S>>,
+        offset: usize,
+    ) -> (Option<Arc<RBNode<S>>>, (Piece, S)) {
+        let removed;
+
+        if offset < node.left_measure::<Bytes>() {
+            if !Self::is_red(&node.left) && !Self::is_red_left_child(&node.left) {
+                node = Self::move_red_left(node);
+            }
+            let n = Arc::make_mut(&mut node);
+            let (left, entry) = Self::remove_recursive(n.left.take().unwrap(), offset);
+            n.left = left;
+            removed = entry;
         } else {
-            // Target is in right subtree
-            let right_offset = offset - node.left_subtree_length - node.piece.length;
-            Self::find_piece_at_offset_recursive(node.right.as_deref(), right_offset)
+            if Self::is_red(&node.left) {
+                node = Self::rotate_right(node);
+            }
+
+            let in_node = |node: &RBNode<S>| {
+                let left_length = node.left_measure::<Bytes>();
+                offset >= left_length && offset < left_length + node.piece.length
+            };
+
+            if in_node(&node) && node.right.is_none() {
+                return (None, (node.piece.clone(), node.summary.clone()));
+            }
+
+            if !Self::is_red(&node.right) && !Self::is_red_left_child(&node.right) {
+                node = Self::move_red_right(node);
+            }
+
+            let target_in_node = in_node(&node);
+            let n = Arc::make_mut(&mut node);
+            if target_in_node {
+                let (right, (min_piece, min_summary)) = Self::remove_min(n.right.take().unwrap());
+                n.right = right;
+                removed = (
+                    std::mem::replace(&mut n.piece, min_piece),
+                    std::mem::replace(&mut n.summary, min_summary),
+                );
+            } else {
+                let right_offset = offset - n.left_measure::<Bytes>() - n.piece.length;
+                let (right, entry) = Self::remove_recursive(n.right.take().unwrap(), right_offset);
+                n.right = right;
+                removed = entry;
+            }
         }
+
+        (Some(Self::balance(node)), removed)
     }
 
-    /// Finds pieces at the given line
-    pub fn find_pieces_at_line(&self, line: usize) -> Vec<&Piece> {
-        let mut pieces = Vec::new();
-        Self::find_pieces_at_line_recursive(self.root.as_deref(), line, &mut pieces);
-        pieces
+    /// Removes the leftmost node of a subtree and returns its piece and summary
+    fn remove_min(mut node: Arc<RBNode<S>>) -> (Option<Arc<RBNode<S>>>, (Piece, S)) {
+        if node.left.is_none() {
+            return (None, (node.piece.clone(), node.summary.clone()));
+        }
+
+        if !Self::is_red(&node.left) && !Self::is_red_left_child(&node.left) {
+            node = Self::move_red_left(node);
+        }
+
+        let n = Arc::make_mut(&mut node);
+        let (left, entry) = Self::remove_min(n.left.take().unwrap());
+        n.left = left;
+        (Some(Self::balance(node)), entry)
     }
 
-    /// Recursive helper for finding pieces at line
-    fn find_pieces_at_line_recursive<'a>(
-        node: Option<&'a RBNode>,
-        line: usize,
-        pieces: &mut Vec<&'a Piece>,
-    ) {
-        let node = match node {
-            Some(node) => node,
-            None => return,
+    /// Replaces the piece containing the given offset, returning the old piece
+    pub fn replace_at(&mut self, offset: usize, piece: Piece, summary: S) -> Option<Piece> {
+        if offset >= self.total_length() {
+            return None;
+        }
+        Self::replace_recursive(self.root.as_mut(), offset, piece, summary)
+    }
+
+    /// Recursive helper for replacing a piece
+    fn replace_recursive(
+        node: Option<&mut Arc<RBNode<S>>>,
+        offset: usize,
+        piece: Piece,
+        summary: S,
+    ) -> Option<Piece> {
+        let node = Arc::make_mut(node?);
+        let left_length = node.left_measure::<Bytes>();
+
+        let replaced = if offset < left_length {
+            Self::replace_recursive(node.left.as_mut(), offset, piece, summary)
+        } else if offset < left_length + node.piece.length {
+            node.summary = summary;
+            Some(std::mem::replace(&mut node.piece, piece))
+        } else {
+            let right_offset = offset - left_length - node.piece.length;
+            Self::replace_recursive(node.right.as_mut(), right_offset, piece, summary)
         };
 
-        if line < node.left_subtree_line_breaks {
-            // Target line is in left subtree
-            Self::find_pieces_at_line_recursive(node.left.as_deref(), line, pieces);
-        } else if line < node.left_subtree_line_breaks + node.piece.line_break_count {
-            // Target line intersects with this node
-            pieces.push(&node.piece);
-        } else {
-            // Target line is in right subtree
-            let right_line = line - node.left_subtree_line_breaks - node.piece.line_break_count;
-            Self::find_pieces_at_line_recursive(node.right.as_deref(), right_line, pieces);
-        }
+        node.update_metadata();
+        replaced
     }
 
-    /// Collects all pieces in order
-    pub fn collect_pieces(&self) -> Vec<&Piece> {
-        let mut pieces = Vec::new();
-        Self::collect_pieces_recursive(self.root.as_deref(), &mut pieces);
-        pieces
+    /// Finds a piece at the given offset
+    pub fn find_piece_at_offset(&self, offset: usize) -> Option<&Piece> {
+        self.seek_by::<Bytes>(offset).map(|seek| seek.piece)
     }
 
-    /// Recursive helper for collecting pieces
-    fn collect_pieces_recursive<'a>(node: Option<&'a RBNode>, pieces: &mut Vec<&'a Piece>) {
-        if let Some(node) = node {
-            Self::collect_pieces_recursive(node.left.as_deref(), pieces);
-            pieces.push(&node.piece);
-            Self::collect_pieces_recursive(node.right.as_deref(), pieces);
-        }
+    /// Finds the piece containing the given offset along with the offset at
+    /// which that piece starts
+    pub fn locate_piece(&self, offset: usize) -> Option<(&Piece, usize)> {
+        self.seek_by::<Bytes>(offset)
+            .map(|seek| (seek.piece, seek.piece_start()))
+    }
+
+    /// Finds pieces at the given line
+    pub fn find_pieces_at_line(&self, line: usize) -> Vec<&Piece>
+    where
+        LineBreaks: Metric<S>,
+    {
+        self.seek_by::<LineBreaks>(line)
+            .map(|seek| seek.piece)
+            .into_iter()
+            .collect()
     }
 
     // Red-Black Tree helper methods
-    fn is_red(node: &Option<Box<RBNode>>) -> bool {
+    fn is_red(node: &Option<Arc<RBNode<S>>>) -> bool {
         node.as_ref().map(|n| n.is_red()).unwrap_or(false)
     }
 
-    fn is_red_left_child(node: &Option<Box<RBNode>>) -> bool {
+    fn is_red_left_child(node: &Option<Arc<RBNode<S>>>) -> bool {
         node.as_ref()
             .and_then(|n| n.left.as_ref())
             .map(|n| n.is_red())
             .unwrap_or(false)
     }
 
-    fn rotate_left(mut node: Box<RBNode>) -> Box<RBNode> {
-        let mut new_root = node.right.take().unwrap();
-        node.right = new_root.left.take();
-        new_root.color = node.color;
-        node.color = Color::Red;
+    /// Restores the left-leaning invariants on the way back up
+    fn balance(mut node: Arc<RBNode<S>>) -> Arc<RBNode<S>> {
+        if Self::is_red(&node.right) && !Self::is_red(&node.left) {
+            node = Self::rotate_left(node);
+        }
+        if Self::is_red(&node.left) && Self::is_red_left_child(&node.left) {
+            node = Self::rotate_right(node);
+        }
+        if Self::is_red(&node.left) && Self::is_red(&node.right) {
+            Self::flip_colors(&mut node);
+        }
 
-        node.update_metadata();
-        new_root.left = Some(node);
-        new_root.update_metadata();
+        Arc::make_mut(&mut node).update_metadata();
+        node
+    }
+
+    /// Borrows a red link from the right so the left child is not a 2-node
+    fn move_red_left(mut node: Arc<RBNode<S>>) -> Arc<RBNode<S>> {
+        Self::flip_colors(&mut node);
+        if Self::is_red_left_child(&node.right) {
+            let n = Arc::make_mut(&mut node);
+            n.right = n.right.take().map(Self::rotate_right);
+            node = Self::rotate_left(node);
+            Self::flip_colors(&mut node);
+        }
+        node
+    }
+
+    /// Borrows a red link from the left so the right child is not a 2-node
+    fn move_red_right(mut node: Arc<RBNode<S>>) -> Arc<RBNode<S>> {
+        Self::flip_colors(&mut node);
+        if Self::is_red_left_child(&node.left) {
+            node = Self::rotate_right(node);
+            Self::flip_colors(&mut node);
+        }
+        node
+    }
+
+    fn rotate_left(mut node: Arc<RBNode<S>>) -> Arc<RBNode<S>> {
+        let n = Arc::make_mut(&mut node);
+        let mut new_root = n.right.take().unwrap();
+        let r = Arc::make_mut(&mut new_root);
+        n.right = r.left.take();
+        r.color = n.color;
+        n.color = Color::Red;
+
+        n.update_metadata();
+        r.left = Some(node);
+        r.update_metadata();
 
         new_root
     }
 
-    fn rotate_right(mut node: Box<RBNode>) -> Box<RBNode> {
-        let mut new_root = node.left.take().unwrap();
-        node.left = new_root.right.take();
-        new_root.color = node.color;
-        node.color = Color::Red;
+    fn rotate_right(mut node: Arc<RBNode<S>>) -> Arc<RBNode<S>> {
+        let n = Arc::make_mut(&mut node);
+        let mut new_root = n.left.take().unwrap();
+        let r = Arc::make_mut(&mut new_root);
+        n.left = r.right.take();
+        r.color = n.color;
+        n.color = Color::Red;
 
-        node.update_metadata();
-        new_root.right = Some(node);
-        new_root.update_metadata();
+        n.update_metadata();
+        r.right = Some(node);
+        r.update_metadata();
 
         new_root
     }
 
-    fn flip_colors(node: &mut Box<RBNode>) {
-        node.color = Color::Red;
+    /// Inverts the colors of a node and its children, copying shared nodes
+    fn flip_colors(node: &mut Arc<RBNode<S>>) {
+        let node = Arc::make_mut(node);
+        node.color = node.color.flipped();
         if let Some(ref mut left) = node.left {
-            left.color = Color::Black;
+            let left = Arc::make_mut(left);
+            left.color = left.color.flipped();
         }
         if let Some(ref mut right) = node.right {
-            right.color = Color::Black;
+            let right = Arc::make_mut(right);
+            right.color = right.color.flipped();
+        }
+    }
+}
+
+/// In-order iterator over the pieces of a tree
+#[derive(Debug, Clone)]
+pub struct Pieces<'a, S: Summary = TextSummary> {
+    /// Nodes whose piece and right subtree are still to be visited
+    stack: Vec<&'a RBNode<S>>,
+}
+
+impl<'a, S: Summary> Pieces<'a, S> {
+    fn push_left_spine(&mut self, mut node: Option<&'a RBNode<S>>) {
+        while let Some(current) = node {
+            self.stack.push(current);
+            node = current.left.as_deref();
         }
     }
 }
 
-impl Default for RBTree {
+impl<'a, S: Summary> Iterator for Pieces<'a, S> {
+    type Item = &'a Piece;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(node.right.as_deref());
+        Some(&node.piece)
+    }
+}
+
+impl<S: Summary> Default for RBTree<S> {
     fn default() -> Self {
         Self::new()
     }
@@ -326,9 +598,18 @@ mod tests {
     use super::*;
     use crate::piece::Piece;
 
+    /// Summary matching the piece's recorded length and line breaks
+    fn summary_of(piece: &Piece) -> TextSummary {
+        TextSummary {
+            bytes: piece.length,
+            line_breaks: piece.line_break_count,
+            ..TextSummary::default()
+        }
+    }
+
     #[test]
     fn test_empty_tree() {
-        let tree = RBTree::new();
+        let tree: RBTree = RBTree::new();
         assert!(tree.is_empty());
         assert_eq!(tree.len(), 0);
         assert_eq!(tree.total_length(), 0);
@@ -338,7 +619,7 @@ mod tests {
     #[test]
     fn test_single_piece_tree() {
         let piece = Piece::original(0, 0, 10, 2);
-        let tree = RBTree::from_piece(piece.clone());
+        let tree = RBTree::from_piece(piece.clone(), summary_of(&piece));
 
         assert!(!tree.is_empty());
         assert_eq!(tree.len(), 1);
@@ -348,12 +629,12 @@ mod tests {
 
     #[test]
     fn test_insert_and_find() {
-        let mut tree = RBTree::new();
+        let mut tree: RBTree = RBTree::new();
         let piece1 = Piece::original(0, 0, 10, 1);
         let piece2 = Piece::original(0, 10, 15, 2);
 
-        tree.insert(piece1.clone());
-        tree.insert(piece2.clone());
+        tree.insert(piece1.clone(), summary_of(&piece1));
+        tree.insert(piece2.clone(), summary_of(&piece2));
 
         assert_eq!(tree.len(), 2);
         assert_eq!(tree.total_length(), 25);
@@ -372,31 +653,122 @@ mod tests {
 
     #[test]
     fn test_collect_pieces() {
-        let mut tree = RBTree::new();
+        let mut tree: RBTree = RBTree::new();
         let piece1 = Piece::original(0, 0, 10, 1);
         let piece2 = Piece::original(0, 10, 15, 2);
         let piece3 = Piece::added(0, 0, 5, 0);
 
-        tree.insert(piece1.clone());
-        tree.insert(piece2.clone());
-        tree.insert(piece3.clone());
+        tree.insert(piece1.clone(), summary_of(&piece1));
+        tree.insert(piece2.clone(), summary_of(&piece2));
+        tree.insert(piece3.clone(), summary_of(&piece3));
 
         let pieces = tree.collect_pieces();
         assert_eq!(pieces.len(), 3);
+        assert_eq!(tree.iter().collect::<Vec<_>>(), pieces);
+    }
+
+    #[test]
+    fn test_insert_at_and_remove_at() {
+        let mut tree: RBTree = RBTree::new();
+        let mut expected = Vec::new();
+
+        for i in 0..100 {
+            let index = (i * 7) % (expected.len() + 1);
+            let offset = expected.len().min(index) * 10;
+            let piece = Piece::added(0, i * 10, 10, 1);
+            tree.insert_at(offset, piece.clone(), summary_of(&piece));
+            expected.insert(index, i * 10);
+        }
+
+        for i in 0..60 {
+            let index = (i * 13) % expected.len();
+            let removed = tree.remove_at(index * 10 + 3).unwrap();
+            assert_eq!(removed.start, expected.remove(index));
+        }
+
+        let starts: Vec<usize> = tree.collect_pieces().iter().map(|p| p.start).collect();
+        assert_eq!(starts, expected);
+        assert_eq!(tree.len(), 40);
+        assert_eq!(tree.total_length(), 400);
+        assert_eq!(tree.total_line_breaks(), 40);
+        assert_eq!(tree.locate_piece(125).unwrap().1, 120);
+        assert!(tree.remove_at(400).is_none());
+    }
+
+    #[test]
+    fn test_clone_shares_nodes() {
+        fn collect(node: &Arc<RBNode>, out: &mut Vec<*const RBNode>) {
+            out.push(Arc::as_ptr(node));
+            for child in [&node.left, &node.right].into_iter().flatten() {
+                collect(child, out);
+            }
+        }
+
+        let mut tree: RBTree = RBTree::new();
+        for i in 0..100 {
+            let piece = Piece::original(0, i * 10, 10, 1);
+            tree.insert(piece.clone(), summary_of(&piece));
+        }
+
+        let mut edited = tree.clone();
+        let piece = Piece::added(0, 0, 3, 0);
+        edited.insert_at(500, piece.clone(), summary_of(&piece));
+        edited.remove_at(0);
+
+        // The original version is untouched
+        assert_eq!(tree.len(), 100);
+        assert_eq!(tree.total_length(), 1000);
+        assert_eq!(tree.collect_pieces()[0].start, 0);
+        assert_eq!(edited.total_length(), 993);
+
+        // Only the paths touched by the edits were copied
+        let (mut old_nodes, mut new_nodes) = (Vec::new(), Vec::new());
+        collect(tree.root.as_ref().unwrap(), &mut old_nodes);
+        collect(edited.root.as_ref().unwrap(), &mut new_nodes);
+        let shared = new_nodes.iter().filter(|n| old_nodes.contains(n)).count();
+        assert!(
+            shared >= 70,
+            "only {shared} of {} nodes shared",
+            new_nodes.len()
+        );
     }
 
     #[test]
     fn test_node_metadata() {
         let piece = Piece::original(0, 0, 10, 2);
-        let mut node = RBNode::new(piece);
+        let mut node = RBNode::new(piece.clone(), summary_of(&piece));
 
-        assert_eq!(node.left_subtree_length, 0);
-        assert_eq!(node.left_subtree_line_breaks, 0);
+        assert_eq!(node.left_summary, TextSummary::default());
         assert_eq!(node.total_length(), 10);
         assert_eq!(node.total_line_breaks(), 2);
 
         node.update_metadata();
-        assert_eq!(node.left_subtree_length, 0);
-        assert_eq!(node.left_subtree_line_breaks, 0);
+        assert_eq!(node.left_summary, TextSummary::default());
+        assert_eq!(node.subtree_summary, summary_of(&piece));
+    }
+
+    #[test]
+    fn test_seek_by_metric() {
+        let mut tree: RBTree = RBTree::new();
+        for i in 0..50 {
+            // Every other piece holds no line break
+            let piece = Piece::original(0, i * 10, 10, i % 2);
+            tree.insert(piece.clone(), summary_of(&piece));
+        }
+
+        let seek = tree.seek_by::<Bytes>(137).unwrap();
+        assert_eq!(seek.piece.start, 130);
+        assert_eq!(seek.piece_start(), 130);
+        assert_eq!(seek.prefix.line_breaks, 6);
+
+        // Line break 3 is held by the 4th piece with a break, the 8th piece
+        let seek = tree.seek_by::<LineBreaks>(3).unwrap();
+        assert_eq!(seek.piece.start, 70);
+        assert_eq!((seek.prefix.bytes, seek.prefix.line_breaks), (70, 3));
+        assert_eq!(tree.measure::<Bytes>(), 500);
+        assert_eq!(tree.summary().line_breaks, 25);
+
+        assert!(tree.seek_by::<LineBreaks>(25).is_none());
+        assert!(tree.seek_by::<Bytes>(500).is_none());
     }
 }
@@ -0,0 +1,375 @@
+//! Selection module for multiple cursors
+//!
+//! This module implements the SelectionSet, the cursors and selections of an
+//! editor view kept alongside a TextBuffer. Selections are kept sorted and
+//! overlapping ones are merged. Typing, deleting and pasting apply to every
+//! selection in one batch edit, so the whole operation is a single version
+//! and a single undo step, and every cursor ends up after its own edit.
+
+use crate::text_buffer::TextBuffer;
+use crate::types::{EditOperation, Position, Range, TextBufferResult};
+
+/// A selected range with a fixed anchor and a moving head
+///
+/// An empty selection is a plain cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Position,
+    pub head: Position,
+}
+
+impl Selection {
+    pub fn new(anchor: Position, head: Position) -> Self {
+        Self { anchor, head }
+    }
+
+    /// Creates an empty selection at a position
+    pub fn cursor(position: Position) -> Self {
+        Self::new(position, position)
+    }
+
+    /// Returns true if nothing is selected
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// Returns true if the head is before the anchor
+    pub fn is_reversed(&self) -> bool {
+        self.head < self.anchor
+    }
+
+    /// Returns the selected range, start first
+    pub fn range(&self) -> Range {
+        Range::new(self.anchor.min(self.head), self.anchor.max(self.head))
+    }
+}
+
+/// Sorted, non-overlapping selections with one primary selection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionSet {
+    selections: Vec<Selection>,
+    /// Index of the primary selection
+    primary: usize,
+}
+
+impl SelectionSet {
+    /// Creates a set holding a single selection
+    pub fn new(selection: Selection) -> Self {
+        Self {
+            selections: vec![selection],
+            primary: 0,
+        }
+    }
+
+    /// Creates a set from selections, merging overlapping ones; the last
+    /// selection becomes the primary one
+    pub fn from_selections(selections: Vec<Selection>) -> TextBufferResult<Self> {
+        let primary = selections
+            .last()
+            .copied()
+            .ok_or_else(|| "Selection set cannot be empty".to_string())?;
+        let mut set = Self {
+            selections,
+            primary: 0,
+        };
+        set.normalize(primary);
+        Ok(set)
+    }
+
+    /// Returns the selections in document order
+    pub fn selections(&self) -> &[Selection] {
+        &self.selections
+    }
+
+    /// Returns the primary selection, the one the view follows
+    pub fn primary(&self) -> Selection {
+        self.selections[self.primary]
+    }
+
+    /// Returns the number of selections
+    pub fn len(&self) -> usize {
+        self.selections.len()
+    }
+
+    /// Returns true if there are no selections, which never happens as a set
+    /// holds at least one
+    pub fn is_empty(&self) -> bool {
+        self.selections.is_empty()
+    }
+
+    /// Adds a selection and makes it the primary one
+    pub fn add(&mut self, selection: Selection) {
+        self.selections.push(selection);
+        self.normalize(selection);
+    }
+
+    /// Replaces every selection with a single one
+    pub fn set(&mut self, selection: Selection) {
+        *self = Self::new(selection);
+    }
+
+    /// Replaces every selection with the given text
+    ///
+    /// Returns the edits that undo the batch.
+    pub fn insert_text(
+        &mut self,
+        buffer: &mut TextBuffer,
+        text: &str,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let texts = vec![text; self.selections.len()];
+        self.replace_each(buffer, &texts)
+    }
+
+    /// Pastes text into every selection
+    ///
+    /// When the text has one line per selection, each selection receives its
+    /// own line, like a paste of text copied from the same number of
+    /// cursors; otherwise every selection receives the whole text. Lines
+    /// may end with LF or CRLF.
+    pub fn paste(
+        &mut self,
+        buffer: &mut TextBuffer,
+        text: &str,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let lines: Vec<&str> = text.lines().collect();
+        if self.selections.len() > 1 && lines.len() == self.selections.len() {
+            self.replace_each(buffer, &lines)
+        } else {
+            self.insert_text(buffer, text)
+        }
+    }
+
+    /// Deletes every selection, or the grapheme before each cursor
+    pub fn delete_backward(
+        &mut self,
+        buffer: &mut TextBuffer,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        self.delete(buffer, |buffer, offset| {
+            buffer.prev_grapheme_boundary(offset)
+        })
+    }
+
+    /// Deletes every selection, or the grapheme after each cursor
+    pub fn delete_forward(
+        &mut self,
+        buffer: &mut TextBuffer,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        self.delete(buffer, |buffer, offset| {
+            buffer.next_grapheme_boundary(offset)
+        })
+    }
+
+    /// Helper method to delete every selection, or the range between each
+    /// cursor and the boundary found by `boundary`
+    fn delete(
+        &mut self,
+        buffer: &mut TextBuffer,
+        boundary: impl Fn(&TextBuffer, usize) -> TextBufferResult<usize>,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let mut ranges = Vec::with_capacity(self.selections.len());
+        for selection in &self.selections {
+            let range = selection.range();
+            let start = buffer.position_to_offset(range.start)?;
+            let end = buffer.position_to_offset(range.end)?;
+            ranges.push(match selection.is_empty() {
+                true => {
+                    let other = boundary(buffer, start)?;
+                    (start.min(other), start.max(other))
+                }
+                false => (start, end),
+            });
+        }
+
+        // Cursors next to each other may delete the same grapheme
+        for index in 1..ranges.len() {
+            let previous_end = ranges[index - 1].1;
+            let (start, end) = &mut ranges[index];
+            *start = (*start).max(previous_end);
+            *end = (*end).max(*start);
+        }
+
+        let edits = ranges
+            .into_iter()
+            .map(|(start, end)| (start, end, String::new()))
+            .collect();
+        self.apply(buffer, edits)
+    }
+
+    /// Helper method to replace each selection with its own text
+    fn replace_each(
+        &mut self,
+        buffer: &mut TextBuffer,
+        texts: &[&str],
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let mut edits = Vec::with_capacity(self.selections.len());
+        for (selection, text) in self.selections.iter().zip(texts) {
+            let range = selection.range();
+            let start = buffer.position_to_offset(range.start)?;
+            let end = buffer.position_to_offset(range.end)?;
+            edits.push((start, end, text.to_string()));
+        }
+        self.apply(buffer, edits)
+    }
+
+    /// Helper method to apply one edit per selection as a batch and leave a
+    /// cursor after each inserted text
+    ///
+    /// Edits are byte offset ranges in selection order.
+    fn apply(
+        &mut self,
+        buffer: &mut TextBuffer,
+        edits: Vec<(usize, usize, String)>,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let mut cursors = Vec::with_capacity(edits.len());
+        let mut shift = 0isize;
+        let mut operations = Vec::with_capacity(edits.len());
+        for (start, end, text) in edits {
+            let new_start = start.checked_add_signed(shift).unwrap();
+            cursors.push(new_start + text.len());
+            shift += text.len() as isize - (end - start) as isize;
+
+            let range = Range::new(
+                buffer.offset_to_position(start)?,
+                buffer.offset_to_position(end)?,
+            );
+            operations.push(EditOperation::new(range, text));
+        }
+
+        let undo = buffer.apply_edits(operations)?;
+
+        let primary = self.primary;
+        self.selections = cursors
+            .into_iter()
+            .map(|offset| buffer.offset_to_position(offset).map(Selection::cursor))
+            .collect::<TextBufferResult<_>>()?;
+        let primary = self.selections[primary];
+        self.normalize(primary);
+        Ok(undo)
+    }
+
+    /// Sorts the selections and merges the ones that overlap, or that touch
+    /// where one of them is a cursor
+    ///
+    /// The selection merged into the one containing `primary` becomes the
+    /// primary selection.
+    fn normalize(&mut self, primary: Selection) {
+        self.selections
+            .sort_by_key(|selection| selection.range().start);
+
+        let mut merged: Vec<Selection> = Vec::with_capacity(self.selections.len());
+        self.primary = 0;
+        for selection in self.selections.drain(..) {
+            let is_primary = selection == primary;
+            let range = selection.range();
+            match merged.last_mut() {
+                Some(last)
+                    if range.start < last.range().end
+                        || (range.start == last.range().end
+                            && (selection.is_empty() || last.is_empty())) =>
+                {
+                    let start = last.range().start;
+                    let end = last.range().end.max(range.end);
+                    *last = match last.is_reversed() {
+                        true => Selection::new(end, start),
+                        false => Selection::new(start, end),
+                    };
+                }
+                _ => merged.push(selection),
+            }
+            if is_primary {
+                self.primary = merged.len() - 1;
+            }
+        }
+        self.selections = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursors(set: &SelectionSet) -> Vec<(usize, usize)> {
+        set.selections()
+            .iter()
+            .map(|selection| (selection.head.line, selection.head.column))
+            .collect()
+    }
+
+    #[test]
+    fn test_selections_merge() {
+        let set = SelectionSet::from_selections(vec![
+            Selection::new(Position::new(1, 4), Position::new(1, 0)),
+            Selection::cursor(Position::new(0, 2)),
+            Selection::new(Position::new(1, 2), Position::new(1, 6)),
+            Selection::cursor(Position::new(0, 2)),
+            Selection::cursor(Position::new(2, 0)),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            set.selections(),
+            &[
+                Selection::cursor(Position::new(0, 2)),
+                Selection::new(Position::new(1, 6), Position::new(1, 0)),
+                Selection::cursor(Position::new(2, 0)),
+            ]
+        );
+        assert_eq!(set.primary(), Selection::cursor(Position::new(2, 0)));
+        assert!(SelectionSet::from_selections(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_multi_cursor_editing() {
+        let mut buffer = TextBuffer::from_text("one\ntwo\nthree");
+        let mut set = SelectionSet::from_selections(vec![
+            Selection::cursor(Position::new(0, 3)),
+            Selection::cursor(Position::new(1, 3)),
+            Selection::new(Position::new(2, 0), Position::new(2, 5)),
+        ])
+        .unwrap();
+
+        set.insert_text(&mut buffer, "!?").unwrap();
+        assert_eq!(buffer.get_all_text(), "one!?\ntwo!?\n!?");
+        assert_eq!(buffer.version(), 1);
+        assert_eq!(cursors(&set), vec![(0, 5), (1, 5), (2, 2)]);
+
+        set.delete_backward(&mut buffer).unwrap();
+        assert_eq!(buffer.get_all_text(), "one!\ntwo!\n!");
+        assert_eq!(cursors(&set), vec![(0, 4), (1, 4), (2, 1)]);
+
+        // One line per cursor
+        set.paste(&mut buffer, "a\nb\nc\n").unwrap();
+        assert_eq!(buffer.get_all_text(), "one!a\ntwo!b\n!c");
+        set.paste(&mut buffer, "x\ny").unwrap();
+        assert_eq!(buffer.get_all_text(), "one!ax\ny\ntwo!bx\ny\n!cx\ny");
+        assert_eq!(cursors(&set), vec![(1, 1), (3, 1), (5, 1)]);
+        assert_eq!(buffer.version(), 4);
+    }
+
+    #[test]
+    fn test_paste_crlf_lines() {
+        let mut buffer = TextBuffer::from_text("1\r\n2");
+        let mut set = SelectionSet::from_selections(vec![
+            Selection::cursor(Position::new(0, 1)),
+            Selection::cursor(Position::new(1, 1)),
+        ])
+        .unwrap();
+
+        set.paste(&mut buffer, "a\r\nb\r\n").unwrap();
+        assert_eq!(buffer.get_all_text(), "1a\r\n2b");
+    }
+
+    #[test]
+    fn test_adjacent_cursors_delete_once() {
+        let mut buffer = TextBuffer::from_text("abc");
+        let mut set = SelectionSet::from_selections(vec![
+            Selection::cursor(Position::new(0, 1)),
+            Selection::cursor(Position::new(0, 2)),
+        ])
+        .unwrap();
+
+        set.delete_forward(&mut buffer).unwrap();
+        assert_eq!(buffer.get_all_text(), "a");
+        assert_eq!(cursors(&set), vec![(0, 1)]);
+    }
+}
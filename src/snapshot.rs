@@ -0,0 +1,685 @@
+//! Snapshot module providing a read-only view of a text buffer
+//!
+//! This module implements TextSnapshot, the immutable combination of buffers
+//! and piece tree that backs a TextBuffer. Snapshots share the append-only
+//! buffers with the live buffer; an edit copies at most the last, size-capped
+//! added buffer, so background tasks can read a consistent version while
+//! editing continues.
+
+use std::sync::Arc;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::buffer::Buffer;
+use crate::piece::{utils, Piece};
+use crate::red_black_tree::{Pieces, RBTree};
+use crate::summary::{Bytes, Chars, LineBreaks, Metric, Summary, TextSummary, Utf16};
+use crate::types::{ColumnUnit, Position, Range, TextBufferResult};
+
+/// Read-only view of a text buffer at one version
+///
+/// Cloning a snapshot is O(1): the buffer lists and the piece tree are shared.
+#[derive(Debug, Clone)]
+pub struct TextSnapshot {
+    /// Original buffers (read-only content from files)
+    pub(crate) original_buffers: Arc<Vec<Arc<Buffer>>>,
+    /// Added buffers (append-only content from edits)
+    pub(crate) added_buffers: Arc<Vec<Arc<Buffer>>>,
+    /// Red-black tree storing the pieces, summarized by `TextSummary`
+    pub(crate) tree: RBTree,
+    /// Unit of the columns accepted and returned by the position APIs
+    pub(crate) column_unit: ColumnUnit,
+}
+
+impl TextSnapshot {
+    /// Creates a snapshot from buffers and the tree of pieces referencing them
+    pub(crate) fn new(
+        original_buffers: Vec<Arc<Buffer>>,
+        added_buffers: Vec<Arc<Buffer>>,
+        tree: RBTree,
+    ) -> Self {
+        Self {
+            original_buffers: Arc::new(original_buffers),
+            added_buffers: Arc::new(added_buffers),
+            tree,
+            column_unit: ColumnUnit::default(),
+        }
+    }
+
+    /// Returns the unit in which position columns are measured
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.column_unit
+    }
+
+    /// Sets the unit in which position columns are measured
+    pub fn set_column_unit(&mut self, unit: ColumnUnit) {
+        self.column_unit = unit;
+    }
+
+    /// Returns the total number of lines in the buffer
+    pub fn line_count(&self) -> usize {
+        if self.tree.is_empty() {
+            return 1; // Always at least one line
+        }
+
+        // Count total line breaks and add 1
+        self.tree.total_line_breaks() + 1
+    }
+
+    /// Returns the total length of the buffer in bytes
+    pub fn length(&self) -> usize {
+        self.tree.total_length()
+    }
+
+    /// Returns true if the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.length() == 0
+    }
+
+    /// Gets the content of a specific line (0-indexed)
+    pub fn get_line_content(&self, line: usize) -> TextBufferResult<String> {
+        if line >= self.line_count() {
+            return Err(format!("Line {line} out of bounds"));
+        }
+
+        let (start, end) = self.line_bounds(line)?;
+        self.text_between(start.bytes, end.bytes)
+    }
+
+    /// Gets the length of a specific line (0-indexed), in the column unit
+    pub fn get_line_length(&self, line: usize) -> TextBufferResult<usize> {
+        self.get_line_length_in(line, self.column_unit)
+    }
+
+    /// Gets the length of a specific line (0-indexed), in the given unit
+    pub fn get_line_length_in(&self, line: usize, unit: ColumnUnit) -> TextBufferResult<usize> {
+        if line >= self.line_count() {
+            return Err(format!("Line {line} out of bounds"));
+        }
+
+        let (start, end) = self.line_bounds(line)?;
+        Ok(match unit {
+            ColumnUnit::Bytes => end.bytes - start.bytes,
+            ColumnUnit::Chars => end.chars - start.chars,
+            ColumnUnit::Utf16 => end.utf16 - start.utf16,
+            ColumnUnit::Graphemes => self
+                .text_between(start.bytes, end.bytes)?
+                .graphemes(true)
+                .count(),
+        })
+    }
+
+    /// Gets text content in the specified range
+    pub fn get_text_in_range(&self, range: Range) -> TextBufferResult<String> {
+        let start_offset = self.position_to_offset(range.start)?;
+        let end_offset = self.position_to_offset(range.end)?;
+
+        if start_offset >= end_offset {
+            return Err("Invalid range".to_string());
+        }
+
+        self.text_between(start_offset, end_offset)
+    }
+
+    /// Gets the text between two byte offsets
+    pub(crate) fn text_between(
+        &self,
+        start_offset: usize,
+        end_offset: usize,
+    ) -> TextBufferResult<String> {
+        let mut content = String::with_capacity(end_offset.saturating_sub(start_offset));
+        let mut offset = start_offset;
+
+        while offset < end_offset {
+            let seek = self
+                .tree
+                .seek_by::<Bytes>(offset)
+                .ok_or_else(|| "Offset out of bounds".to_string())?;
+            let piece_content = self
+                .get_piece_content(seek.piece)
+                .ok_or_else(|| "Failed to get piece content".to_string())?;
+
+            let piece_start = seek.piece_start();
+            let content_end = (end_offset - piece_start).min(piece_content.len());
+            content.push_str(
+                piece_content
+                    .get(offset - piece_start..content_end)
+                    .ok_or_else(|| "Offset is not on a char boundary".to_string())?,
+            );
+            offset = piece_start + content_end;
+        }
+
+        Ok(content)
+    }
+
+    /// Gets all text content as a string
+    pub fn get_all_text(&self) -> String {
+        let mut content = String::new();
+        let pieces = self.tree.collect_pieces();
+
+        for piece in pieces {
+            if let Some(piece_content) = self.get_piece_content(piece) {
+                content.push_str(piece_content);
+            }
+        }
+
+        content
+    }
+
+    /// Converts a position to a byte offset, with the column in the column
+    /// unit
+    pub fn position_to_offset(&self, position: Position) -> TextBufferResult<usize> {
+        self.position_to_offset_in(position, self.column_unit)
+    }
+
+    /// Converts a byte offset to a position, with the column in the column
+    /// unit
+    pub fn offset_to_position(&self, offset: usize) -> TextBufferResult<Position> {
+        self.offset_to_position_in(offset, self.column_unit)
+    }
+
+    /// Converts a position whose column is measured in `unit` to a byte offset
+    ///
+    /// Columns in bytes, chars and UTF-16 are resolved in O(log n); grapheme
+    /// columns scan the line.
+    pub fn position_to_offset_in(
+        &self,
+        position: Position,
+        unit: ColumnUnit,
+    ) -> TextBufferResult<usize> {
+        match unit {
+            ColumnUnit::Bytes => self.position_to_offset_by::<Bytes>(position),
+            ColumnUnit::Chars => self.position_to_offset_by::<Chars>(position),
+            ColumnUnit::Utf16 => self.position_to_offset_by::<Utf16>(position),
+            ColumnUnit::Graphemes => self.grapheme_position_to_offset(position),
+        }
+    }
+
+    /// Converts a byte offset to a position whose column is measured in `unit`
+    pub fn offset_to_position_in(
+        &self,
+        offset: usize,
+        unit: ColumnUnit,
+    ) -> TextBufferResult<Position> {
+        match unit {
+            ColumnUnit::Bytes => self.offset_to_position_by::<Bytes>(offset),
+            ColumnUnit::Chars => self.offset_to_position_by::<Chars>(offset),
+            ColumnUnit::Utf16 => self.offset_to_position_by::<Utf16>(offset),
+            ColumnUnit::Graphemes => {
+                let position = self.offset_to_position_by::<Bytes>(offset)?;
+                let line_start = offset - position.column;
+                let column = self
+                    .text_between(line_start, offset)?
+                    .graphemes(true)
+                    .count();
+                Ok(Position::new(position.line, column))
+            }
+        }
+    }
+
+    /// Converts a position with a column in UTF-16 code units to a byte offset
+    pub fn utf16_position_to_offset(&self, position: Position) -> TextBufferResult<usize> {
+        self.position_to_offset_by::<Utf16>(position)
+    }
+
+    /// Converts a byte offset to a position with a column in UTF-16 code units
+    pub fn offset_to_utf16_position(&self, offset: usize) -> TextBufferResult<Position> {
+        self.offset_to_position_by::<Utf16>(offset)
+    }
+
+    /// Converts a byte offset to a char index
+    pub fn byte_to_char(&self, offset: usize) -> TextBufferResult<usize> {
+        Ok(self.summary_at(offset)?.chars)
+    }
+
+    /// Converts a char index to a byte offset
+    pub fn char_to_byte(&self, index: usize) -> TextBufferResult<usize> {
+        self.offset_of::<Chars>(index)
+    }
+
+    /// Converts a byte offset to a UTF-16 code unit index
+    pub fn byte_to_utf16(&self, offset: usize) -> TextBufferResult<usize> {
+        Ok(self.summary_at(offset)?.utf16)
+    }
+
+    /// Converts a UTF-16 code unit index to a byte offset
+    ///
+    /// An index pointing between the two halves of a surrogate pair resolves
+    /// to the start of the char.
+    pub fn utf16_to_byte(&self, index: usize) -> TextBufferResult<usize> {
+        self.offset_of::<Utf16>(index)
+    }
+
+    /// Converts a position whose column is measured in metric `M` to a byte
+    /// offset
+    ///
+    /// A column past the end of a line resolves to the end of that line,
+    /// except on the last line where it is out of bounds.
+    pub(crate) fn position_to_offset_by<M: Metric<TextSummary>>(
+        &self,
+        position: Position,
+    ) -> TextBufferResult<usize> {
+        if position.line >= self.line_count() {
+            return Err("Position out of bounds".to_string());
+        }
+
+        let (start, end) = self.line_bounds(position.line)?;
+        let units = M::measure(&start) + position.column;
+        if units > M::measure(&end) {
+            return if position.line + 1 < self.line_count() {
+                Ok(end.bytes)
+            } else {
+                Err("Position out of bounds".to_string())
+            };
+        }
+
+        self.offset_of::<M>(units)
+    }
+
+    /// Converts a byte offset to a position whose column is measured in
+    /// metric `M`
+    pub(crate) fn offset_to_position_by<M: Metric<TextSummary>>(
+        &self,
+        offset: usize,
+    ) -> TextBufferResult<Position> {
+        let summary = self.summary_at(offset)?;
+        let line_start = self.line_start_summary(summary.line_breaks)?;
+        Ok(Position::new(
+            summary.line_breaks,
+            M::measure(&summary) - M::measure(&line_start),
+        ))
+    }
+
+    /// Converts a position with a column in grapheme clusters to a byte offset
+    fn grapheme_position_to_offset(&self, position: Position) -> TextBufferResult<usize> {
+        if position.line >= self.line_count() {
+            return Err("Position out of bounds".to_string());
+        }
+
+        let (start, end) = self.line_bounds(position.line)?;
+        let line = self.text_between(start.bytes, end.bytes)?;
+        let mut boundaries = line
+            .grapheme_indices(true)
+            .map(|(index, _)| index)
+            .chain(std::iter::once(line.len()));
+
+        match boundaries.nth(position.column) {
+            Some(index) => Ok(start.bytes + index),
+            None if position.line + 1 < self.line_count() => Ok(end.bytes),
+            None => Err("Position out of bounds".to_string()),
+        }
+    }
+
+    /// Returns the summary of the text before the given byte offset
+    pub(crate) fn summary_at(&self, offset: usize) -> TextBufferResult<TextSummary> {
+        let total = self.tree.summary();
+        if offset >= total.bytes {
+            return if offset == total.bytes {
+                Ok(total)
+            } else {
+                Err("Offset out of bounds".to_string())
+            };
+        }
+
+        let seek = self
+            .tree
+            .seek_by::<Bytes>(offset)
+            .ok_or_else(|| "Offset out of bounds".to_string())?;
+        let piece_content = self
+            .get_piece_content(seek.piece)
+            .ok_or_else(|| "Failed to get piece content".to_string())?;
+
+        let within = offset - seek.piece_start();
+        if !piece_content.is_char_boundary(within) {
+            return Err("Offset is not on a char boundary".to_string());
+        }
+
+        let mut summary = seek.prefix;
+        summary.add_summary(&TextSummary::from_text(&piece_content[..within]));
+        Ok(summary)
+    }
+
+    /// Returns the byte offset of the text before which metric `M` counts
+    /// `units`
+    pub(crate) fn offset_of<M: Metric<TextSummary>>(
+        &self,
+        units: usize,
+    ) -> TextBufferResult<usize> {
+        let total = self.tree.summary();
+        if units >= M::measure(&total) {
+            return if units == M::measure(&total) {
+                Ok(total.bytes)
+            } else {
+                Err("Offset out of bounds".to_string())
+            };
+        }
+
+        let seek = self
+            .tree
+            .seek_by::<M>(units)
+            .ok_or_else(|| "Offset out of bounds".to_string())?;
+        let piece_content = self
+            .get_piece_content(seek.piece)
+            .ok_or_else(|| "Failed to get piece content".to_string())?;
+
+        let mut remaining = units - M::measure(&seek.prefix);
+        let mut offset = seek.piece_start();
+        for ch in piece_content.chars() {
+            let size = M::measure(&TextSummary::from_char(ch));
+            if remaining < size {
+                break;
+            }
+            remaining -= size;
+            offset += ch.len_utf8();
+        }
+
+        Ok(offset)
+    }
+
+    /// Returns the summary of the text before the start of a line
+    pub(crate) fn line_start_summary(&self, line: usize) -> TextBufferResult<TextSummary> {
+        if line == 0 {
+            return Ok(TextSummary::default());
+        }
+
+        let seek = self
+            .tree
+            .seek_by::<LineBreaks>(line - 1)
+            .ok_or_else(|| format!("Line {line} out of bounds"))?;
+        let piece_content = self
+            .get_piece_content(seek.piece)
+            .ok_or_else(|| "Failed to get piece content".to_string())?;
+
+        let (index, _) = piece_content
+            .match_indices('\n')
+            .nth(line - 1 - seek.prefix.line_breaks)
+            .ok_or_else(|| "Failed to find line break".to_string())?;
+
+        let mut summary = seek.prefix;
+        summary.add_summary(&TextSummary::from_text(&piece_content[..=index]));
+        Ok(summary)
+    }
+
+    /// Returns the summaries of the text before the start and before the end
+    /// of a line, excluding its line break
+    pub(crate) fn line_bounds(&self, line: usize) -> TextBufferResult<(TextSummary, TextSummary)> {
+        let start = self.line_start_summary(line)?;
+        let end = if line + 1 < self.line_count() {
+            let next = self.line_start_summary(line + 1)?;
+            self.summary_at(next.bytes - 1)?
+        } else {
+            self.tree.summary()
+        };
+        Ok((start, end))
+    }
+
+    /// Returns the byte offset of the end of a line's content, before the CR
+    /// of a CRLF line break
+    pub(crate) fn line_content_end(&self, line: usize) -> TextBufferResult<usize> {
+        let (start, end) = self.line_bounds(line)?;
+        let ends_with_cr = end.bytes > start.bytes
+            && self.chars_in(end.bytes - 1, end.bytes).next() == Some((end.bytes - 1, '\r'));
+        Ok(end.bytes - usize::from(ends_with_cr))
+    }
+
+    /// Returns the line break of the text: CRLF when its first line ends
+    /// with one, LF otherwise
+    ///
+    /// Edits that add lines use it so the text keeps a single line ending.
+    pub fn line_ending(&self) -> &'static str {
+        let crlf = self.line_count() > 1
+            && self
+                .line_content_end(0)
+                .is_ok_and(|end| self.chars_in(end, end + 1).next() == Some((end, '\r')));
+        if crlf {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    /// Helper method to get piece content from the appropriate buffer
+    pub(crate) fn get_piece_content(&self, piece: &Piece) -> Option<&str> {
+        utils::get_piece_content(piece, &self.original_buffers, &self.added_buffers)
+    }
+
+    /// Returns the text of the piece containing the given byte offset along
+    /// with the offset at which that piece starts
+    pub(crate) fn chunk_at(&self, offset: usize) -> TextBufferResult<(&str, usize)> {
+        let seek = self
+            .tree
+            .seek_by::<Bytes>(offset)
+            .ok_or_else(|| "Offset out of bounds".to_string())?;
+        let piece_content = self
+            .get_piece_content(seek.piece)
+            .ok_or_else(|| "Failed to get piece content".to_string())?;
+        Ok((piece_content, seek.piece_start()))
+    }
+
+    /// Returns the piece texts covering `start..end`, clipped to that range,
+    /// each with the offset at which it starts
+    pub(crate) fn chunks_in(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = (&str, usize)> + '_ {
+        let mut offset = start;
+        std::iter::from_fn(move || {
+            if offset >= end {
+                return None;
+            }
+            let (chunk, chunk_start) = self.chunk_at(offset).ok()?;
+            let from = offset - chunk_start;
+            let to = (end - chunk_start).min(chunk.len());
+            let slice = chunk.get(from..to)?;
+            offset = chunk_start + to;
+            Some((slice, chunk_start + from))
+        })
+    }
+
+    /// Returns the chars in `start..end` with their byte offsets
+    pub(crate) fn chars_in(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = (usize, char)> + '_ {
+        self.chunks_in(start, end).flat_map(|(chunk, chunk_start)| {
+            chunk
+                .char_indices()
+                .map(move |(index, ch)| (chunk_start + index, ch))
+        })
+    }
+
+    /// Returns an iterator over the text of each piece in order
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            snapshot: self,
+            pieces: self.tree.iter(),
+        }
+    }
+
+    /// Returns an iterator over the content of each line, without line breaks
+    pub fn lines(&self) -> Lines<'_> {
+        Lines {
+            chunks: self.chunks(),
+            pending: "",
+            done: false,
+        }
+    }
+}
+
+/// Iterator over the text of each piece of a snapshot
+#[derive(Debug, Clone)]
+pub struct Chunks<'a> {
+    snapshot: &'a TextSnapshot,
+    pieces: Pieces<'a>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let piece = self.pieces.next()?;
+            if piece.is_empty() {
+                continue;
+            }
+            return self.snapshot.get_piece_content(piece);
+        }
+    }
+}
+
+/// Iterator over the lines of a snapshot
+#[derive(Debug, Clone)]
+pub struct Lines<'a> {
+    chunks: Chunks<'a>,
+    /// Unconsumed rest of the current chunk
+    pending: &'a str,
+    done: bool,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut line = String::new();
+        loop {
+            if let Some(newline) = self.pending.find('\n') {
+                line.push_str(&self.pending[..newline]);
+                self.pending = &self.pending[newline + 1..];
+                return Some(line);
+            }
+
+            line.push_str(self.pending);
+            match self.chunks.next() {
+                Some(chunk) => self.pending = chunk,
+                None => {
+                    // The last line has no trailing line break
+                    self.pending = "";
+                    self.done = true;
+                    return Some(line);
+                }
+            }
+        }
+    }
+}
+
+impl AsRef<TextSnapshot> for TextSnapshot {
+    fn as_ref(&self) -> &TextSnapshot {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_buffer::TextBuffer;
+    use crate::types::{Position, Range};
+
+    #[test]
+    fn test_snapshot_is_unchanged_by_edits() {
+        let mut buffer = TextBuffer::from_text("Hello\nWorld");
+        buffer.insert(Position::new(1, 0), "Big ").unwrap();
+        let snapshot = buffer.snapshot();
+
+        buffer.insert(Position::new(0, 5), ", there").unwrap();
+        buffer
+            .delete(Range::new(Position::new(1, 0), Position::new(1, 4)))
+            .unwrap();
+
+        assert_eq!(buffer.get_all_text(), "Hello, there\nWorld");
+        assert_eq!(snapshot.get_all_text(), "Hello\nBig World");
+        assert_eq!(snapshot.get_line_content(1).unwrap(), "Big World");
+        let range = Range::new(Position::new(0, 3), Position::new(1, 3));
+        assert_eq!(snapshot.get_text_in_range(range).unwrap(), "lo\nBig");
+    }
+
+    #[test]
+    fn test_snapshot_shares_buffers() {
+        let mut buffer = TextBuffer::from_text("Hello");
+        buffer.insert(Position::new(0, 5), "!").unwrap();
+        let snapshot = buffer.snapshot();
+        buffer.insert(Position::new(0, 6), "?").unwrap();
+
+        let text = buffer.snapshot();
+        assert!(std::sync::Arc::ptr_eq(
+            &snapshot.original_buffers[0],
+            &text.original_buffers[0]
+        ));
+        // The shared added buffer was copied before the append
+        assert_eq!(snapshot.added_buffers[0].content, "!");
+        assert_eq!(text.added_buffers[0].content, "!?");
+    }
+
+    #[test]
+    fn test_snapshot_per_edit_keeps_added_buffers_bounded() {
+        let mut buffer = TextBuffer::new();
+        let mut snapshots = Vec::new();
+        for i in 0..1000 {
+            buffer.insert(Position::new(0, i), "x").unwrap();
+            snapshots.push(buffer.snapshot());
+        }
+
+        assert_eq!(buffer.snapshot().added_buffers.len(), 1);
+        assert_eq!(snapshots[0].get_all_text(), "x");
+        assert_eq!(snapshots[999].get_all_text().len(), 1000);
+    }
+
+    #[test]
+    fn test_snapshot_shares_tree_nodes() {
+        let mut buffer = TextBuffer::new();
+        for i in 0..100 {
+            buffer.insert(Position::new(i, 0), "line\n").unwrap();
+        }
+        let snapshot = buffer.snapshot();
+        let current = buffer.snapshot();
+        assert!(std::sync::Arc::ptr_eq(
+            snapshot.tree.root.as_ref().unwrap(),
+            current.tree.root.as_ref().unwrap()
+        ));
+
+        buffer.insert(Position::new(99, 0), "last ").unwrap();
+        assert_eq!(snapshot.line_count(), 101);
+        assert_eq!(snapshot.get_line_content(99).unwrap(), "line");
+        assert_eq!(buffer.get_line_content(99).unwrap(), "last line");
+    }
+
+    #[test]
+    fn test_snapshot_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let snapshot = TextBuffer::from_text("text").snapshot();
+        assert_send_sync(&snapshot);
+
+        let handle = std::thread::spawn(move || snapshot.get_all_text());
+        assert_eq!(handle.join().unwrap(), "text");
+    }
+
+    #[test]
+    fn test_chunks_and_lines() {
+        let mut buffer = TextBuffer::from_text("one\ntwo");
+        buffer.insert(Position::new(1, 0), "2\n").unwrap();
+        buffer.insert(Position::new(0, 3), " 1").unwrap();
+        let snapshot = buffer.snapshot();
+
+        assert_eq!(snapshot.chunks().collect::<String>(), "one 1\n2\ntwo");
+        let lines: Vec<String> = snapshot.lines().collect();
+        assert_eq!(lines, vec!["one 1", "2", "two"]);
+
+        let trailing = TextBuffer::from_text("a\n").snapshot();
+        assert_eq!(trailing.lines().collect::<Vec<_>>(), vec!["a", ""]);
+        assert_eq!(TextBuffer::new().snapshot().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_line_ending() {
+        assert_eq!(TextBuffer::from_text("a\r\nb\nc").line_ending(), "\r\n");
+        assert_eq!(TextBuffer::from_text("a\nb\r\n").line_ending(), "\n");
+        // A CR without a line break after it is part of the content
+        assert_eq!(TextBuffer::from_text("a\r").line_ending(), "\n");
+        assert_eq!(TextBuffer::new().line_ending(), "\n");
+    }
+}
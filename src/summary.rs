@@ -0,0 +1,207 @@
+//! Summary module for the aggregates maintained by the piece tree
+//!
+//! Every node of the tree caches a summary of its piece and of its subtrees.
+//! Summaries form a monoid: they start from `Default` and are combined left
+//! to right with `add_summary`. A metric projects one dimension out of a
+//! summary, which lets the tree descend by bytes, line breaks or any
+//! user-defined measure.
+//!
+//! The framework is generic at the level of `RBTree<S>` only: the tree backing
+//! a TextBuffer or TextSnapshot always caches the built-in `TextSummary`, so
+//! user-defined summaries apply to standalone trees of pieces.
+
+use std::fmt;
+
+/// Aggregate information about a run of text
+pub trait Summary: Clone + Default + fmt::Debug {
+    /// Computes the summary of a piece of text
+    fn from_text(text: &str) -> Self;
+
+    /// Appends the summary of the text following this one
+    fn add_summary(&mut self, other: &Self);
+}
+
+/// A dimension of a summary that grows monotonically along the text
+pub trait Metric<S: Summary> {
+    /// Returns the size of the summarized text in this dimension
+    fn measure(summary: &S) -> usize;
+}
+
+/// Built-in summary of a run of text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextSummary {
+    /// Length in bytes
+    pub bytes: usize,
+    /// Number of line breaks
+    pub line_breaks: usize,
+    /// Number of chars (Unicode scalar values)
+    pub chars: usize,
+    /// Number of UTF-16 code units
+    pub utf16: usize,
+}
+
+impl TextSummary {
+    /// Computes the summary of a single char
+    pub fn from_char(ch: char) -> Self {
+        Self {
+            bytes: ch.len_utf8(),
+            line_breaks: usize::from(ch == '\n'),
+            chars: 1,
+            utf16: ch.len_utf16(),
+        }
+    }
+}
+
+impl Summary for TextSummary {
+    fn from_text(text: &str) -> Self {
+        let mut summary = Self {
+            bytes: text.len(),
+            ..Self::default()
+        };
+        for ch in text.chars() {
+            summary.line_breaks += usize::from(ch == '\n');
+            summary.chars += 1;
+            summary.utf16 += ch.len_utf16();
+        }
+        summary
+    }
+
+    fn add_summary(&mut self, other: &Self) {
+        self.bytes += other.bytes;
+        self.line_breaks += other.line_breaks;
+        self.chars += other.chars;
+        self.utf16 += other.utf16;
+    }
+}
+
+/// Pairs of summaries are maintained side by side, which is how user-defined
+/// metrics are added next to the built-in ones in an `RBTree`
+impl<A: Summary, B: Summary> Summary for (A, B) {
+    fn from_text(text: &str) -> Self {
+        (A::from_text(text), B::from_text(text))
+    }
+
+    fn add_summary(&mut self, other: &Self) {
+        self.0.add_summary(&other.0);
+        self.1.add_summary(&other.1);
+    }
+}
+
+/// Metric counting bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bytes;
+
+/// Metric counting line breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineBreaks;
+
+/// Metric counting chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chars;
+
+/// Metric counting UTF-16 code units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utf16;
+
+impl Metric<TextSummary> for Bytes {
+    fn measure(summary: &TextSummary) -> usize {
+        summary.bytes
+    }
+}
+
+impl Metric<TextSummary> for LineBreaks {
+    fn measure(summary: &TextSummary) -> usize {
+        summary.line_breaks
+    }
+}
+
+impl Metric<TextSummary> for Chars {
+    fn measure(summary: &TextSummary) -> usize {
+        summary.chars
+    }
+}
+
+impl Metric<TextSummary> for Utf16 {
+    fn measure(summary: &TextSummary) -> usize {
+        summary.utf16
+    }
+}
+
+impl<A: Summary, B: Summary> Metric<(A, B)> for Bytes
+where
+    Bytes: Metric<A>,
+{
+    fn measure(summary: &(A, B)) -> usize {
+        Bytes::measure(&summary.0)
+    }
+}
+
+impl<A: Summary, B: Summary> Metric<(A, B)> for LineBreaks
+where
+    LineBreaks: Metric<A>,
+{
+    fn measure(summary: &(A, B)) -> usize {
+        LineBreaks::measure(&summary.0)
+    }
+}
+
+impl<A: Summary, B: Summary> Metric<(A, B)> for Chars
+where
+    Chars: Metric<A>,
+{
+    fn measure(summary: &(A, B)) -> usize {
+        Chars::measure(&summary.0)
+    }
+}
+
+impl<A: Summary, B: Summary> Metric<(A, B)> for Utf16
+where
+    Utf16: Metric<A>,
+{
+    fn measure(summary: &(A, B)) -> usize {
+        Utf16::measure(&summary.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_summary() {
+        let mut summary = TextSummary::from_text("héllo\nworld");
+        assert_eq!(summary.bytes, 12);
+        assert_eq!(summary.line_breaks, 1);
+        assert_eq!(summary.chars, 11);
+        assert_eq!(summary.utf16, 11);
+
+        summary.add_summary(&TextSummary::from_text("\n🦀"));
+        assert_eq!(Bytes::measure(&summary), 17);
+        assert_eq!(LineBreaks::measure(&summary), 2);
+        assert_eq!(Chars::measure(&summary), 13);
+        assert_eq!(Utf16::measure(&summary), 14);
+        assert_eq!(TextSummary::from_char('🦀'), TextSummary::from_text("🦀"));
+    }
+
+    #[test]
+    fn test_pair_summary() {
+        #[derive(Debug, Clone, Default)]
+        struct Vowels(usize);
+
+        impl Summary for Vowels {
+            fn from_text(text: &str) -> Self {
+                Vowels(text.chars().filter(|c| "aeiou".contains(*c)).count())
+            }
+
+            fn add_summary(&mut self, other: &Self) {
+                self.0 += other.0;
+            }
+        }
+
+        let mut summary = <(TextSummary, Vowels)>::from_text("piece\n");
+        summary.add_summary(&<(TextSummary, Vowels)>::from_text("tree"));
+        assert_eq!(Bytes::measure(&summary), 10);
+        assert_eq!(LineBreaks::measure(&summary), 1);
+        assert_eq!(summary.1 .0, 5);
+    }
+}
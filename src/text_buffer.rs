@@ -3,10 +3,60 @@
 //! This module provides the primary TextBuffer interface that combines
 //! buffers, pieces, and red-black tree to create an efficient text editor backend.
 
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::block;
+use crate::bracket::{BracketConfig, BracketStates};
 use crate::buffer::Buffer;
+use crate::cache::QueryCache;
+use crate::change_log::ChangeLog;
+use crate::decoration::{
+    Decoration, DecorationId, DecorationPayload, DecorationTree, TrackedRangeStickiness,
+};
+use crate::event::{ContentChange, ContentChangeEvent, Listeners, SubscriptionId};
+use crate::fold_map::{FoldId, FoldMap};
+use crate::indentation::{self, FoldingRange, IndentCache, IndentKind, IndentStyle};
+use crate::marker::{MarkerId, MarkerTree, Stickiness};
 use crate::piece::{utils, Piece};
 use crate::red_black_tree::RBTree;
-use crate::types::{Position, Range, TextBufferResult};
+use crate::snapshot::{Chunks, Lines, TextSnapshot};
+use crate::summary::{Summary, TextSummary};
+use crate::transform::{self, Transform};
+use crate::types::{ColumnUnit, EditOperation, Position, Range, TextBufferResult};
+use crate::whitespace;
+use crate::word::DEFAULT_WORD_SEPARATORS;
+
+/// Largest piece created from a single chunk of text, in bytes
+///
+/// Lookups scan at most one piece after descending the tree, so bounding the
+/// piece size keeps them logarithmic even for large files.
+const MAX_PIECE_LENGTH: usize = 64 * 1024;
+
+/// Largest added buffer, in bytes
+///
+/// Snapshots share the added buffers. The first edit after a snapshot copies
+/// the last buffer only, so bounding its size bounds the cost of that copy
+/// while full buffers stay shared.
+const MAX_ADDED_BUFFER_LENGTH: usize = MAX_PIECE_LENGTH;
+
+/// Splits text into parts of at most `MAX_PIECE_LENGTH` bytes on char
+/// boundaries
+fn bounded_chunks(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut end = rest.len().min(MAX_PIECE_LENGTH);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, tail) = rest.split_at(end);
+        rest = tail;
+        Some(part)
+    })
+}
 
 /// Builder for creating TextBuffer instances
 #[derive(Debug)]
@@ -17,6 +67,8 @@ pub struct TextBufferBuilder {
     added_buffers: Vec<Buffer>,
     /// Pieces representing the text structure
     pieces: Vec<Piece>,
+    /// Whether `build` detects the indentation style
+    detect_indentation: bool,
 }
 
 impl TextBufferBuilder {
@@ -26,9 +78,18 @@ impl TextBufferBuilder {
             original_buffers: Vec::new(),
             added_buffers: vec![Buffer::new()], // Always have one added buffer
             pieces: Vec::new(),
+            detect_indentation: false,
         }
     }
 
+    /// Sets whether the built buffer detects its indentation style
+    ///
+    /// The result is available from `TextBuffer::indent_style`.
+    pub fn detect_indentation(&mut self, detect: bool) -> &mut Self {
+        self.detect_indentation = detect;
+        self
+    }
+
     /// Accepts a chunk of text (typically from file reading)
     pub fn accept_chunk(&mut self, text: &str) -> &mut Self {
         if text.is_empty() {
@@ -36,27 +97,20 @@ impl TextBufferBuilder {
         }
 
         // Add to the last original buffer or create a new one
-        if let Some(last_buffer) = self.original_buffers.last_mut() {
-            let start_offset = last_buffer.len();
-            last_buffer.append(text);
-
-            // Create a piece for this chunk
-            let line_breaks = utils::count_line_breaks(text);
-            let piece = Piece::original(
-                self.original_buffers.len() - 1,
-                start_offset,
-                text.len(),
-                line_breaks,
-            );
-            self.pieces.push(piece);
-        } else {
-            // First chunk - create the first original buffer
-            let buffer = Buffer::from_text(text);
-            let line_breaks = utils::count_line_breaks(text);
-            let piece = Piece::original(0, 0, text.len(), line_breaks);
-
-            self.original_buffers.push(buffer);
+        if self.original_buffers.is_empty() {
+            self.original_buffers.push(Buffer::new());
+        }
+        let buffer_index = self.original_buffers.len() - 1;
+        let last_buffer = &mut self.original_buffers[buffer_index];
+        let mut start_offset = last_buffer.len();
+        last_buffer.append(text);
+
+        // Create pieces for this chunk
+        for part in bounded_chunks(text) {
+            let line_breaks = utils::count_line_breaks(part);
+            let piece = Piece::original(buffer_index, start_offset, part.len(), line_breaks);
             self.pieces.push(piece);
+            start_offset += part.len();
         }
 
         self
@@ -68,30 +122,34 @@ impl TextBufferBuilder {
 
         // Insert all pieces into the tree
         for piece in self.pieces {
-            tree.insert(piece);
+            let summary = utils::get_piece_content(&piece, &self.original_buffers, &[])
+                .map(TextSummary::from_text)
+                .unwrap_or_default();
+            tree.insert(piece, summary);
         }
 
+        let mut original_buffers = self.original_buffers;
+
         // If no pieces were added, create an empty piece
         if tree.is_empty() {
             let empty_piece = Piece::original(0, 0, 0, 0);
-            tree.insert(empty_piece);
+            tree.insert(empty_piece, TextSummary::default());
 
             // Ensure we have at least one original buffer
-            if self.original_buffers.is_empty() {
-                let original_buffers = vec![Buffer::new()];
-                return TextBuffer {
-                    original_buffers,
-                    added_buffers: self.added_buffers,
-                    tree,
-                };
+            if original_buffers.is_empty() {
+                original_buffers.push(Buffer::new());
             }
         }
 
-        TextBuffer {
-            original_buffers: self.original_buffers,
-            added_buffers: self.added_buffers,
+        let mut buffer = TextBuffer::with_text(TextSnapshot::new(
+            original_buffers.into_iter().map(Arc::new).collect(),
+            self.added_buffers.into_iter().map(Arc::new).collect(),
             tree,
+        ));
+        if self.detect_indentation {
+            buffer.indent_style = buffer.detect_indentation().ok();
         }
+        buffer
     }
 }
 
@@ -102,24 +160,48 @@ impl Default for TextBufferBuilder {
 }
 
 /// High-performance text buffer using piece tree data structure
+///
+/// Cloning is O(1): the text is persistent and the markers, decorations,
+/// folds, caches and change log are shared until either copy changes them.
+/// Clones do not inherit listeners.
 #[derive(Debug, Clone)]
 pub struct TextBuffer {
-    /// Original buffers (read-only content from files)
-    original_buffers: Vec<Buffer>,
-    /// Added buffers (append-only content from edits)
-    added_buffers: Vec<Buffer>,
-    /// Red-black tree storing the pieces
-    tree: RBTree,
+    /// Buffers and piece tree holding the current text
+    text: TextSnapshot,
+    /// Markers that follow edits
+    markers: Arc<MarkerTree>,
+    /// Decorated ranges that follow edits
+    decorations: Arc<DecorationTree<DecorationPayload>>,
+    /// Folded ranges that follow edits
+    folds: Arc<DecorationTree<()>>,
+    /// Indentation of each line, measured on demand
+    indent_cache: QueryCache<IndentCache>,
+    /// Bracket pairs and the strings and comments that hide them
+    bracket_config: BracketConfig,
+    /// Lexer state at the start of each line, computed on demand
+    bracket_states: QueryCache<BracketStates>,
+    /// Chars that end a word besides whitespace
+    word_separators: String,
+    /// Indentation style detected when the buffer was built, or set
+    indent_style: Option<IndentStyle>,
+    /// Chars after which a new line is indented one level further
+    indent_openers: String,
+    /// Version id, advanced once per edit or batch
+    version_id: u64,
+    /// Line spans changed by recent versions
+    change_log: Arc<ChangeLog>,
+    /// Listeners notified after each change
+    listeners: Listeners,
 }
 
 impl TextBuffer {
     /// Creates a new empty TextBuffer
     pub fn new() -> Self {
-        Self {
-            original_buffers: vec![Buffer::new()],
-            added_buffers: vec![Buffer::new()],
-            tree: RBTree::new(),
-        }
+        Self::with_text(TextSnapshot::new(
+            vec![Arc::new(Buffer::new())],
+            vec![Arc::new(Buffer::new())],
+            RBTree::new(),
+        ))
     }
 
     /// Creates a TextBuffer from text content
@@ -129,67 +211,267 @@ impl TextBuffer {
         builder.build()
     }
 
-    /// Returns the total number of lines in the buffer
-    pub fn line_count(&self) -> usize {
-        if self.tree.is_empty() {
-            return 1; // Always at least one line
+    /// Creates a TextBuffer around existing buffers and pieces
+    fn with_text(text: TextSnapshot) -> Self {
+        Self {
+            text,
+            markers: Arc::new(MarkerTree::new()),
+            decorations: Arc::new(DecorationTree::new()),
+            folds: Arc::new(DecorationTree::new()),
+            indent_cache: QueryCache::default(),
+            bracket_config: BracketConfig::default(),
+            bracket_states: QueryCache::default(),
+            word_separators: DEFAULT_WORD_SEPARATORS.to_string(),
+            indent_style: None,
+            indent_openers: "{[(:".to_string(),
+            version_id: 0,
+            change_log: Arc::new(ChangeLog::new(0)),
+            listeners: Listeners::new(),
         }
+    }
+
+    /// Returns a read-only view of the current text
+    ///
+    /// The snapshot shares the buffers with this TextBuffer and keeps its
+    /// content when the buffer is edited afterwards.
+    pub fn snapshot(&self) -> TextSnapshot {
+        self.text.clone()
+    }
 
-        // Count total line breaks and add 1
-        self.tree.total_line_breaks() + 1
+    /// Returns the total number of lines in the buffer
+    pub fn line_count(&self) -> usize {
+        self.text.line_count()
     }
 
     /// Returns the total length of the buffer in bytes
     pub fn length(&self) -> usize {
-        self.tree.total_length()
+        self.text.length()
     }
 
     /// Returns true if the buffer is empty
     pub fn is_empty(&self) -> bool {
-        self.length() == 0
+        self.text.is_empty()
     }
 
     /// Gets the content of a specific line (0-indexed)
     pub fn get_line_content(&self, line: usize) -> TextBufferResult<String> {
-        if line >= self.line_count() {
-            return Err(format!("Line {line} out of bounds"));
-        }
+        self.text.get_line_content(line)
+    }
 
-        let mut content = String::new();
-        let mut current_line = 0;
-        let pieces = self.tree.collect_pieces();
-
-        for piece in pieces {
-            let piece_content = self
-                .get_piece_content(piece)
-                .ok_or_else(|| "Failed to get piece content".to_string())?;
-
-            for ch in piece_content.chars() {
-                if current_line == line {
-                    if ch == '\n' {
-                        break;
-                    }
-                    content.push(ch);
-                } else if ch == '\n' {
-                    current_line += 1;
-                    if current_line > line {
-                        break;
-                    }
-                }
-            }
+    /// Returns the line break of the text: CRLF when its first line ends
+    /// with one, LF otherwise
+    pub fn line_ending(&self) -> &'static str {
+        self.text.line_ending()
+    }
 
-            if current_line > line {
-                break;
-            }
-        }
+    /// Gets the length of a specific line (0-indexed), in the column unit
+    pub fn get_line_length(&self, line: usize) -> TextBufferResult<usize> {
+        self.text.get_line_length(line)
+    }
 
-        Ok(content)
+    /// Gets the length of a specific line (0-indexed), in the given unit
+    pub fn get_line_length_in(&self, line: usize, unit: ColumnUnit) -> TextBufferResult<usize> {
+        self.text.get_line_length_in(line, unit)
     }
 
-    /// Gets the length of a specific line (0-indexed)
-    pub fn get_line_length(&self, line: usize) -> TextBufferResult<usize> {
-        let content = self.get_line_content(line)?;
-        Ok(content.chars().count())
+    /// Gets text content in the specified range
+    pub fn get_text_in_range(&self, range: Range) -> TextBufferResult<String> {
+        self.text.get_text_in_range(range)
+    }
+
+    /// Gets all text content as a string
+    pub fn get_all_text(&self) -> String {
+        self.text.get_all_text()
+    }
+
+    /// Returns the unit in which position columns are measured
+    pub fn column_unit(&self) -> ColumnUnit {
+        self.text.column_unit()
+    }
+
+    /// Sets the unit in which position columns are measured
+    ///
+    /// Every API taking or returning positions follows this setting,
+    /// including change events and snapshots taken afterwards.
+    pub fn set_column_unit(&mut self, unit: ColumnUnit) {
+        self.text.set_column_unit(unit);
+    }
+
+    /// Converts a position to a byte offset, with the column in the column
+    /// unit
+    pub fn position_to_offset(&self, position: Position) -> TextBufferResult<usize> {
+        self.text.position_to_offset(position)
+    }
+
+    /// Converts a byte offset to a position, with the column in the column
+    /// unit
+    pub fn offset_to_position(&self, offset: usize) -> TextBufferResult<Position> {
+        self.text.offset_to_position(offset)
+    }
+
+    /// Converts a position whose column is measured in `unit` to a byte offset
+    pub fn position_to_offset_in(
+        &self,
+        position: Position,
+        unit: ColumnUnit,
+    ) -> TextBufferResult<usize> {
+        self.text.position_to_offset_in(position, unit)
+    }
+
+    /// Converts a byte offset to a position whose column is measured in `unit`
+    pub fn offset_to_position_in(
+        &self,
+        offset: usize,
+        unit: ColumnUnit,
+    ) -> TextBufferResult<Position> {
+        self.text.offset_to_position_in(offset, unit)
+    }
+
+    /// Converts a position with a column in UTF-16 code units to a byte offset
+    pub fn utf16_position_to_offset(&self, position: Position) -> TextBufferResult<usize> {
+        self.text.utf16_position_to_offset(position)
+    }
+
+    /// Converts a byte offset to a position with a column in UTF-16 code units
+    pub fn offset_to_utf16_position(&self, offset: usize) -> TextBufferResult<Position> {
+        self.text.offset_to_utf16_position(offset)
+    }
+
+    /// Converts a byte offset to a char index
+    pub fn byte_to_char(&self, offset: usize) -> TextBufferResult<usize> {
+        self.text.byte_to_char(offset)
+    }
+
+    /// Converts a char index to a byte offset
+    pub fn char_to_byte(&self, index: usize) -> TextBufferResult<usize> {
+        self.text.char_to_byte(index)
+    }
+
+    /// Converts a byte offset to a UTF-16 code unit index
+    pub fn byte_to_utf16(&self, offset: usize) -> TextBufferResult<usize> {
+        self.text.byte_to_utf16(offset)
+    }
+
+    /// Converts a UTF-16 code unit index to a byte offset
+    pub fn utf16_to_byte(&self, index: usize) -> TextBufferResult<usize> {
+        self.text.utf16_to_byte(index)
+    }
+
+    /// Returns true if the byte offset falls on a grapheme boundary
+    pub fn is_grapheme_boundary(&self, offset: usize) -> TextBufferResult<bool> {
+        self.text.is_grapheme_boundary(offset)
+    }
+
+    /// Returns the first grapheme boundary after the byte offset
+    pub fn next_grapheme_boundary(&self, offset: usize) -> TextBufferResult<usize> {
+        self.text.next_grapheme_boundary(offset)
+    }
+
+    /// Returns the last grapheme boundary before the byte offset
+    pub fn prev_grapheme_boundary(&self, offset: usize) -> TextBufferResult<usize> {
+        self.text.prev_grapheme_boundary(offset)
+    }
+
+    /// Moves a position to the nearest grapheme boundary
+    pub fn snap_to_grapheme(&self, position: Position) -> TextBufferResult<Position> {
+        self.text.snap_to_grapheme(position)
+    }
+
+    /// Returns the bracket pairs and delimiters used to match brackets
+    pub fn bracket_config(&self) -> &BracketConfig {
+        &self.bracket_config
+    }
+
+    /// Sets the bracket pairs and delimiters used to match brackets
+    pub fn set_bracket_config(&mut self, config: BracketConfig) {
+        self.bracket_config = config;
+        self.bracket_states.clear();
+    }
+
+    /// Returns the ranges of the bracket touching a position and of its
+    /// partner, opening bracket first
+    ///
+    /// The lexer state at the start of each line is cached between calls.
+    pub fn matching_bracket(&self, position: Position) -> TextBufferResult<Option<(Range, Range)>> {
+        self.with_bracket_states(|text, states| text.matching_bracket(position, states))
+    }
+
+    /// Returns the ranges of the innermost bracket pair around a position
+    pub fn enclosing_brackets(
+        &self,
+        position: Position,
+    ) -> TextBufferResult<Option<(Range, Range)>> {
+        self.with_bracket_states(|text, states| text.enclosing_brackets(position, states))
+    }
+
+    /// Helper method to run a bracket search with the cached lexer states
+    fn with_bracket_states<R>(&self, f: impl FnOnce(&TextSnapshot, &mut BracketStates) -> R) -> R {
+        self.bracket_states.with(
+            |_| true,
+            || BracketStates::new(&self.text, self.bracket_config.clone()),
+            |states| f(&self.text, states),
+        )
+    }
+
+    /// Returns the chars that end a word besides whitespace
+    pub fn word_separators(&self) -> &str {
+        &self.word_separators
+    }
+
+    /// Sets the chars that end a word besides whitespace
+    pub fn set_word_separators(&mut self, separators: impl Into<String>) {
+        self.word_separators = separators.into();
+    }
+
+    /// Returns the range of the word containing a position, or of the word
+    /// ending at it
+    pub fn word_at(&self, position: Position) -> TextBufferResult<Option<Range>> {
+        self.text.word_at(position, &self.word_separators)
+    }
+
+    /// Returns the start of the next word after a position, stopping at line
+    /// ends
+    pub fn next_word_start(&self, position: Position) -> TextBufferResult<Position> {
+        self.text.next_word_start(position, &self.word_separators)
+    }
+
+    /// Returns the start of the word before a position, stopping at line
+    /// starts
+    pub fn previous_word_start(&self, position: Position) -> TextBufferResult<Position> {
+        self.text
+            .previous_word_start(position, &self.word_separators)
+    }
+
+    /// Returns the end of the word containing a position or of the next
+    /// word, stopping at line ends
+    pub fn word_end(&self, position: Position) -> TextBufferResult<Position> {
+        self.text.word_end(position, &self.word_separators)
+    }
+
+    /// Returns the display column of a position, expanding tabs and counting
+    /// wide characters as two cells
+    pub fn visual_column(&self, position: Position, tab_size: usize) -> TextBufferResult<usize> {
+        self.text.visual_column(position, tab_size)
+    }
+
+    /// Returns the position on a line displayed at the given column
+    pub fn position_at_visual_column(
+        &self,
+        line: usize,
+        column: usize,
+        tab_size: usize,
+    ) -> TextBufferResult<Position> {
+        self.text.position_at_visual_column(line, column, tab_size)
+    }
+
+    /// Returns an iterator over the text of each piece in order
+    pub fn chunks(&self) -> Chunks<'_> {
+        self.text.chunks()
+    }
+
+    /// Returns an iterator over the content of each line, without line breaks
+    pub fn lines(&self) -> Lines<'_> {
+        self.text.lines()
     }
 
     /// Inserts text at the specified position
@@ -201,29 +483,8 @@ impl TextBuffer {
         // Find the offset for the position
         let offset = self.position_to_offset(position)?;
 
-        // Add text to the added buffer
-        let added_buffer = self.added_buffers.last_mut().unwrap();
-        let start_offset = added_buffer.len();
-        added_buffer.append(text);
-
-        // Create a new piece for the inserted text
-        let line_breaks = utils::count_line_breaks(text);
-        let new_piece = Piece::added(
-            self.added_buffers.len() - 1,
-            start_offset,
-            text.len(),
-            line_breaks,
-        );
-
-        // Find the piece at the insertion point and split if necessary
-        if let Some(_piece_at_offset) = self.tree.find_piece_at_offset(offset) {
-            // This is a simplified insertion - in a full implementation,
-            // you would need to split the piece and rebuild the tree
-            self.tree.insert(new_piece);
-        } else {
-            // Insert at the end
-            self.tree.insert(new_piece);
-        }
+        let change = self.replace_range(offset, offset, text)?;
+        self.emit_changes(vec![change]);
 
         Ok(())
     }
@@ -240,151 +501,644 @@ impl TextBuffer {
         // Get the text that will be deleted
         let deleted_text = self.get_text_in_range(range)?;
 
-        // This is a simplified deletion - in a full implementation,
-        // you would need to modify the tree structure to remove the range
-        // For now, we'll just return the deleted text
+        let change = self.replace_range(start_offset, end_offset, "")?;
+        self.emit_changes(vec![change]);
 
         Ok(deleted_text)
     }
 
-    /// Gets text content in the specified range
-    pub fn get_text_in_range(&self, range: Range) -> TextBufferResult<String> {
+    /// Applies several edits as one batch and returns the edits that undo it
+    ///
+    /// All ranges refer to the buffer before the batch and must not overlap.
+    /// Listeners receive a single event and the version advances once. The
+    /// returned edits refer to the buffer after the batch and are ordered by
+    /// position.
+    pub fn apply_edits(
+        &mut self,
+        edits: Vec<EditOperation>,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let mut resolved = Vec::with_capacity(edits.len());
+        for edit in edits {
+            let start_offset = self.position_to_offset(edit.range.start)?;
+            let end_offset = self.position_to_offset(edit.range.end)?;
+            if start_offset > end_offset {
+                return Err("Invalid range".to_string());
+            }
+            if start_offset == end_offset && edit.text.is_empty() {
+                continue;
+            }
+            resolved.push((start_offset, end_offset, edit));
+        }
+
+        resolved.sort_by_key(|&(start_offset, end_offset, _)| (start_offset, end_offset));
+        for pair in resolved.windows(2) {
+            if pair[0].1 > pair[1].0 {
+                return Err("Overlapping edits".to_string());
+            }
+        }
+
+        // Capture the replaced text and where each edit ends up afterwards
+        let mut inverse = Vec::with_capacity(resolved.len());
+        let mut shift = 0isize;
+        for (start_offset, end_offset, edit) in &resolved {
+            let old_text = if start_offset < end_offset {
+                self.get_text_in_range(edit.range)?
+            } else {
+                String::new()
+            };
+            let new_start = start_offset.checked_add_signed(shift).unwrap();
+            inverse.push((new_start, new_start + edit.text.len(), old_text));
+            shift += edit.text.len() as isize - (end_offset - start_offset) as isize;
+        }
+
+        // Apply from the end so earlier offsets stay valid
+        let mut changes = Vec::with_capacity(resolved.len());
+        for (start_offset, end_offset, edit) in resolved.iter().rev() {
+            changes.push(self.replace_range(*start_offset, *end_offset, &edit.text)?);
+        }
+        self.emit_changes(changes);
+
+        inverse
+            .into_iter()
+            .map(|(start_offset, end_offset, old_text)| {
+                let range = Range::new(
+                    self.offset_to_position(start_offset)?,
+                    self.offset_to_position(end_offset)?,
+                );
+                Ok(EditOperation::new(range, old_text))
+            })
+            .collect()
+    }
+
+    /// Returns the version id, advanced once per edit or batch
+    pub fn version(&self) -> u64 {
+        self.version_id
+    }
+
+    /// Returns the coalesced line ranges modified since the given version, in
+    /// current line numbers
+    ///
+    /// When the version is too old to be reconstructed, every line is reported.
+    pub fn changed_lines_since(&self, version: u64) -> Vec<std::ops::Range<usize>> {
+        if version >= self.version_id {
+            return Vec::new();
+        }
+
+        self.change_log
+            .changed_lines_since(version)
+            .unwrap_or_else(|| std::iter::once(0..self.line_count()).collect())
+    }
+
+    /// Registers a listener called synchronously after every change
+    pub fn subscribe<F>(&mut self, listener: F) -> SubscriptionId
+    where
+        F: FnMut(&ContentChangeEvent) + Send + Sync + 'static,
+    {
+        self.listeners.subscribe(Box::new(listener))
+    }
+
+    /// Removes a listener, returning true if it was registered
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.listeners.unsubscribe(id)
+    }
+
+    /// Adds a marker at the given byte offset that follows subsequent edits
+    pub fn add_marker(
+        &mut self,
+        offset: usize,
+        stickiness: Stickiness,
+    ) -> TextBufferResult<MarkerId> {
+        if offset > self.length() {
+            return Err("Offset out of bounds".to_string());
+        }
+        Ok(Arc::make_mut(&mut self.markers).add(offset, stickiness))
+    }
+
+    /// Returns the current byte offset of a marker, or None if it was removed
+    pub fn marker_position(&self, id: MarkerId) -> Option<usize> {
+        self.markers.offset(id)
+    }
+
+    /// Removes a marker, returning its last byte offset
+    pub fn remove_marker(&mut self, id: MarkerId) -> Option<usize> {
+        Arc::make_mut(&mut self.markers).remove(id)
+    }
+
+    /// Returns the number of markers in the buffer
+    pub fn marker_count(&self) -> usize {
+        self.markers.len()
+    }
+
+    /// Adds a decoration over the range with an arbitrary payload
+    pub fn add_decoration<T: Any + Send + Sync>(
+        &mut self,
+        range: Range,
+        stickiness: TrackedRangeStickiness,
+        payload: T,
+    ) -> TextBufferResult<DecorationId> {
         let start_offset = self.position_to_offset(range.start)?;
         let end_offset = self.position_to_offset(range.end)?;
 
-        if start_offset >= end_offset {
+        if start_offset > end_offset {
             return Err("Invalid range".to_string());
         }
 
-        let mut content = String::new();
-        let mut current_offset = 0;
-        let pieces = self.tree.collect_pieces();
+        Ok(Arc::make_mut(&mut self.decorations).add(
+            start_offset,
+            end_offset,
+            stickiness,
+            Arc::new(payload),
+        ))
+    }
 
-        for piece in pieces {
-            let piece_content = self
-                .get_piece_content(piece)
-                .ok_or_else(|| "Failed to get piece content".to_string())?;
+    /// Removes a decoration, returning true if it existed
+    pub fn remove_decoration(&mut self, id: DecorationId) -> bool {
+        Arc::make_mut(&mut self.decorations).remove(id).is_some()
+    }
 
-            let piece_start = current_offset;
-            let piece_end = current_offset + piece.length;
+    /// Returns the current range of a decoration, or None if it was removed
+    pub fn decoration_range(&self, id: DecorationId) -> Option<Range> {
+        let (start_offset, end_offset) = self.decorations.range(id)?;
+        let start = self.offset_to_position(start_offset).ok()?;
+        let end = self.offset_to_position(end_offset).ok()?;
+        Some(Range::new(start, end))
+    }
 
-            if piece_start >= end_offset {
-                break;
-            }
+    /// Returns the number of decorations in the buffer
+    pub fn decoration_count(&self) -> usize {
+        self.decorations.len()
+    }
 
-            if piece_end > start_offset {
-                let content_start = start_offset.saturating_sub(piece_start);
+    /// Gets the decorations intersecting the range, ordered by start
+    ///
+    /// Decorations touching either end of the range are included.
+    pub fn decorations_in_range(&self, range: Range) -> TextBufferResult<Vec<Decoration>> {
+        let start_offset = self.position_to_offset(range.start)?;
+        let end_offset = self.position_to_offset(range.end)?;
+        self.decorations_between(start_offset, end_offset)
+    }
 
-                let content_end = if end_offset < piece_end {
-                    end_offset - piece_start
-                } else {
-                    piece.length
-                };
+    /// Gets the decorations intersecting the lines `start_line..=end_line`,
+    /// ordered by start
+    pub fn decorations_in_lines(
+        &self,
+        start_line: usize,
+        end_line: usize,
+    ) -> TextBufferResult<Vec<Decoration>> {
+        let start_offset = self.position_to_offset(Position::new(start_line, 0))?;
+        let end_column = self.get_line_length(end_line)?;
+        let end_offset = self.position_to_offset(Position::new(end_line, end_column))?;
+        self.decorations_between(start_offset, end_offset)
+    }
 
-                if content_start < piece_content.len() && content_end <= piece_content.len() {
-                    content.push_str(&piece_content[content_start..content_end]);
-                }
-            }
+    /// Folds the lines after the first line of the range up to its last line
+    ///
+    /// The fold follows edits like a decoration that never grows when typing
+    /// at its edges.
+    pub fn fold(&mut self, range: Range) -> TextBufferResult<FoldId> {
+        let start_offset = self.position_to_offset(range.start)?;
+        let end_offset = self.position_to_offset(range.end)?;
 
-            current_offset = piece_end;
+        if start_offset > end_offset {
+            return Err("Invalid range".to_string());
         }
 
-        Ok(content)
+        Ok(FoldId(Arc::make_mut(&mut self.folds).add(
+            start_offset,
+            end_offset,
+            TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges,
+            (),
+        )))
     }
 
-    /// Gets all text content as a string
-    pub fn get_all_text(&self) -> String {
-        let mut content = String::new();
-        let pieces = self.tree.collect_pieces();
+    /// Removes a fold, returning true if it existed
+    pub fn unfold(&mut self, id: FoldId) -> bool {
+        Arc::make_mut(&mut self.folds).remove(id.0).is_some()
+    }
 
-        for piece in pieces {
-            if let Some(piece_content) = self.get_piece_content(piece) {
-                content.push_str(piece_content);
-            }
-        }
+    /// Removes every fold
+    pub fn unfold_all(&mut self) {
+        self.folds = Arc::new(DecorationTree::new());
+    }
 
-        content
+    /// Returns the current range of a fold, or None if it was removed
+    pub fn fold_range(&self, id: FoldId) -> Option<Range> {
+        let (start_offset, end_offset) = self.folds.range(id.0)?;
+        let start = self.offset_to_position(start_offset).ok()?;
+        let end = self.offset_to_position(end_offset).ok()?;
+        Some(Range::new(start, end))
     }
 
-    /// Converts a position to a byte offset
-    pub fn position_to_offset(&self, position: Position) -> TextBufferResult<usize> {
-        let mut current_line = 0;
-        let mut current_column = 0;
-        let mut byte_offset = 0;
-        let pieces = self.tree.collect_pieces();
-
-        for piece in pieces {
-            let piece_content = self
-                .get_piece_content(piece)
-                .ok_or_else(|| "Failed to get piece content".to_string())?;
-
-            for ch in piece_content.chars() {
-                if current_line == position.line {
-                    if current_column == position.column {
-                        return Ok(byte_offset);
-                    }
-                    if ch == '\n' {
-                        // End of line reached, position is at the end of line
-                        return Ok(byte_offset);
-                    }
-                    current_column += 1;
-                } else if ch == '\n' {
-                    current_line += 1;
-                    current_column = 0;
-                    if current_line > position.line {
-                        return Err("Position out of bounds".to_string());
-                    }
-                }
-                byte_offset += ch.len_utf8();
-            }
+    /// Returns the number of folds
+    pub fn fold_count(&self) -> usize {
+        self.folds.len()
+    }
+
+    /// Computes the mapping between buffer lines and displayed lines under
+    /// the current folds
+    pub fn fold_map(&self) -> TextBufferResult<FoldMap> {
+        let folds = self
+            .folds
+            .search(0, self.length())
+            .into_iter()
+            .map(|(_, start, end)| {
+                let first_line = self.text.summary_at(start)?.line_breaks;
+                let last_line = self.text.summary_at(end)?.line_breaks;
+                Ok((first_line, last_line))
+            })
+            .collect::<TextBufferResult<Vec<_>>>()?;
+
+        Ok(FoldMap::new(self.line_count(), folds))
+    }
+
+    /// Computes nestable folding ranges from the indentation of the lines
+    ///
+    /// A line starts a range when the lines below it are indented further;
+    /// blank lines take the indentation of their surroundings, so blank lines
+    /// ending a block belong to it. Indentation and ranges are cached between
+    /// calls, so after an edit only the block around the edited lines is
+    /// scanned again.
+    pub fn folding_ranges_by_indent(&self, tab_size: usize) -> TextBufferResult<Vec<FoldingRange>> {
+        self.indent_folding_ranges(tab_size, false)
+    }
+
+    /// Computes folding ranges from indentation for off-side rule languages
+    /// (Python, YAML)
+    ///
+    /// Ranges are found as by `folding_ranges_by_indent`, except that blank
+    /// lines ending a block are left outside of it.
+    pub fn folding_ranges_by_indent_off_side(
+        &self,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<FoldingRange>> {
+        self.indent_folding_ranges(tab_size, true)
+    }
+
+    /// Helper method to compute folding ranges with the cached indentation
+    fn indent_folding_ranges(
+        &self,
+        tab_size: usize,
+        off_side: bool,
+    ) -> TextBufferResult<Vec<FoldingRange>> {
+        self.indent_cache.with(
+            |cache| cache.tab_size() == tab_size,
+            || IndentCache::new(self.line_count(), tab_size),
+            |cache| cache.folding_ranges(&self.text, off_side),
+        )
+    }
+
+    /// Returns the range of each line covered by the block between two
+    /// positions, spanning the display columns between them
+    pub fn block_ranges(
+        &self,
+        start: Position,
+        end: Position,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<Range>> {
+        self.text.block_ranges(start, end, tab_size)
+    }
+
+    /// Returns the text of each line covered by the block between two
+    /// positions
+    pub fn block_text(
+        &self,
+        start: Position,
+        end: Position,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<String>> {
+        self.text.block_text(start, end, tab_size)
+    }
+
+    /// Inserts one line of text per line at the display column of a
+    /// position, starting on its line
+    ///
+    /// Lines past the end of the text are appended. With `pad`, lines ending
+    /// before the column are padded with spaces. The lines are changed in
+    /// one batch; the returned edits undo it.
+    pub fn insert_block(
+        &mut self,
+        position: Position,
+        lines: &[&str],
+        tab_size: usize,
+        pad: bool,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let edits = block::insert_block(&self.text, position, lines, tab_size, pad)?;
+        self.apply_edits(edits)
+    }
+
+    /// Replaces the block between two positions with one line of text per
+    /// block line, or with the same text on every line
+    ///
+    /// With `pad`, lines ending before the block are padded with spaces up to
+    /// its first column. The lines are changed in one batch; the returned
+    /// edits undo it.
+    pub fn replace_block(
+        &mut self,
+        start: Position,
+        end: Position,
+        lines: &[&str],
+        tab_size: usize,
+        pad: bool,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let edits = block::replace_block(&self.text, start, end, lines, tab_size, pad)?;
+        self.apply_edits(edits)
+    }
+
+    /// Deletes the block between two positions in one batch
+    pub fn delete_block(
+        &mut self,
+        start: Position,
+        end: Position,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        self.replace_block(start, end, &[""], tab_size, false)
+    }
+
+    /// Detects the indentation style from the leading whitespace of the lines
+    pub fn detect_indentation(&self) -> TextBufferResult<IndentStyle> {
+        indentation::detect_indentation(&self.text)
+    }
+
+    /// Returns the indentation style detected by the builder, if requested,
+    /// or set with `set_indent_style`
+    ///
+    /// Indent commands fall back to `IndentStyle::default()` without one.
+    pub fn indent_style(&self) -> Option<IndentStyle> {
+        self.indent_style
+    }
+
+    /// Sets the indentation style used by indent commands
+    pub fn set_indent_style(&mut self, style: IndentStyle) {
+        self.indent_style = Some(style);
+    }
+
+    /// Returns the chars after which a new line is indented one level further
+    pub fn indent_openers(&self) -> &str {
+        &self.indent_openers
+    }
+
+    /// Sets the chars after which a new line is indented one level further
+    pub fn set_indent_openers(&mut self, openers: impl Into<String>) {
+        self.indent_openers = openers.into();
+    }
+
+    /// Rewrites the leading indentation of the lines of a range with spaces
+    ///
+    /// Tabs expand to the next multiple of `tab_size`. The lines are changed
+    /// in one batch; the returned edits undo it.
+    pub fn indentation_to_spaces(
+        &mut self,
+        range: Range,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        self.convert_indentation(range, IndentKind::Spaces, tab_size)
+    }
+
+    /// Rewrites the leading indentation of the lines of a range with tabs
+    ///
+    /// Columns that do not fill a whole tab stay as spaces. The lines are
+    /// changed in one batch; the returned edits undo it.
+    pub fn indentation_to_tabs(
+        &mut self,
+        range: Range,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        self.convert_indentation(range, IndentKind::Tabs, tab_size)
+    }
+
+    /// Returns the lines holding text inserted since the text was loaded, in
+    /// order
+    pub fn modified_lines(&self) -> Vec<usize> {
+        self.text.modified_lines()
+    }
+
+    /// Removes trailing whitespace from every line in one batch, or only from
+    /// the lines modified since the text was loaded
+    ///
+    /// A CR ending a line is kept. The returned edits undo the batch.
+    pub fn trim_trailing_whitespace(
+        &mut self,
+        modified_only: bool,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        let edits = whitespace::trim_trailing_whitespace(&self.text, modified_only)?;
+        self.apply_edits(edits)
+    }
+
+    /// Appends a line break to a non-empty text not ending with one
+    ///
+    /// The break is CRLF when the first line ends with one. Returns the edits
+    /// that undo it, empty if nothing changed.
+    pub fn ensure_final_newline(&mut self) -> TextBufferResult<Vec<EditOperation>> {
+        let edits = whitespace::ensure_final_newline(&self.text)?;
+        self.apply_edits(edits)
+    }
+
+    /// Removes the blank lines at the end of the text, keeping the line
+    /// break after the last non-blank line
+    ///
+    /// Returns the edits that undo it, empty if nothing changed.
+    pub fn collapse_trailing_blank_lines(&mut self) -> TextBufferResult<Vec<EditOperation>> {
+        let edits = whitespace::collapse_trailing_blank_lines(&self.text)?;
+        self.apply_edits(edits)
+    }
+
+    /// Transforms the text of a range in one edit
+    ///
+    /// Returns the range holding the transformed text and the edits that
+    /// undo it. When the text would not change no edit is made, the range is
+    /// returned as given and the undo edits are empty.
+    pub fn transform(
+        &mut self,
+        range: Range,
+        transform: Transform,
+    ) -> TextBufferResult<(Range, Vec<EditOperation>)> {
+        let Some((start, end, text)) = transform::transform(&self.text, range, transform)? else {
+            return Ok((range, Vec::new()));
+        };
+
+        let edit = EditOperation::new(
+            Range::new(
+                self.offset_to_position(start)?,
+                self.offset_to_position(end)?,
+            ),
+            text,
+        );
+        let undo = self.apply_edits(vec![edit])?;
+        let changed = undo.first().map_or(range, |edit| edit.range);
+        Ok((changed, undo))
+    }
+
+    /// Helper method to convert the indentation of the lines of a range
+    fn convert_indentation(
+        &mut self,
+        range: Range,
+        kind: IndentKind,
+        tab_size: usize,
+    ) -> TextBufferResult<Vec<EditOperation>> {
+        if range.start.line > range.end.line {
+            return Err("Invalid range".to_string());
         }
+        let edits = indentation::convert_indentation(
+            &self.text,
+            range.start.line,
+            range.end.line,
+            kind,
+            tab_size,
+        )?;
+        self.apply_edits(edits)
+    }
 
-        // Handle end of buffer case
-        if current_line == position.line && current_column == position.column {
-            Ok(byte_offset)
-        } else {
-            Err("Position out of bounds".to_string())
+    /// Helper method to query decorations between two byte offsets
+    fn decorations_between(
+        &self,
+        start_offset: usize,
+        end_offset: usize,
+    ) -> TextBufferResult<Vec<Decoration>> {
+        if start_offset > end_offset {
+            return Err("Invalid range".to_string());
         }
+
+        self.decorations
+            .search(start_offset, end_offset)
+            .into_iter()
+            .map(|(id, start, end)| {
+                let range = Range::new(
+                    self.offset_to_position(start)?,
+                    self.offset_to_position(end)?,
+                );
+                let payload = self
+                    .decorations
+                    .payload(id)
+                    .cloned()
+                    .ok_or_else(|| "Failed to get decoration payload".to_string())?;
+                Ok(Decoration::new(id, range, payload))
+            })
+            .collect()
     }
 
-    /// Converts a byte offset to a position
-    pub fn offset_to_position(&self, offset: usize) -> TextBufferResult<Position> {
-        let mut current_line = 0;
-        let mut current_column = 0;
-        let mut current_offset = 0;
-        let pieces = self.tree.collect_pieces();
-
-        for piece in pieces {
-            let piece_content = self
-                .get_piece_content(piece)
-                .ok_or_else(|| "Failed to get piece content".to_string())?;
-
-            for ch in piece_content.chars() {
-                if current_offset == offset {
-                    return Ok(Position::new(current_line, current_column));
-                }
+    /// Replaces the bytes in `start_offset..end_offset` with text, keeping
+    /// markers and decorations in sync, and describes the change
+    fn replace_range(
+        &mut self,
+        start_offset: usize,
+        end_offset: usize,
+        text: &str,
+    ) -> TextBufferResult<ContentChange> {
+        let range = Range::new(
+            self.offset_to_position(start_offset)?,
+            self.offset_to_position(end_offset)?,
+        );
 
-                if ch == '\n' {
-                    current_line += 1;
-                    current_column = 0;
-                } else {
-                    current_column += 1;
-                }
+        // Make both ends of the range fall on piece boundaries, then drop
+        // every piece in between
+        self.split_piece_at(start_offset)?;
+        self.split_piece_at(end_offset)?;
+
+        let mut remaining = end_offset - start_offset;
+        while remaining > 0 {
+            let removed = self
+                .text
+                .tree
+                .remove_at(start_offset)
+                .ok_or_else(|| "Failed to remove piece".to_string())?;
+            remaining -= removed.length;
+        }
 
-                current_offset += ch.len_utf8();
+        let line_breaks = utils::count_line_breaks(text);
+        if !text.is_empty() {
+            // Append each part to the last added buffer, starting a new one
+            // when it is full. A last buffer shared with a snapshot is copied
+            // on write; snapshots only read the bytes they already had.
+            let added_buffers = Arc::make_mut(&mut self.text.added_buffers);
+            let mut piece_offset = start_offset;
+            for part in bounded_chunks(text) {
+                let last = added_buffers.last().unwrap();
+                if last.len() + part.len() > MAX_ADDED_BUFFER_LENGTH {
+                    added_buffers.push(Arc::new(Buffer::new()));
+                }
+                let buffer_index = added_buffers.len() - 1;
+                let added_buffer = Arc::make_mut(&mut added_buffers[buffer_index]);
+                let buffer_offset = added_buffer.len();
+                added_buffer.append(part);
+
+                let summary = TextSummary::from_text(part);
+                let new_piece =
+                    Piece::added(buffer_index, buffer_offset, part.len(), summary.line_breaks);
+                self.text.tree.insert_at(piece_offset, new_piece, summary);
+                piece_offset += part.len();
             }
         }
 
-        if current_offset == offset {
-            Ok(Position::new(current_line, current_column))
-        } else {
-            Err("Offset out of bounds".to_string())
+        let markers = Arc::make_mut(&mut self.markers);
+        markers.apply_delete(start_offset, end_offset);
+        markers.apply_insert(start_offset, text.len());
+        Arc::make_mut(&mut self.decorations).apply_edit(
+            start_offset,
+            end_offset - start_offset,
+            text.len(),
+        );
+        Arc::make_mut(&mut self.folds).apply_edit(
+            start_offset,
+            end_offset - start_offset,
+            text.len(),
+        );
+        self.indent_cache
+            .update(|cache| cache.splice(range.start.line, range.end.line, line_breaks + 1));
+        self.bracket_states
+            .update(|states| states.splice(range.start.line, range.end.line, line_breaks + 1));
+
+        let removed_lines = range.end.line - range.start.line;
+        Ok(ContentChange {
+            range,
+            start_offset,
+            old_end_offset: end_offset,
+            new_end_offset: start_offset + text.len(),
+            text: text.to_string(),
+            line_delta: line_breaks as isize - removed_lines as isize,
+        })
+    }
+
+    /// Advances the version and notifies listeners about applied changes
+    fn emit_changes(&mut self, changes: Vec<ContentChange>) {
+        if changes.is_empty() {
+            return;
         }
+
+        self.version_id += 1;
+        Arc::make_mut(&mut self.change_log).record(self.version_id, &changes);
+        let event = ContentChangeEvent {
+            changes,
+            version_id: self.version_id,
+        };
+        self.listeners.emit(&event);
     }
 
-    /// Helper method to get piece content from the appropriate buffer
-    fn get_piece_content(&self, piece: &Piece) -> Option<&str> {
-        utils::get_piece_content(piece, &self.original_buffers, &self.added_buffers)
+    /// Splits the piece containing the offset so that a piece boundary falls on it
+    fn split_piece_at(&mut self, offset: usize) -> TextBufferResult<()> {
+        let (piece, piece_start) = match self.text.tree.locate_piece(offset) {
+            Some((piece, piece_start)) if piece_start < offset => (piece.clone(), piece_start),
+            _ => return Ok(()),
+        };
+
+        let split_offset = offset - piece_start;
+        let piece_content = self
+            .text
+            .get_piece_content(&piece)
+            .ok_or_else(|| "Failed to get piece content".to_string())?;
+        let (left_text, right_text) = piece_content.split_at(split_offset);
+        let left_summary = TextSummary::from_text(left_text);
+        let right_summary = TextSummary::from_text(right_text);
+        let (left, right) = piece.split_at(split_offset, left_summary.line_breaks);
+
+        self.text.tree.replace_at(piece_start, left, left_summary);
+        self.text.tree.insert_at(offset, right, right_summary);
+        Ok(())
+    }
+}
+
+impl AsRef<TextSnapshot> for TextBuffer {
+    fn as_ref(&self) -> &TextSnapshot {
+        &self.text
     }
 }
 
@@ -450,6 +1204,86 @@ mod tests {
         assert_eq!(buffer.offset_to_position(11).unwrap(), Position::new(1, 5));
     }
 
+    #[test]
+    fn test_unit_conversions() {
+        let mut buffer = TextBuffer::from_text("aé🦀\nx");
+        buffer.insert(Position::new(1, 0), "日\n").unwrap();
+
+        // "aé🦀\n日\nx": bytes 0,1,3,7,8,11,12
+        assert_eq!(buffer.byte_to_char(7).unwrap(), 3);
+        assert_eq!(buffer.char_to_byte(5).unwrap(), 11);
+        assert_eq!(buffer.byte_to_utf16(7).unwrap(), 4);
+        assert_eq!(buffer.utf16_to_byte(5).unwrap(), 8);
+        // Between the halves of the surrogate pair
+        assert_eq!(buffer.utf16_to_byte(3).unwrap(), 3);
+        assert!(buffer.byte_to_char(2).is_err());
+        assert!(buffer.char_to_byte(9).is_err());
+
+        let end = Position::new(0, 4);
+        assert_eq!(buffer.utf16_position_to_offset(end).unwrap(), 7);
+        assert_eq!(buffer.offset_to_utf16_position(7).unwrap(), end);
+        assert_eq!(buffer.offset_to_position(7).unwrap(), Position::new(0, 3));
+        assert_eq!(
+            buffer.offset_to_utf16_position(12).unwrap(),
+            Position::new(2, 0)
+        );
+        // Columns past the end clamp, except on the last line
+        assert_eq!(buffer.position_to_offset(Position::new(1, 9)).unwrap(), 11);
+        assert!(buffer.position_to_offset(Position::new(2, 2)).is_err());
+        assert!(buffer.position_to_offset(Position::new(3, 0)).is_err());
+    }
+
+    #[test]
+    fn test_column_units() {
+        // "e" + combining acute accent forms one grapheme of two chars
+        let mut buffer = TextBuffer::from_text("ae\u{301}🦀b\nx");
+        let offset = 8; // Before "b"
+
+        let columns = [
+            (ColumnUnit::Bytes, 8, 9),
+            (ColumnUnit::Chars, 4, 5),
+            (ColumnUnit::Utf16, 5, 6),
+            (ColumnUnit::Graphemes, 3, 4),
+        ];
+        for (unit, column, length) in columns {
+            let position = Position::new(0, column);
+            assert_eq!(
+                buffer.offset_to_position_in(offset, unit).unwrap(),
+                position
+            );
+            assert_eq!(
+                buffer.position_to_offset_in(position, unit).unwrap(),
+                offset
+            );
+            assert_eq!(buffer.get_line_length_in(0, unit).unwrap(), length);
+        }
+
+        buffer.set_column_unit(ColumnUnit::Graphemes);
+        assert_eq!(buffer.get_line_length(0).unwrap(), 4);
+        let range = Range::new(Position::new(0, 1), Position::new(0, 3));
+        assert_eq!(buffer.get_text_in_range(range).unwrap(), "e\u{301}🦀");
+        buffer.delete(range).unwrap();
+        assert_eq!(buffer.get_all_text(), "ab\nx");
+        assert_eq!(buffer.snapshot().column_unit(), ColumnUnit::Graphemes);
+    }
+
+    #[test]
+    fn test_large_text_is_split_into_bounded_pieces() {
+        let line = "é".repeat(99) + "\n";
+        let text = line.repeat(1000);
+        let mut buffer = TextBuffer::from_text(&text);
+        buffer.insert(Position::new(0, 0), &text).unwrap();
+
+        assert!(buffer.chunks().all(|chunk| chunk.len() <= MAX_PIECE_LENGTH));
+        assert_eq!(buffer.line_count(), 2001);
+        assert_eq!(buffer.get_line_length(1500).unwrap(), 99);
+        assert_eq!(
+            buffer.position_to_offset(Position::new(1500, 10)).unwrap(),
+            1500 * line.len() + 20
+        );
+        assert_eq!(buffer.get_all_text(), text.repeat(2));
+    }
+
     #[test]
     fn test_get_all_text() {
         let text = "Hello\nWorld\nTest";
@@ -493,8 +1327,264 @@ mod tests {
         let mut buffer = TextBuffer::from_text("Hello\nWorld");
         let result = buffer.insert(Position::new(0, 5), " there");
         assert!(result.is_ok());
+        assert_eq!(buffer.get_all_text(), "Hello there\nWorld");
+
+        buffer.insert(Position::new(1, 0), "Big ").unwrap();
+        buffer.insert(Position::new(0, 0), ">").unwrap();
+        assert_eq!(buffer.get_all_text(), ">Hello there\nBig World");
+        assert_eq!(buffer.get_line_content(1).unwrap(), "Big World");
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut buffer = TextBuffer::from_text("Hello\nWorld");
+        buffer.insert(Position::new(0, 5), " there").unwrap();
+
+        let range = Range::new(Position::new(0, 3), Position::new(1, 2));
+        assert_eq!(buffer.delete(range).unwrap(), "lo there\nWo");
+        assert_eq!(buffer.get_all_text(), "Helrld");
+        assert_eq!(buffer.line_count(), 1);
+
+        assert!(buffer
+            .delete(Range::new(Position::new(0, 2), Position::new(0, 2)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_markers_follow_edits() {
+        let mut buffer = TextBuffer::from_text("Hello\nWorld");
+        let left = buffer.add_marker(6, Stickiness::Left).unwrap();
+        let right = buffer.add_marker(6, Stickiness::Right).unwrap();
+        let end = buffer.add_marker(11, Stickiness::Left).unwrap();
+        assert!(buffer.add_marker(12, Stickiness::Left).is_err());
+
+        buffer.insert(Position::new(1, 0), "Big ").unwrap();
+        assert_eq!(buffer.marker_position(left), Some(6));
+        assert_eq!(buffer.marker_position(right), Some(10));
+        assert_eq!(buffer.marker_position(end), Some(15));
+
+        let range = Range::new(Position::new(0, 2), Position::new(1, 6));
+        buffer.delete(range).unwrap();
+        assert_eq!(buffer.get_all_text(), "Herld");
+        assert_eq!(buffer.marker_position(left), Some(2));
+        assert_eq!(buffer.marker_position(right), Some(2));
+        assert_eq!(buffer.marker_position(end), Some(5));
+
+        assert_eq!(buffer.remove_marker(end), Some(5));
+        assert_eq!(buffer.marker_position(end), None);
+        assert_eq!(buffer.marker_count(), 2);
+    }
+
+    #[test]
+    fn test_clone_shares_state_until_changed() {
+        let mut buffer = TextBuffer::from_text("Hello\nWorld");
+        let marker = buffer.add_marker(6, Stickiness::Right).unwrap();
+        buffer
+            .add_decoration(
+                Range::new(Position::new(1, 0), Position::new(1, 5)),
+                TrackedRangeStickiness::AlwaysGrowsWhenTypingAtEdges,
+                (),
+            )
+            .unwrap();
+
+        let clone = buffer.clone();
+        assert!(Arc::ptr_eq(&buffer.markers, &clone.markers));
+        assert!(Arc::ptr_eq(&buffer.decorations, &clone.decorations));
+        assert!(Arc::ptr_eq(&buffer.change_log, &clone.change_log));
+
+        buffer.insert(Position::new(1, 0), "Big ").unwrap();
+        assert!(!Arc::ptr_eq(&buffer.markers, &clone.markers));
+        assert_eq!(buffer.marker_position(marker), Some(10));
+        assert_eq!(clone.marker_position(marker), Some(6));
+        assert_eq!(clone.get_all_text(), "Hello\nWorld");
+    }
+
+    #[test]
+    fn test_decorations_follow_edits() {
+        let mut buffer = TextBuffer::from_text("one\ntwo\nthree\nfour");
+        let two = Range::new(Position::new(1, 0), Position::new(1, 3));
+        let stickiness = TrackedRangeStickiness::NeverGrowsWhenTypingAtEdges;
+        let id = buffer.add_decoration(two, stickiness, "warning").unwrap();
+        buffer
+            .add_decoration(
+                Range::new(Position::new(3, 0), Position::new(3, 4)),
+                stickiness,
+                42u32,
+            )
+            .unwrap();
+
+        buffer.insert(Position::new(0, 0), "zero\n").unwrap();
+        buffer.insert(Position::new(2, 0), "my ").unwrap();
+
+        let moved = Range::new(Position::new(2, 3), Position::new(2, 6));
+        assert_eq!(buffer.decoration_range(id), Some(moved));
+
+        let found = buffer.decorations_in_lines(1, 3).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, id);
+        assert_eq!(found[0].range, moved);
+        assert_eq!(found[0].payload::<&str>(), Some(&"warning"));
+        assert_eq!(found[0].payload::<u32>(), None);
+
+        let all = buffer.decorations_in_lines(0, 4).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1].payload::<u32>(), Some(&42));
+
+        assert!(buffer.remove_decoration(id));
+        assert!(!buffer.remove_decoration(id));
+        assert_eq!(buffer.decoration_count(), 1);
+    }
+
+    #[test]
+    fn test_folds_follow_edits() {
+        let mut buffer = TextBuffer::from_text("fn a() {\n    1\n    2\n}\nfn b() {}\n");
+        let id = buffer
+            .fold(Range::new(Position::new(0, 8), Position::new(3, 0)))
+            .unwrap();
+
+        let map = buffer.fold_map().unwrap();
+        assert_eq!(map.visible_lines().collect::<Vec<_>>(), vec![0, 4, 5]);
+        assert_eq!(map.to_buffer_line(1), Some(4));
+
+        // Lines inserted above and inside the fold move and widen it
+        buffer.insert(Position::new(0, 0), "// a\n").unwrap();
+        buffer.insert(Position::new(2, 0), "    0\n").unwrap();
+        assert_eq!(
+            buffer.fold_range(id),
+            Some(Range::new(Position::new(1, 8), Position::new(5, 0)))
+        );
+        let map = buffer.fold_map().unwrap();
+        assert_eq!(map.visible_lines().collect::<Vec<_>>(), vec![0, 1, 6, 7]);
+        assert_eq!(map.to_display_line(3), Some(1));
+
+        assert!(buffer.unfold(id));
+        assert_eq!(buffer.fold_map().unwrap().display_line_count(), 8);
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let mut buffer = TextBuffer::from_text("one two\nthree");
+        let inverse = buffer
+            .apply_edits(vec![
+                EditOperation::new(Range::new(Position::new(1, 0), Position::new(1, 5)), "3"),
+                EditOperation::insert(Position::new(0, 0), "> "),
+                EditOperation::delete(Range::new(Position::new(0, 3), Position::new(0, 7))),
+            ])
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "> one\n3");
+
+        buffer.apply_edits(inverse).unwrap();
+        assert_eq!(buffer.get_all_text(), "one two\nthree");
+
+        let overlapping = vec![
+            EditOperation::delete(Range::new(Position::new(0, 0), Position::new(0, 4))),
+            EditOperation::delete(Range::new(Position::new(0, 2), Position::new(0, 6))),
+        ];
+        assert!(buffer.apply_edits(overlapping).is_err());
+        assert_eq!(buffer.get_all_text(), "one two\nthree");
+    }
+
+    #[test]
+    fn test_change_listeners() {
+        use std::sync::{Arc, Mutex};
+
+        let mut buffer = TextBuffer::from_text("Hello\nWorld");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let id = buffer.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+
+        buffer.insert(Position::new(0, 5), "\nthere").unwrap();
+        buffer
+            .delete(Range::new(Position::new(1, 0), Position::new(2, 0)))
+            .unwrap();
+        buffer
+            .apply_edits(vec![
+                EditOperation::insert(Position::new(0, 0), "a"),
+                EditOperation::insert(Position::new(1, 5), "b"),
+            ])
+            .unwrap();
+
+        {
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), 3);
+            assert_eq!(
+                events.iter().map(|e| e.version_id).collect::<Vec<_>>(),
+                vec![1, 2, 3]
+            );
+
+            let insert = &events[0].changes[0];
+            assert_eq!(
+                insert.range,
+                Range::new(Position::new(0, 5), Position::new(0, 5))
+            );
+            assert_eq!((insert.start_offset, insert.old_end_offset), (5, 5));
+            assert_eq!(insert.new_end_offset, 11);
+            assert_eq!(insert.text, "\nthere");
+            assert_eq!(insert.line_delta, 1);
+
+            let delete = &events[1].changes[0];
+            assert_eq!((delete.start_offset, delete.old_end_offset), (6, 12));
+            assert_eq!(delete.line_delta, -1);
+
+            // Batch changes arrive in one event, last position first
+            let batch = &events[2].changes;
+            assert_eq!(batch.len(), 2);
+            assert_eq!(batch[0].start_offset, 11);
+            assert_eq!(batch[1].start_offset, 0);
+        }
+
+        assert!(buffer.unsubscribe(id));
+        buffer.insert(Position::new(0, 0), "x").unwrap();
+        assert_eq!(events.lock().unwrap().len(), 3);
+
+        // Clones do not inherit listeners
+        let mut clone = buffer.clone();
+        clone.subscribe(|_| panic!("clone listener should not fire for the original"));
+        buffer.insert(Position::new(0, 0), "y").unwrap();
+    }
+
+    #[test]
+    fn test_changed_lines_since() {
+        let mut buffer = TextBuffer::from_text("a\nb\nc\nd\ne");
+        let initial = buffer.version();
+        assert_eq!(initial, 0);
+        assert!(buffer.changed_lines_since(initial).is_empty());
+
+        buffer.insert(Position::new(3, 1), "!").unwrap();
+        let after_first = buffer.version();
+        buffer.insert(Position::new(0, 0), "new\n").unwrap();
+
+        assert_eq!(buffer.version(), 2);
+        assert_eq!(buffer.changed_lines_since(initial), vec![0..2, 4..5]);
+        assert_eq!(buffer.changed_lines_since(after_first), vec![0..2]);
+        assert!(buffer.changed_lines_since(buffer.version()).is_empty());
+    }
+
+    #[test]
+    fn test_many_edits() {
+        let mut buffer = TextBuffer::new();
+        let mut expected = String::new();
+
+        for i in 0..200 {
+            let offset = (i * 7) % (expected.len() + 1);
+            let position = buffer.offset_to_position(offset).unwrap();
+            let text = format!("{i}\n");
+            buffer.insert(position, &text).unwrap();
+            expected.insert_str(offset, &text);
+
+            if i % 3 == 0 {
+                let end = (offset + 4).min(expected.len());
+                let range = Range::new(
+                    buffer.offset_to_position(offset).unwrap(),
+                    buffer.offset_to_position(end).unwrap(),
+                );
+                buffer.delete(range).unwrap();
+                expected.replace_range(offset..end, "");
+            }
+        }
 
-        // Note: This is a simplified test - the actual insertion logic
-        // would need to be more sophisticated to handle piece splitting
+        assert_eq!(buffer.get_all_text(), expected);
+        assert_eq!(buffer.length(), expected.len());
+        assert_eq!(buffer.line_count(), expected.matches('\n').count() + 1);
     }
 }
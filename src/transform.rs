@@ -0,0 +1,299 @@
+//! Transform module for case conversion and text transformations
+//!
+//! This module rewrites the text of a range: case changes, identifier case
+//! conversions, transposition of grapheme clusters and Unicode normalization.
+//! Words are found with Unicode word segmentation and transposition moves
+//! whole grapheme clusters, so ideographs, combining marks and emoji
+//! sequences are never split.
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{Range, TextBufferResult};
+
+/// A transformation of the text of a range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transform {
+    /// "Hello" -> "HELLO"
+    Upper,
+    /// "Hello" -> "hello"
+    Lower,
+    /// "hello world" -> "Hello World"
+    Title,
+    /// "fooBar" -> "foo_bar"
+    Snake,
+    /// "foo_bar" -> "fooBar"
+    Camel,
+    /// "fooBar" -> "foo-bar"
+    Kebab,
+    /// Swaps the grapheme clusters around an empty range, or the first and
+    /// last ones of a range
+    Transpose,
+    /// Unicode canonical composition
+    Nfc,
+    /// Unicode canonical decomposition
+    Nfd,
+}
+
+/// Returns true for chars that can be part of an identifier
+///
+/// Combining marks count, so decomposed letters stay whole.
+fn is_identifier_char(ch: char) -> bool {
+    ch.is_alphanumeric() || is_combining_mark(ch) || ch == '_' || ch == '-'
+}
+
+/// Splits an identifier into its words at underscores, hyphens and case
+/// changes
+///
+/// An uppercase run followed by a lowercase char ends before its last char,
+/// so "HTTPServer" gives "HTTP" and "Server". Combining marks are skipped
+/// when looking at the chars around a case change.
+fn identifier_words(identifier: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = identifier.char_indices().collect();
+    let mut words = Vec::new();
+    let mut start = None;
+    for (index, &(offset, ch)) in chars.iter().enumerate() {
+        if ch == '_' || ch == '-' {
+            if let Some(start) = start.take() {
+                words.push(&identifier[start..offset]);
+            }
+            continue;
+        }
+
+        let previous = chars[..index]
+            .iter()
+            .rev()
+            .map(|&(_, ch)| ch)
+            .find(|&ch| !is_combining_mark(ch));
+        let next = chars[index + 1..]
+            .iter()
+            .map(|&(_, ch)| ch)
+            .find(|&ch| !is_combining_mark(ch));
+        let boundary = ch.is_uppercase()
+            && previous.is_some_and(|previous| {
+                previous.is_lowercase()
+                    || previous.is_numeric()
+                    || (previous.is_uppercase() && next.is_some_and(char::is_lowercase))
+            });
+        match start {
+            Some(word_start) if boundary => {
+                words.push(&identifier[word_start..offset]);
+                start = Some(offset);
+            }
+            Some(_) => {}
+            None => start = Some(offset),
+        }
+    }
+    if let Some(start) = start {
+        words.push(&identifier[start..]);
+    }
+    words
+}
+
+/// Returns a word with its first char uppercase and the rest lowercase
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Rewrites each identifier of a text, keeping leading and trailing
+/// underscores and hyphens and everything between identifiers
+fn convert_identifiers(text: &str, convert: impl Fn(&[&str]) -> String) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|ch| !is_identifier_char(ch))
+            .unwrap_or(rest.len());
+        let (identifier, tail) = rest.split_at(split);
+
+        let trimmed = identifier.trim_matches(['_', '-']);
+        let prefix = identifier.len() - identifier.trim_start_matches(['_', '-']).len();
+        if trimmed.is_empty() {
+            result.push_str(identifier);
+        } else {
+            result.push_str(&identifier[..prefix]);
+            result.push_str(&convert(&identifier_words(trimmed)));
+            result.push_str(&identifier[prefix + trimmed.len()..]);
+        }
+
+        let split = tail.find(is_identifier_char).unwrap_or(tail.len());
+        result.push_str(&tail[..split]);
+        rest = &tail[split..];
+    }
+    result
+}
+
+/// Applies a transformation that depends on the text alone
+fn transform_text(text: &str, transform: Transform) -> String {
+    match transform {
+        Transform::Upper => text.to_uppercase(),
+        Transform::Lower => text.to_lowercase(),
+        Transform::Title => text
+            .split_word_bounds()
+            .map(|segment| match segment.chars().any(char::is_alphanumeric) {
+                true => capitalize(segment),
+                false => segment.to_string(),
+            })
+            .collect(),
+        Transform::Snake => convert_identifiers(text, |words| words.join("_").to_lowercase()),
+        Transform::Kebab => convert_identifiers(text, |words| words.join("-").to_lowercase()),
+        Transform::Camel => convert_identifiers(text, |words| {
+            let mut words = words.iter();
+            let first = words.next().map(|word| word.to_lowercase());
+            first
+                .into_iter()
+                .chain(words.map(|word| capitalize(word)))
+                .collect()
+        }),
+        Transform::Nfc => text.nfc().collect(),
+        Transform::Nfd => text.nfd().collect(),
+        // Transposition needs the surrounding text and is done by `transform`
+        Transform::Transpose => text.to_string(),
+    }
+}
+
+impl TextSnapshot {
+    /// Helper method to find the two grapheme clusters swapped by a
+    /// transposition, as the byte ranges `a_start..a_end` and
+    /// `b_start..b_end`
+    ///
+    /// A range swaps its first and last clusters. A cursor swaps the
+    /// clusters before and after it, or the last two of the line at its end;
+    /// these never include a line break.
+    fn transpose_bounds(&self, start: usize, end: usize) -> TextBufferResult<Option<[usize; 4]>> {
+        if start < end {
+            let first_end = self.next_grapheme_boundary(start)?;
+            let last_start = self.prev_grapheme_boundary(end)?;
+            return Ok((first_end <= last_start).then_some([start, first_end, last_start, end]));
+        }
+
+        let position = self.offset_to_position(start)?;
+        let (line_start, line_end) = self.line_bounds(position.line)?;
+        let ends_with_cr = line_end.bytes > line_start.bytes
+            && self.chars_in(line_end.bytes - 1, line_end.bytes).next()
+                == Some((line_end.bytes - 1, '\r'));
+        let content_end = line_end.bytes - usize::from(ends_with_cr);
+
+        let middle = match start >= content_end && content_end > line_start.bytes {
+            true => self.prev_grapheme_boundary(content_end)?,
+            false => start,
+        };
+        if middle <= line_start.bytes || middle >= content_end {
+            return Ok(None);
+        }
+        let first = self.prev_grapheme_boundary(middle)?;
+        let last = self.next_grapheme_boundary(middle)?.min(content_end);
+        Ok(Some([first, middle, middle, last]))
+    }
+}
+
+/// Returns the byte range to replace and its new text for a transformation
+/// of a range, or None when the text stays the same
+pub(crate) fn transform(
+    text: &TextSnapshot,
+    range: Range,
+    transform: Transform,
+) -> TextBufferResult<Option<(usize, usize, String)>> {
+    let start = text.position_to_offset(range.start)?;
+    let end = text.position_to_offset(range.end)?;
+    if start > end {
+        return Err("Invalid range".to_string());
+    }
+
+    let (start, end, old, new) = match transform {
+        Transform::Transpose => {
+            let Some([a_start, a_end, b_start, b_end]) = text.transpose_bounds(start, end)? else {
+                return Ok(None);
+            };
+            let old = text.text_between(a_start, b_end)?;
+            let (a, rest) = old.split_at(a_end - a_start);
+            let (middle, b) = rest.split_at(b_start - a_end);
+            let new = format!("{b}{middle}{a}");
+            (a_start, b_end, old, new)
+        }
+        _ => {
+            let old = text.text_between(start, end)?;
+            let new = transform_text(&old, transform);
+            (start, end, old, new)
+        }
+    };
+
+    Ok((old != new).then_some((start, end, new)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+    use crate::types::Position;
+
+    #[test]
+    fn test_transform_text() {
+        let convert = |text: &str, transform| transform_text(text, transform);
+
+        assert_eq!(convert("straße 日本", Transform::Upper), "STRASSE 日本");
+        assert_eq!(
+            convert("hello wORLD can't", Transform::Title),
+            "Hello World Can't"
+        );
+        assert_eq!(
+            convert("parseHTTPServer2Url x", Transform::Snake),
+            "parse_http_server2_url x"
+        );
+        assert_eq!(convert("__foo_bar_baz", Transform::Camel), "__fooBarBaz");
+        assert_eq!(
+            convert("fooBar(日本_語);", Transform::Kebab),
+            "foo-bar(日本-語);"
+        );
+        assert_eq!(convert("e\u{301}", Transform::Nfc), "\u{e9}");
+        assert_eq!(convert("\u{e9}", Transform::Nfd), "e\u{301}");
+
+        // Decomposed letters keep their marks within the word
+        assert_eq!(
+            convert("cafe\u{301}Bar", Transform::Snake),
+            "cafe\u{301}_bar"
+        );
+        assert_eq!(
+            convert("E\u{301}TATUnis", Transform::Kebab),
+            "e\u{301}tat-unis"
+        );
+    }
+
+    #[test]
+    fn test_transform_range() {
+        let mut buffer = TextBuffer::from_text("fooBar 👍🏽日\r\nab");
+        let line = |start, end| Range::new(Position::new(0, start), Position::new(0, end));
+
+        let (range, undo) = buffer.transform(line(0, 6), Transform::Snake).unwrap();
+        assert_eq!(buffer.get_all_text(), "foo_bar 👍🏽日\r\nab");
+        assert_eq!(range, line(0, 7));
+        assert_eq!(buffer.version(), 1);
+        buffer.apply_edits(undo).unwrap();
+
+        // Transposing at the end of a line swaps its last two clusters
+        let (range, _) = buffer
+            .transform(line(10, 10), Transform::Transpose)
+            .unwrap();
+        assert_eq!(buffer.get_all_text(), "fooBar 日👍🏽\r\nab");
+        assert_eq!(range, line(7, 10));
+        buffer.transform(line(1, 1), Transform::Transpose).unwrap();
+        assert_eq!(buffer.get_all_text(), "ofoBar 日👍🏽\r\nab");
+        buffer.transform(line(0, 4), Transform::Transpose).unwrap();
+        assert_eq!(buffer.get_all_text(), "Bfooar 日👍🏽\r\nab");
+
+        // Nothing changes, so no edit is made
+        let (range, undo) = buffer.transform(line(7, 10), Transform::Upper).unwrap();
+        assert_eq!(range, line(7, 10));
+        assert!(undo.is_empty());
+        assert_eq!(buffer.version(), 5);
+    }
+}
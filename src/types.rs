@@ -1,7 +1,9 @@
 //! Common types used throughout the text buffer implementation
 
 /// Position in the text buffer represented as line and column
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Positions order by line, then by column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -13,6 +15,20 @@ impl Position {
     }
 }
 
+/// Unit in which `Position::column` is measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// UTF-8 bytes
+    Bytes,
+    /// Unicode scalar values
+    #[default]
+    Chars,
+    /// UTF-16 code units, as used by LSP
+    Utf16,
+    /// Extended grapheme clusters, as perceived by users
+    Graphemes,
+}
+
 /// Range in the text buffer from start to end position
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Range {
@@ -26,6 +42,32 @@ impl Range {
     }
 }
 
+/// Replacement of a range with new text, the unit of batch edits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditOperation {
+    pub range: Range,
+    pub text: String,
+}
+
+impl EditOperation {
+    pub fn new(range: Range, text: impl Into<String>) -> Self {
+        Self {
+            range,
+            text: text.into(),
+        }
+    }
+
+    /// Creates an operation inserting text at a position
+    pub fn insert(position: Position, text: impl Into<String>) -> Self {
+        Self::new(Range::new(position, position), text)
+    }
+
+    /// Creates an operation deleting a range
+    pub fn delete(range: Range) -> Self {
+        Self::new(range, String::new())
+    }
+}
+
 /// Result of a text buffer operation
 pub type TextBufferResult<T> = Result<T, String>;
 
@@ -0,0 +1,104 @@
+//! Visual module for display columns
+//!
+//! This module maps positions to the columns they occupy on screen, expanding
+//! tabs to the next tab stop and counting East Asian wide characters as two
+//! cells. Lines are walked piece by piece without allocating.
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{Position, TextBufferResult};
+
+/// Returns the number of cells a char occupies when drawn at `column`
+pub(crate) fn char_width(ch: char, column: usize, tab_size: usize) -> usize {
+    if ch == '\t' {
+        let tab_size = tab_size.max(1);
+        tab_size - column % tab_size
+    } else {
+        ch.width().unwrap_or(0)
+    }
+}
+
+impl TextSnapshot {
+    /// Returns the display column of a position, expanding tabs to multiples
+    /// of `tab_size` and counting wide characters as two cells
+    pub fn visual_column(&self, position: Position, tab_size: usize) -> TextBufferResult<usize> {
+        let offset = self.position_to_offset(position)?;
+        let line_start = self.line_start_summary(position.line)?.bytes;
+
+        Ok(self
+            .chars_in(line_start, offset)
+            .fold(0, |column, (_, ch)| {
+                column + char_width(ch, column, tab_size)
+            }))
+    }
+
+    /// Returns the position on a line displayed at the given column
+    ///
+    /// A column inside a tab or a wide character resolves to the start of
+    /// that character; a column past the end resolves to the end of the line.
+    pub fn position_at_visual_column(
+        &self,
+        line: usize,
+        column: usize,
+        tab_size: usize,
+    ) -> TextBufferResult<Position> {
+        if line >= self.line_count() {
+            return Err(format!("Line {line} out of bounds"));
+        }
+
+        let (start, end) = self.line_bounds(line)?;
+        let mut visual = 0;
+        let mut offset = end.bytes;
+        for (char_offset, ch) in self.chars_in(start.bytes, end.bytes) {
+            let width = char_width(ch, visual, tab_size);
+            if visual + width > column {
+                offset = char_offset;
+                break;
+            }
+            visual += width;
+        }
+
+        self.offset_to_position(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_buffer::TextBuffer;
+    use crate::types::Position;
+
+    #[test]
+    fn test_visual_column() {
+        let buffer = TextBuffer::from_text("\tab\tc\n日本x");
+
+        assert_eq!(buffer.visual_column(Position::new(0, 1), 4).unwrap(), 4);
+        assert_eq!(buffer.visual_column(Position::new(0, 3), 4).unwrap(), 6);
+        assert_eq!(buffer.visual_column(Position::new(0, 4), 4).unwrap(), 8);
+        assert_eq!(buffer.visual_column(Position::new(0, 4), 8).unwrap(), 16);
+        assert_eq!(buffer.visual_column(Position::new(1, 2), 4).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_position_at_visual_column() {
+        let buffer = TextBuffer::from_text("\tab\tc\n日本x");
+
+        assert_eq!(
+            buffer.position_at_visual_column(0, 2, 4).unwrap(),
+            Position::new(0, 0)
+        );
+        assert_eq!(
+            buffer.position_at_visual_column(0, 5, 4).unwrap(),
+            Position::new(0, 2)
+        );
+        assert_eq!(
+            buffer.position_at_visual_column(1, 3, 4).unwrap(),
+            Position::new(1, 1)
+        );
+        assert_eq!(
+            buffer.position_at_visual_column(1, 40, 4).unwrap(),
+            Position::new(1, 3)
+        );
+        assert!(buffer.position_at_visual_column(2, 0, 4).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Whitespace module for save-time whitespace cleanup
+//!
+//! This module computes the edits formatters apply on save: trimming
+//! trailing whitespace, ensuring a final newline and collapsing trailing
+//! blank lines. Trimming can be limited to the lines modified since the text
+//! was loaded, found from the pieces referencing the added buffers, so saving
+//! a file touches only what was edited.
+
+use crate::piece::PieceType;
+use crate::snapshot::TextSnapshot;
+use crate::types::{EditOperation, Range, TextBufferResult};
+
+impl TextSnapshot {
+    /// Returns the lines holding text inserted since the text was loaded, in
+    /// order
+    ///
+    /// A line counts when any of its chars comes from an added piece; line
+    /// breaks do not count, so a line split by an inserted break is not
+    /// reported unless text was added to it. Lines changed only by deletions
+    /// are not reported.
+    pub fn modified_lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = Vec::new();
+        let mut line = 0;
+        for piece in self.tree.iter() {
+            if piece.is_empty() {
+                continue;
+            }
+            if piece.piece_type == PieceType::Added {
+                // Line breaks at either end of the piece only end the line
+                // before them
+                let content = self.get_piece_content(piece).unwrap_or_default();
+                let starts_with_break = content.starts_with('\n') || content.starts_with("\r\n");
+                let ends_with_break = content.ends_with('\n');
+                let first = line + usize::from(starts_with_break);
+                let last =
+                    (line + piece.line_break_count).checked_sub(usize::from(ends_with_break));
+                let first = match lines.last() {
+                    Some(&previous) if previous >= first => previous + 1,
+                    _ => first,
+                };
+                if let Some(last) = last {
+                    lines.extend(first..=last);
+                }
+            }
+            line += piece.line_break_count;
+        }
+        lines
+    }
+
+    /// Helper method to split a line into its content before trailing
+    /// whitespace and the whitespace, as byte offsets
+    ///
+    /// A CR ending the line is kept out of the whitespace.
+    fn trailing_whitespace(&self, line: usize) -> TextBufferResult<(usize, usize)> {
+        let (start, end) = self.line_bounds(line)?;
+        let content = self.text_between(start.bytes, end.bytes)?;
+        let body = content.strip_suffix('\r').unwrap_or(&content);
+        let trimmed = body.trim_end_matches(|ch: char| ch.is_whitespace() && ch != '\r');
+        Ok((start.bytes + trimmed.len(), start.bytes + body.len()))
+    }
+}
+
+/// Returns the edits removing trailing whitespace from every line, or only
+/// from the lines modified since the text was loaded
+pub(crate) fn trim_trailing_whitespace(
+    text: &TextSnapshot,
+    modified_only: bool,
+) -> TextBufferResult<Vec<EditOperation>> {
+    let lines = match modified_only {
+        true => text.modified_lines(),
+        false => (0..text.line_count()).collect(),
+    };
+
+    let mut edits = Vec::new();
+    for line in lines {
+        let (start, end) = text.trailing_whitespace(line)?;
+        if start < end {
+            let range = Range::new(
+                text.offset_to_position(start)?,
+                text.offset_to_position(end)?,
+            );
+            edits.push(EditOperation::delete(range));
+        }
+    }
+    Ok(edits)
+}
+
+/// Returns the edit appending a line break to a non-empty text not ending
+/// with one
+///
+/// The break is CRLF when the first line of the text ends with one.
+pub(crate) fn ensure_final_newline(text: &TextSnapshot) -> TextBufferResult<Vec<EditOperation>> {
+    let length = text.length();
+    if length == 0 || text.chars_in(length - 1, length).next() == Some((length - 1, '\n')) {
+        return Ok(Vec::new());
+    }
+
+    let end = text.offset_to_position(length)?;
+    Ok(vec![EditOperation::insert(end, text.line_ending())])
+}
+
+/// Returns the edit removing the blank lines at the end of the text
+///
+/// The line break after the last non-blank line is kept, so a text ending
+/// in blank lines ends with exactly one line break. Lines holding only
+/// whitespace count as blank.
+pub(crate) fn collapse_trailing_blank_lines(
+    text: &TextSnapshot,
+) -> TextBufferResult<Vec<EditOperation>> {
+    let mut line = text.line_count();
+    let keep = loop {
+        if line == 0 {
+            break 0;
+        }
+        line -= 1;
+        let (line_start, line_end) = text.line_bounds(line)?;
+        let (content_end, _) = text.trailing_whitespace(line)?;
+        if content_end > line_start.bytes {
+            // Keep the line and the line break ending it, if any
+            break (line_end.bytes + 1).min(text.length());
+        }
+    };
+
+    if keep >= text.length() {
+        return Ok(Vec::new());
+    }
+    let range = Range::new(
+        text.offset_to_position(keep)?,
+        text.offset_to_position(text.length())?,
+    );
+    Ok(vec![EditOperation::delete(range)])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_buffer::TextBuffer;
+    use crate::types::Position;
+
+    #[test]
+    fn test_modified_lines() {
+        let mut buffer = TextBuffer::from_text("one\ntwo\nthree\nfour");
+        assert!(buffer.modified_lines().is_empty());
+
+        buffer.insert(Position::new(1, 3), "!").unwrap();
+        buffer.insert(Position::new(2, 0), "x\ny").unwrap();
+        // A lone line break touches neither line around it
+        buffer.insert(Position::new(4, 4), "\n").unwrap();
+        assert_eq!(buffer.modified_lines(), vec![1, 2, 3]);
+
+        // Pressing Enter after a line leaves it unmodified
+        let mut buffer = TextBuffer::from_text("a  \r\nb  \r\nc");
+        buffer.insert(Position::new(0, 3), "\r\n").unwrap();
+        buffer.insert(Position::new(2, 3), "\r\nx  ").unwrap();
+        assert_eq!(buffer.modified_lines(), vec![3]);
+        buffer.trim_trailing_whitespace(true).unwrap();
+        assert_eq!(buffer.get_all_text(), "a  \r\n\r\nb  \r\nx\r\nc");
+    }
+
+    #[test]
+    fn test_whitespace_cleanup() {
+        let mut buffer = TextBuffer::from_text("a  \r\nb\t\r\n \r\n\u{3000}\r\n");
+
+        buffer.insert(Position::new(0, 0), "x").unwrap();
+        buffer.trim_trailing_whitespace(true).unwrap();
+        assert_eq!(buffer.get_all_text(), "xa\r\nb\t\r\n \r\n\u{3000}\r\n");
+
+        let undo = buffer.collapse_trailing_blank_lines().unwrap();
+        assert_eq!(buffer.get_all_text(), "xa\r\nb\t\r\n");
+        buffer.apply_edits(undo).unwrap();
+
+        buffer.trim_trailing_whitespace(false).unwrap();
+        assert_eq!(buffer.get_all_text(), "xa\r\nb\r\n\r\n\r\n");
+        buffer.collapse_trailing_blank_lines().unwrap();
+        assert_eq!(buffer.get_all_text(), "xa\r\nb\r\n");
+        assert!(buffer.ensure_final_newline().unwrap().is_empty());
+
+        let mut buffer = TextBuffer::from_text("a\r\nb");
+        buffer.ensure_final_newline().unwrap();
+        assert_eq!(buffer.get_all_text(), "a\r\nb\r\n");
+        let mut buffer = TextBuffer::from_text("\n \n");
+        buffer.collapse_trailing_blank_lines().unwrap();
+        assert_eq!(buffer.get_all_text(), "");
+    }
+}
@@ -0,0 +1,230 @@
+//! Word module for word boundaries and word navigation
+//!
+//! This module finds words for double-click selection, Ctrl+Left/Right and
+//! completion prefixes. A line is split with Unicode word segmentation and
+//! words are then cut at whitespace and at the configured separator chars.
+//! Words never span lines, so only the lines visited are read.
+
+use std::ops::Range as ByteRange;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::snapshot::TextSnapshot;
+use crate::types::{Position, Range, TextBufferResult};
+
+/// Chars that end a word besides whitespace
+pub(crate) const DEFAULT_WORD_SEPARATORS: &str = "`~!@#$%^&*()-=+[{]}\\|;:'\",.<>/?";
+
+/// Returns the byte ranges of the words of a line
+///
+/// Chars that are neither whitespace nor separators join the word before
+/// them, except where two segments holding letters or digits meet, such as
+/// two ideographs.
+fn line_words(line: &str, separators: &str) -> Vec<ByteRange<usize>> {
+    let mut words: Vec<ByteRange<usize>> = Vec::new();
+    let mut previous_alphanumeric = false;
+
+    for (start, segment) in line.split_word_bound_indices() {
+        let alphanumeric = segment.chars().any(char::is_alphanumeric);
+        for (index, ch) in segment.char_indices() {
+            if ch.is_whitespace() || separators.contains(ch) {
+                continue;
+            }
+            let offset = start + index;
+            let joins = index > 0 || !(alphanumeric && previous_alphanumeric);
+            match words.last_mut() {
+                Some(word) if word.end == offset && joins => word.end = offset + ch.len_utf8(),
+                _ => words.push(offset..offset + ch.len_utf8()),
+            }
+        }
+        previous_alphanumeric = alphanumeric;
+    }
+
+    words
+}
+
+impl TextSnapshot {
+    /// Returns the range of the word containing a position, or of the word
+    /// ending at it
+    pub fn word_at(&self, position: Position, separators: &str) -> TextBufferResult<Option<Range>> {
+        let offset = self.position_to_offset(position)?;
+        let (line_start, _, words) = self.words_in_line(position.line, separators)?;
+        let within = offset - line_start;
+
+        let word = words
+            .iter()
+            .find(|word| word.contains(&within))
+            .or_else(|| words.iter().find(|word| word.end == within));
+        match word {
+            Some(word) => Ok(Some(Range::new(
+                self.offset_to_position(line_start + word.start)?,
+                self.offset_to_position(line_start + word.end)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the start of the next word after a position
+    ///
+    /// Line ends are stops too, so moving forward never skips a line
+    /// silently. Returns the end of the text when nothing follows.
+    pub fn next_word_start(
+        &self,
+        position: Position,
+        separators: &str,
+    ) -> TextBufferResult<Position> {
+        self.next_stop(position, separators, |word| word.start)
+    }
+
+    /// Returns the end of the word containing a position or of the next word
+    ///
+    /// Line ends are stops too. Returns the end of the text when nothing
+    /// follows.
+    pub fn word_end(&self, position: Position, separators: &str) -> TextBufferResult<Position> {
+        self.next_stop(position, separators, |word| word.end)
+    }
+
+    /// Returns the start of the word before a position
+    ///
+    /// Line starts are stops too. Returns the start of the text when nothing
+    /// precedes.
+    pub fn previous_word_start(
+        &self,
+        position: Position,
+        separators: &str,
+    ) -> TextBufferResult<Position> {
+        let offset = self.position_to_offset(position)?;
+        let mut line = position.line;
+        loop {
+            let (line_start, _, words) = self.words_in_line(line, separators)?;
+            let stop = std::iter::once(line_start)
+                .chain(words.iter().map(|word| line_start + word.start))
+                .rfind(|&stop| stop < offset);
+            match stop {
+                Some(stop) => return self.offset_to_position(stop),
+                None if line == 0 => return Ok(Position::new(0, 0)),
+                None => line -= 1,
+            }
+        }
+    }
+
+    /// Helper method to find the first word boundary or line end after a
+    /// position
+    fn next_stop(
+        &self,
+        position: Position,
+        separators: &str,
+        boundary: impl Fn(&ByteRange<usize>) -> usize,
+    ) -> TextBufferResult<Position> {
+        let offset = self.position_to_offset(position)?;
+        let mut line = position.line;
+        loop {
+            let (line_start, line_end, words) = self.words_in_line(line, separators)?;
+            let stop = words
+                .iter()
+                .map(|word| line_start + boundary(word))
+                .chain(std::iter::once(line_end))
+                .find(|&stop| stop > offset);
+            match stop {
+                Some(stop) => return self.offset_to_position(stop),
+                None if line + 1 >= self.line_count() => return self.offset_to_position(line_end),
+                None => line += 1,
+            }
+        }
+    }
+
+    /// Helper method to read the words of a line, with the byte offsets at
+    /// which the line starts and ends
+    fn words_in_line(
+        &self,
+        line: usize,
+        separators: &str,
+    ) -> TextBufferResult<(usize, usize, Vec<ByteRange<usize>>)> {
+        let (start, end) = self.line_bounds(line)?;
+        let content = self.text_between(start.bytes, end.bytes)?;
+        Ok((start.bytes, end.bytes, line_words(&content, separators)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+
+    #[test]
+    fn test_line_words() {
+        let words = |line: &str, separators: &str| -> Vec<String> {
+            line_words(line, separators)
+                .into_iter()
+                .map(|word| line[word].to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            words("let foo_bar = can't(3.14);", DEFAULT_WORD_SEPARATORS),
+            vec!["let", "foo_bar", "can", "t", "3", "14"]
+        );
+        assert_eq!(words("font-size: 12px", ":"), vec!["font-size", "12px"]);
+        assert_eq!(words("日本語 café", ""), vec!["日", "本", "語", "café"]);
+    }
+
+    #[test]
+    fn test_word_navigation() {
+        let mut buffer = TextBuffer::from_text("fn main() {\n    let x_1 = a.b;\n}");
+
+        assert_eq!(
+            buffer.word_at(Position::new(0, 5)).unwrap(),
+            Some(Range::new(Position::new(0, 3), Position::new(0, 7)))
+        );
+        // Completion prefix: the word ending at the cursor
+        assert_eq!(
+            buffer.word_at(Position::new(1, 11)).unwrap(),
+            Some(Range::new(Position::new(1, 8), Position::new(1, 11)))
+        );
+        assert_eq!(buffer.word_at(Position::new(0, 10)).unwrap(), None);
+
+        assert_eq!(
+            buffer.next_word_start(Position::new(0, 0)).unwrap(),
+            Position::new(0, 3)
+        );
+        assert_eq!(
+            buffer.next_word_start(Position::new(0, 3)).unwrap(),
+            Position::new(0, 11)
+        );
+        assert_eq!(
+            buffer.next_word_start(Position::new(0, 11)).unwrap(),
+            Position::new(1, 4)
+        );
+        assert_eq!(
+            buffer.word_end(Position::new(1, 0)).unwrap(),
+            Position::new(1, 7)
+        );
+        assert_eq!(
+            buffer.word_end(Position::new(1, 14)).unwrap(),
+            Position::new(1, 15)
+        );
+        assert_eq!(
+            buffer.word_end(Position::new(2, 0)).unwrap(),
+            Position::new(2, 1)
+        );
+
+        assert_eq!(
+            buffer.previous_word_start(Position::new(1, 4)).unwrap(),
+            Position::new(1, 0)
+        );
+        assert_eq!(
+            buffer.previous_word_start(Position::new(1, 0)).unwrap(),
+            Position::new(0, 3)
+        );
+        assert_eq!(
+            buffer.previous_word_start(Position::new(0, 2)).unwrap(),
+            Position::new(0, 0)
+        );
+
+        buffer.set_word_separators("");
+        assert_eq!(
+            buffer.word_at(Position::new(1, 16)).unwrap(),
+            Some(Range::new(Position::new(1, 14), Position::new(1, 18)))
+        );
+    }
+}
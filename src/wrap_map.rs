@@ -0,0 +1,544 @@
+//! Wrap map module for soft-wrapped display rows
+//!
+//! This module implements the WrapMap which splits every buffer line into the
+//! display rows it occupies at a given viewport width. Lines are kept in an
+//! implicit treap ordered by line number whose nodes carry the number of
+//! lines and rows below them, so converting between display rows and buffer
+//! positions is O(log n). Change events splice the affected lines out of the
+//! treap and mark the new ones dirty; `sync` then wraps only those lines
+//! against the current text, so events can be queued and applied later.
+
+use crate::event::ContentChangeEvent;
+use crate::snapshot::TextSnapshot;
+use crate::types::{Position, TextBufferResult};
+use crate::visual::char_width;
+
+/// A buffer line in the treap
+#[derive(Debug, Clone)]
+struct WrapNode {
+    /// Byte offsets within the line at which continuation rows start
+    breaks: Vec<usize>,
+    /// Whether the line must be wrapped again
+    dirty: bool,
+    priority: u32,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// Number of lines in the subtree
+    lines: usize,
+    /// Number of display rows in the subtree
+    rows: usize,
+    /// Whether any line in the subtree must be wrapped again
+    has_dirty: bool,
+}
+
+/// Mapping between buffer lines and soft-wrapped display rows
+#[derive(Debug, Clone)]
+pub struct WrapMap {
+    /// Arena holding every node; freed slots are reused
+    nodes: Vec<WrapNode>,
+    /// Indices of freed slots in `nodes`
+    free: Vec<usize>,
+    root: Option<usize>,
+    /// Viewport width in cells
+    width: usize,
+    tab_size: usize,
+    /// State of the priority generator
+    seed: u32,
+}
+
+impl WrapMap {
+    /// Wraps every line of the text at the given width
+    pub fn new(
+        text: &impl AsRef<TextSnapshot>,
+        width: usize,
+        tab_size: usize,
+    ) -> TextBufferResult<Self> {
+        let mut map = Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: None,
+            width: width.max(1),
+            tab_size,
+            seed: 0x9e37_79b9,
+        };
+        map.rebuild(text.as_ref())?;
+        Ok(map)
+    }
+
+    /// Returns the viewport width in cells
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the tab size used to expand tabs
+    pub fn tab_size(&self) -> usize {
+        self.tab_size
+    }
+
+    /// Changes the viewport width and tab size, wrapping every line again
+    pub fn set_width(
+        &mut self,
+        text: &impl AsRef<TextSnapshot>,
+        width: usize,
+        tab_size: usize,
+    ) -> TextBufferResult<()> {
+        self.width = width.max(1);
+        self.tab_size = tab_size;
+        self.rebuild(text.as_ref())
+    }
+
+    /// Returns the number of buffer lines
+    pub fn line_count(&self) -> usize {
+        self.lines(self.root)
+    }
+
+    /// Returns the number of display rows
+    pub fn row_count(&self) -> usize {
+        self.rows(self.root)
+    }
+
+    /// Returns the first display row of a buffer line
+    pub fn row_of_line(&self, line: usize) -> Option<usize> {
+        if line >= self.line_count() {
+            return None;
+        }
+
+        let mut node = self.root;
+        let mut line = line;
+        let mut row = 0;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            let left_lines = self.lines(current.left);
+            if line < left_lines {
+                node = current.left;
+            } else if line == left_lines {
+                return Some(row + self.rows(current.left));
+            } else {
+                line -= left_lines + 1;
+                row += self.rows(current.left) + current.breaks.len() + 1;
+                node = current.right;
+            }
+        }
+
+        None
+    }
+
+    /// Returns the buffer line shown on a display row and the index of the
+    /// row among the rows of that line
+    pub fn line_at_row(&self, row: usize) -> Option<(usize, usize)> {
+        self.find_row(row)
+            .map(|(_, line, row_in_line)| (line, row_in_line))
+    }
+
+    /// Returns the buffer position at the start of a display row
+    pub fn position_at_row(
+        &self,
+        text: &impl AsRef<TextSnapshot>,
+        row: usize,
+    ) -> TextBufferResult<Position> {
+        let text = text.as_ref();
+        let (index, line, row_in_line) = self
+            .find_row(row)
+            .ok_or_else(|| format!("Row {row} out of bounds"))?;
+        let line_start = text.line_start_summary(line)?.bytes;
+        let offset = match row_in_line {
+            0 => 0,
+            n => self.nodes[index].breaks[n - 1],
+        };
+        text.offset_to_position(line_start + offset)
+    }
+
+    /// Returns the display row showing a buffer position
+    pub fn row_at_position(
+        &self,
+        text: &impl AsRef<TextSnapshot>,
+        position: Position,
+    ) -> TextBufferResult<usize> {
+        let text = text.as_ref();
+        let offset = text.position_to_offset(position)?;
+        let line_start = text.line_start_summary(position.line)?.bytes;
+        let first_row = self
+            .row_of_line(position.line)
+            .ok_or_else(|| format!("Line {} out of bounds", position.line))?;
+        let index = self
+            .find_line(position.line)
+            .ok_or_else(|| format!("Line {} out of bounds", position.line))?;
+
+        let within = offset - line_start;
+        let breaks = &self.nodes[index].breaks;
+        Ok(first_row + breaks.partition_point(|&start| start <= within))
+    }
+
+    /// Splices the lines touched by a change event and marks them dirty
+    ///
+    /// Events must be applied in the order they were emitted. Dirty lines
+    /// count as a single row until `sync` wraps them again.
+    pub fn apply_event(&mut self, event: &ContentChangeEvent) -> TextBufferResult<()> {
+        for change in &event.changes {
+            let start_line = change.range.start.line;
+            let removed = change.range.end.line - start_line + 1;
+            let inserted = removed
+                .checked_add_signed(change.line_delta)
+                .ok_or_else(|| "Invalid line delta".to_string())?;
+
+            let (before, rest) = self.split(self.root, start_line);
+            let (replaced, after) = self.split(rest, removed);
+            self.release(replaced);
+
+            let mut middle = None;
+            for _ in 0..inserted {
+                let node = self.alloc();
+                middle = self.merge(middle, Some(node));
+            }
+
+            let root = self.merge(before, middle);
+            self.root = self.merge(root, after);
+        }
+        Ok(())
+    }
+
+    /// Wraps the dirty lines again against the current text
+    ///
+    /// `text` must be the buffer after the last applied event.
+    pub fn sync(&mut self, text: &impl AsRef<TextSnapshot>) -> TextBufferResult<()> {
+        let text = text.as_ref();
+        if self.line_count() != text.line_count() {
+            return Err("Line count mismatch".to_string());
+        }
+        self.refresh(text, self.root, 0)
+    }
+
+    /// Returns true if lines are waiting for `sync`
+    pub fn is_dirty(&self) -> bool {
+        self.has_dirty(self.root)
+    }
+
+    /// Replaces the treap with freshly wrapped lines
+    fn rebuild(&mut self, text: &TextSnapshot) -> TextBufferResult<()> {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+
+        for _ in 0..text.line_count() {
+            let node = self.alloc();
+            self.root = self.merge(self.root, Some(node));
+        }
+
+        self.refresh(text, self.root, 0)
+    }
+
+    /// Wraps the dirty lines of a subtree whose first line is `first_line`
+    fn refresh(
+        &mut self,
+        text: &TextSnapshot,
+        root: Option<usize>,
+        first_line: usize,
+    ) -> TextBufferResult<()> {
+        let index = match root {
+            Some(index) if self.nodes[index].has_dirty => index,
+            _ => return Ok(()),
+        };
+
+        let (left, right) = (self.nodes[index].left, self.nodes[index].right);
+        let line = first_line + self.lines(left);
+        self.refresh(text, left, first_line)?;
+        if self.nodes[index].dirty {
+            self.nodes[index].breaks = self.wrap_line(text, line)?;
+            self.nodes[index].dirty = false;
+        }
+        self.refresh(text, right, line + 1)?;
+
+        self.update(index);
+        Ok(())
+    }
+
+    /// Returns the byte offsets within a line at which continuation rows
+    /// start, preferring to break after whitespace
+    fn wrap_line(&self, text: &TextSnapshot, line: usize) -> TextBufferResult<Vec<usize>> {
+        let (start, end) = text.line_bounds(line)?;
+        let mut breaks = Vec::new();
+        let mut column = 0;
+        let mut row_start_column = 0;
+        // Last break opportunity in the current row and its column
+        let mut opportunity: Option<(usize, usize)> = None;
+
+        for (offset, ch) in text.chars_in(start.bytes, end.bytes) {
+            let within = offset - start.bytes;
+            let width = char_width(ch, column, self.tab_size);
+
+            if column + width - row_start_column > self.width && column > row_start_column {
+                let (row_start, row_column) = match opportunity {
+                    Some(opportunity) => opportunity,
+                    None => (within, column),
+                };
+                breaks.push(row_start);
+                row_start_column = row_column;
+                opportunity = None;
+            }
+
+            column += width;
+            if ch.is_whitespace() {
+                opportunity = Some((within + ch.len_utf8(), column));
+            }
+        }
+
+        Ok(breaks)
+    }
+
+    /// Finds the node of a buffer line
+    fn find_line(&self, line: usize) -> Option<usize> {
+        let mut node = self.root;
+        let mut line = line;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            let left_lines = self.lines(current.left);
+            if line < left_lines {
+                node = current.left;
+            } else if line == left_lines {
+                return Some(index);
+            } else {
+                line -= left_lines + 1;
+                node = current.right;
+            }
+        }
+        None
+    }
+
+    /// Finds the node showing a display row, with its line number and the
+    /// index of the row within the line
+    fn find_row(&self, row: usize) -> Option<(usize, usize, usize)> {
+        let mut node = self.root;
+        let mut row = row;
+        let mut line = 0;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            let left_rows = self.rows(current.left);
+            let own_rows = current.breaks.len() + 1;
+            if row < left_rows {
+                node = current.left;
+            } else if row < left_rows + own_rows {
+                return Some((index, line + self.lines(current.left), row - left_rows));
+            } else {
+                row -= left_rows + own_rows;
+                line += self.lines(current.left) + 1;
+                node = current.right;
+            }
+        }
+        None
+    }
+
+    fn lines(&self, root: Option<usize>) -> usize {
+        root.map(|index| self.nodes[index].lines).unwrap_or(0)
+    }
+
+    fn rows(&self, root: Option<usize>) -> usize {
+        root.map(|index| self.nodes[index].rows).unwrap_or(0)
+    }
+
+    fn has_dirty(&self, root: Option<usize>) -> bool {
+        root.map(|index| self.nodes[index].has_dirty)
+            .unwrap_or(false)
+    }
+
+    /// Recomputes the subtree counts of a node from its children
+    fn update(&mut self, index: usize) {
+        let (left, right) = (self.nodes[index].left, self.nodes[index].right);
+        let lines = self.lines(left) + 1 + self.lines(right);
+        let rows = self.rows(left) + self.nodes[index].breaks.len() + 1 + self.rows(right);
+        let has_dirty = self.nodes[index].dirty || self.has_dirty(left) || self.has_dirty(right);
+
+        let node = &mut self.nodes[index];
+        node.lines = lines;
+        node.rows = rows;
+        node.has_dirty = has_dirty;
+    }
+
+    /// Allocates a dirty node for a line
+    fn alloc(&mut self) -> usize {
+        let node = WrapNode {
+            breaks: Vec::new(),
+            dirty: true,
+            priority: self.next_priority(),
+            left: None,
+            right: None,
+            lines: 1,
+            rows: 1,
+            has_dirty: true,
+        };
+
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Returns every node of a subtree to the free list
+    fn release(&mut self, root: Option<usize>) {
+        let mut stack: Vec<usize> = root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            stack.extend(self.nodes[index].left);
+            stack.extend(self.nodes[index].right);
+            self.nodes[index].breaks = Vec::new();
+            self.free.push(index);
+        }
+    }
+
+    /// Xorshift generator for treap priorities
+    fn next_priority(&mut self) -> u32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    /// Splits a treap into its first `count` lines and the rest
+    fn split(&mut self, root: Option<usize>, count: usize) -> (Option<usize>, Option<usize>) {
+        let index = match root {
+            Some(index) => index,
+            None => return (None, None),
+        };
+
+        let left = self.nodes[index].left;
+        let left_lines = self.lines(left);
+        if count <= left_lines {
+            let (rest, middle) = self.split(left, count);
+            self.nodes[index].left = middle;
+            self.update(index);
+            (rest, Some(index))
+        } else {
+            let right = self.nodes[index].right;
+            let (middle, rest) = self.split(right, count - left_lines - 1);
+            self.nodes[index].right = middle;
+            self.update(index);
+            (Some(index), rest)
+        }
+    }
+
+    /// Merges two treaps where every line of `left` precedes those of `right`
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        let (l, r) = match (left, right) {
+            (None, tree) | (tree, None) => return tree,
+            (Some(l), Some(r)) => (l, r),
+        };
+
+        if self.nodes[l].priority > self.nodes[r].priority {
+            let merged = self.merge(self.nodes[l].right, Some(r));
+            self.nodes[l].right = merged;
+            self.update(l);
+            Some(l)
+        } else {
+            let merged = self.merge(Some(l), self.nodes[r].left);
+            self.nodes[r].left = merged;
+            self.update(r);
+            Some(r)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::text_buffer::TextBuffer;
+    use crate::types::Range;
+
+    #[test]
+    fn test_wrap_rows() {
+        let buffer = TextBuffer::from_text("short\nthe quick brown fox\n\nabcdefghij");
+        let map = WrapMap::new(&buffer, 8, 4).unwrap();
+
+        // "the " "quick " "brown " "fox" and "abcdefgh" "ij"
+        assert_eq!(map.line_count(), 4);
+        assert_eq!(map.row_count(), 8);
+        assert_eq!(map.row_of_line(1), Some(1));
+        assert_eq!(map.row_of_line(3), Some(6));
+        assert_eq!(map.line_at_row(3), Some((1, 2)));
+        assert_eq!(map.line_at_row(8), None);
+
+        assert_eq!(
+            map.position_at_row(&buffer, 3).unwrap(),
+            Position::new(1, 10)
+        );
+        assert_eq!(
+            map.position_at_row(&buffer, 7).unwrap(),
+            Position::new(3, 8)
+        );
+        assert_eq!(
+            map.row_at_position(&buffer, Position::new(1, 12)).unwrap(),
+            3
+        );
+        assert_eq!(
+            map.row_at_position(&buffer, Position::new(1, 4)).unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_wrap_updates_incrementally() {
+        let mut buffer = TextBuffer::from_text("aaaa bbbb\ncccc\ndddd eeee");
+        let mut map = WrapMap::new(&buffer, 5, 4).unwrap();
+        assert_eq!(map.row_count(), 5);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        buffer.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+
+        buffer.insert(Position::new(1, 4), " ffff\ngggg").unwrap();
+        buffer
+            .delete(Range::new(Position::new(0, 4), Position::new(0, 9)))
+            .unwrap();
+        for event in events.lock().unwrap().drain(..) {
+            map.apply_event(&event).unwrap();
+        }
+        assert!(map.is_dirty());
+        map.sync(&buffer).unwrap();
+        assert!(!map.is_dirty());
+
+        let rebuilt = WrapMap::new(&buffer, 5, 4).unwrap();
+        assert_eq!(map.line_count(), buffer.line_count());
+        assert_eq!(map.row_count(), rebuilt.row_count());
+        for row in 0..map.row_count() {
+            assert_eq!(
+                map.position_at_row(&buffer, row).unwrap(),
+                rebuilt.position_at_row(&buffer, row).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrap_applies_queued_events() {
+        let mut buffer = TextBuffer::from_text(
+            "aaaa bbbb
+cccc dddd eeee",
+        );
+        let mut map = WrapMap::new(&buffer, 5, 4).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        buffer.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+
+        // The second edit removes the lines the first one added
+        buffer.insert(Position::new(0, 0), "1\n2\n3\n4\n").unwrap();
+        buffer
+            .delete(Range::new(Position::new(0, 0), Position::new(4, 0)))
+            .unwrap();
+        for event in events.lock().unwrap().drain(..) {
+            map.apply_event(&event).unwrap();
+        }
+        map.sync(&buffer).unwrap();
+
+        let rebuilt = WrapMap::new(&buffer, 5, 4).unwrap();
+        assert_eq!(map.line_count(), 2);
+        assert_eq!(map.row_count(), rebuilt.row_count());
+        assert_eq!(map.row_of_line(1), rebuilt.row_of_line(1));
+    }
+}